@@ -2,14 +2,13 @@ use std::{
     collections::HashSet,
     str::FromStr,
     sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
     },
     thread::{Builder, JoinHandle},
     time::{Duration, Instant, SystemTime},
 };
 
-use cached::{Cached, TimedCache};
 use dashmap::DashMap;
 use jito_core::ofac::is_tx_ofac_related;
 use jito_protos::{
@@ -24,23 +23,25 @@ use jito_protos::{
         PacketBatchUpdate, ProgramsOfInterestRequest, ProgramsOfInterestUpdate,
     },
     convert::packet_to_proto_packet,
-    packet::PacketBatch as ProtoPacketBatch,
-    shared::{Header, Heartbeat},
+    packet::Packet as ProtoPacket,
+    shared::Heartbeat,
 };
 use log::{error, *};
-use prost_types::Timestamp;
 use solana_core::banking_trace::BankingPacketBatch;
 use solana_metrics::{datapoint_error, datapoint_info};
+use solana_perf::packet::Packet;
 use solana_sdk::{
     address_lookup_table::AddressLookupTableAccount, pubkey::Pubkey, signature::Signer,
     signer::keypair::Keypair, transaction::VersionedTransaction,
 };
 use thiserror::Error;
 use tokio::{
-    runtime::Runtime,
     select,
-    sync::mpsc::{channel, Receiver, Sender},
-    time::{interval, sleep},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Mutex as AsyncMutex,
+    },
+    time::{interval, sleep, sleep_until, timeout},
 };
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::{
@@ -50,11 +51,214 @@ use tonic::{
     Response, Status, Streaming,
 };
 
-use crate::block_engine_stats::BlockEngineStats;
+use crate::{
+    block_engine_selector::{BlockEngineSelector, BlockEngineSelectorConfig},
+    block_engine_stats::BlockEngineStats,
+    interest_cache::{InterestCache, InterestKind},
+    packet_coalescer::{PacketCoalescer, PacketCoalescerConfig},
+    replay_buffer::ReplayBuffer,
+    static_interest::{StaticInterest, StaticInterestHandle},
+};
 
+/// Name of the block engine handler's runtime threads. Panics on these threads are recoverable
+/// since the handler restarts its connection task.
+pub const BLOCK_ENGINE_RUNTIME_THREAD_NAME: &str = "block_engine_relayer_rt";
+
+#[derive(Clone)]
 pub struct BlockEngineConfig {
     pub block_engine_url: String,
     pub auth_service_url: String,
+    /// Forward every verified transaction that passes OFAC and the operator's deny list instead
+    /// of only the ones touching accounts or programs of interest
+    pub forward_all: bool,
+}
+
+/// How packets are spread across several block engines
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockEnginePolicy {
+    /// Every connected block engine gets every packet
+    FanOut,
+    /// Only the first connected block engine, in configuration order, gets packets
+    Failover,
+    /// Only the block engine with the lowest probed connect and auth round-trip time is connected
+    Fastest,
+}
+
+impl FromStr for BlockEnginePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fan-out" => Ok(BlockEnginePolicy::FanOut),
+            "failover" => Ok(BlockEnginePolicy::Failover),
+            "fastest" => Ok(BlockEnginePolicy::Fastest),
+            _ => Err(format!(
+                "invalid block engine policy {s}, expected one of: fan-out, failover, fastest"
+            )),
+        }
+    }
+}
+
+/// Connection state of one block engine, shared with the status endpoint
+pub struct BlockEngineState {
+    pub block_engine_url: String,
+    pub auth_service_url: String,
+    pub forward_all: bool,
+    is_connected: AtomicBool,
+    is_failed: AtomicBool,
+    /// Whether the block engine is sent packets under the block engine policy
+    is_active: AtomicBool,
+    /// Whether the relayer should stay connected, always set unless the policy is fastest
+    is_selected: AtomicBool,
+    /// Round-trip time of the latest successful probe, 0 if never probed or the probe failed
+    probe_rtt_us: AtomicU64,
+    /// Cleared on every reconnect
+    interest_cache: RwLock<InterestCache>,
+    /// Packets dispatched while disconnected, forwarded after reconnecting
+    replay_buffer: Mutex<ReplayBuffer>,
+}
+
+impl BlockEngineState {
+    fn new(
+        config: &BlockEngineConfig,
+        is_selected: bool,
+        aoi_cache_ttl: Duration,
+        replay_buffer_max_bytes: usize,
+    ) -> Self {
+        BlockEngineState {
+            block_engine_url: config.block_engine_url.clone(),
+            auth_service_url: config.auth_service_url.clone(),
+            forward_all: config.forward_all,
+            is_connected: AtomicBool::new(false),
+            is_failed: AtomicBool::new(false),
+            is_active: AtomicBool::new(false),
+            is_selected: AtomicBool::new(is_selected),
+            probe_rtt_us: AtomicU64::new(0),
+            interest_cache: RwLock::new(InterestCache::new(aoi_cache_ttl)),
+            replay_buffer: Mutex::new(ReplayBuffer::new(replay_buffer_max_bytes)),
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.is_connected.load(Ordering::Relaxed)
+    }
+
+    /// The connection task panicked and is being restarted
+    pub fn is_failed(&self) -> bool {
+        self.is_failed.load(Ordering::Relaxed)
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.is_active.load(Ordering::Relaxed)
+    }
+
+    pub fn is_selected(&self) -> bool {
+        self.is_selected.load(Ordering::Relaxed)
+    }
+
+    pub fn probe_rtt(&self) -> Option<Duration> {
+        match self.probe_rtt_us.load(Ordering::Relaxed) {
+            0 => None,
+            rtt_us => Some(Duration::from_micros(rtt_us)),
+        }
+    }
+
+    /// Accounts and programs of interest of the current or last connection
+    pub fn interest_cache(&self) -> &RwLock<InterestCache> {
+        &self.interest_cache
+    }
+}
+
+/// An account or program of interest a transaction touches
+#[derive(Clone, Debug)]
+pub struct InterestMatch {
+    pub pubkey: Pubkey,
+    pub kind: InterestKind,
+    /// Lookup table the pubkey was loaded from, None for static account keys
+    pub lookup_table: Option<Pubkey>,
+    /// Only in the operator's static interest, not sent by the block engine
+    pub is_static: bool,
+}
+
+/// Why a transaction would or wouldn't be forwarded to a block engine
+#[derive(Clone, Debug)]
+pub struct TransactionExplanation {
+    pub block_engine_url: String,
+    pub would_forward: bool,
+    pub is_ofac_related: bool,
+    /// The block engine doesn't filter on accounts and programs of interest
+    pub is_forward_all: bool,
+    /// Account keys on the operator's deny list
+    pub denied: Vec<Pubkey>,
+    pub matches: Vec<InterestMatch>,
+    /// Lookup tables missing from the relayer's cache, their accounts can't be matched
+    pub missing_lookup_tables: Vec<Pubkey>,
+}
+
+impl TransactionExplanation {
+    pub fn reason(&self) -> &'static str {
+        if self.is_ofac_related {
+            "transaction references an OFAC address"
+        } else if !self.denied.is_empty() {
+            "transaction references an account or program denied by the operator"
+        } else if self.is_forward_all {
+            "block engine is sent all transactions"
+        } else if self.matches.is_empty() {
+            "transaction doesn't touch any account or program of interest"
+        } else {
+            "transaction touches an account or program of interest"
+        }
+    }
+}
+
+/// Read access to the block engine connections for the web server
+#[derive(Clone)]
+pub struct BlockEngineHandle {
+    block_engine_states: Vec<Arc<BlockEngineState>>,
+    address_lookup_table_cache: Arc<DashMap<Pubkey, AddressLookupTableAccount>>,
+    ofac_addresses: Arc<HashSet<Pubkey>>,
+    static_interest: StaticInterestHandle,
+}
+
+impl BlockEngineHandle {
+    pub fn block_engine_states(&self) -> &[Arc<BlockEngineState>] {
+        &self.block_engine_states
+    }
+
+    /// Explains, for every block engine, whether the transaction would be forwarded to it
+    pub fn explain_transaction(&self, tx: &VersionedTransaction) -> Vec<TransactionExplanation> {
+        let is_ofac_related = !self.ofac_addresses.is_empty()
+            && is_tx_ofac_related(tx, &self.ofac_addresses, &self.address_lookup_table_cache);
+        let static_interest = self.static_interest.read();
+        let denied: Vec<Pubkey> = loaded_account_keys(tx, &self.address_lookup_table_cache)
+            .into_iter()
+            .filter(|pubkey| static_interest.denies(pubkey))
+            .collect();
+        self.block_engine_states
+            .iter()
+            .map(|state| {
+                let (matches, missing_lookup_tables) = interest_matches(
+                    tx,
+                    &Interest {
+                        interest_cache: &state.interest_cache.read().unwrap(),
+                        static_interest: &static_interest,
+                    },
+                    &self.address_lookup_table_cache,
+                );
+                TransactionExplanation {
+                    block_engine_url: state.block_engine_url.clone(),
+                    would_forward: !is_ofac_related
+                        && denied.is_empty()
+                        && (state.forward_all || !matches.is_empty()),
+                    is_ofac_related,
+                    is_forward_all: state.forward_all,
+                    denied: denied.clone(),
+                    matches,
+                    missing_lookup_tables,
+                }
+            })
+            .collect()
+    }
 }
 
 #[derive(Clone)]
@@ -80,12 +284,37 @@ impl Interceptor for AuthInterceptor {
     }
 }
 
+#[derive(Clone)]
 pub struct BlockEnginePackets {
     pub banking_packet_batch: BankingPacketBatch,
     pub stamp: SystemTime,
     pub expiration: u32,
 }
 
+impl BlockEnginePackets {
+    pub fn num_unfiltered_packets(&self) -> u64 {
+        self.unfiltered_packets().count() as u64
+    }
+
+    pub fn num_unfiltered_bytes(&self) -> usize {
+        self.unfiltered_packets()
+            .map(|packet| packet.meta().size)
+            .sum()
+    }
+
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        self.stamp + Duration::from_millis(self.expiration as u64) <= now
+    }
+
+    fn unfiltered_packets(&self) -> impl Iterator<Item = &Packet> {
+        self.banking_packet_batch
+            .0
+            .iter()
+            .flat_map(|batch| batch.iter())
+            .filter(|packet| !packet.meta().discard())
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum BlockEngineError {
     #[error("auth service failed: {0}")]
@@ -97,55 +326,121 @@ pub enum BlockEngineError {
 
 pub type BlockEngineResult<T> = Result<T, BlockEngineError>;
 
-/// Attempts to maintain a connection to a Block Engine and forward packets to it
+/// Attempts to maintain a connection to each Block Engine and forward packets to them
 pub struct BlockEngineRelayerHandler {
     block_engine_forwarder: Option<JoinHandle<()>>,
+    handle: BlockEngineHandle,
 }
 
 impl BlockEngineRelayerHandler {
     const BLOCK_ENGINE_PACKET_QUEUE_CAPACITY: usize = 1_000;
 
+    /// Batches queued for each block engine by the dispatcher before it drops new ones
+    const PER_BLOCK_ENGINE_DISPATCH_QUEUE_CAPACITY: usize = 1_000;
+
+    /// Wait before reconnecting after a failed connection
+    const RECONNECT_BACKOFF: Duration = Duration::from_secs(2);
+
+    /// Packets from block_engine_receiver are dispatched to the block engines per
+    /// block_engine_policy. is_connected_to_block_engine is set while any block engine is
+    /// connected and is_block_engine_failed while all of them are failed. selector_config is only
+    /// used by the fastest policy. When replay_buffer_max_bytes is non-zero, up to that many bytes
+    /// of the newest packets are buffered for disconnected block engines and the unexpired ones are
+    /// forwarded after reconnecting. Filtered packets are merged into fewer messages per
+    /// coalescer_config.
     #[allow(clippy::too_many_arguments)]
     pub fn new(
-        block_engine_config: Option<BlockEngineConfig>,
-        mut block_engine_receiver: Receiver<BlockEnginePackets>,
+        block_engine_configs: Vec<BlockEngineConfig>,
+        block_engine_policy: BlockEnginePolicy,
+        selector_config: BlockEngineSelectorConfig,
+        block_engine_receiver: Receiver<BlockEnginePackets>,
         keypair: Arc<Keypair>,
         exit: Arc<AtomicBool>,
         aoi_cache_ttl_s: u64,
+        replay_buffer_max_bytes: usize,
+        coalescer_config: PacketCoalescerConfig,
         address_lookup_table_cache: Arc<DashMap<Pubkey, AddressLookupTableAccount>>,
         is_connected_to_block_engine: &Arc<AtomicBool>,
+        is_block_engine_failed: &Arc<AtomicBool>,
         ofac_addresses: HashSet<Pubkey>,
+        static_interest: StaticInterestHandle,
     ) -> BlockEngineRelayerHandler {
         let is_connected_to_block_engine = is_connected_to_block_engine.clone();
-        let block_engine_forwarder = block_engine_config.map(|config| {
+        let is_block_engine_failed = is_block_engine_failed.clone();
+        let ofac_addresses = Arc::new(ofac_addresses);
+        let block_engine_states: Vec<Arc<BlockEngineState>> = block_engine_configs
+            .iter()
+            .map(|config| {
+                Arc::new(BlockEngineState::new(
+                    config,
+                    block_engine_policy != BlockEnginePolicy::Fastest,
+                    Duration::from_secs(aoi_cache_ttl_s),
+                    replay_buffer_max_bytes,
+                ))
+            })
+            .collect();
+        let handle = BlockEngineHandle {
+            block_engine_states: block_engine_states.clone(),
+            address_lookup_table_cache: address_lookup_table_cache.clone(),
+            ofac_addresses: ofac_addresses.clone(),
+            static_interest: static_interest.clone(),
+        };
+        let block_engine_forwarder = (!block_engine_configs.is_empty()).then(|| {
             Builder::new()
                 .name("block_engine_relayer_handler_thread".into())
                 .spawn(move || {
-                    let rt = Runtime::new().unwrap();
+                    let rt = tokio::runtime::Builder::new_multi_thread()
+                        .thread_name(BLOCK_ENGINE_RUNTIME_THREAD_NAME)
+                        .enable_all()
+                        .build()
+                        .unwrap();
                     rt.block_on(async move {
-                        while !exit.load(Ordering::Relaxed) {
-                            let result = Self::auth_and_connect(
-                                &config.block_engine_url,
-                                &config.auth_service_url,
-                                &mut block_engine_receiver,
-                                &keypair,
-                                &exit,
-                                aoi_cache_ttl_s,
-                                &address_lookup_table_cache,
-                                &is_connected_to_block_engine,
-                                &ofac_addresses,
-                            )
-                            .await;
-                            is_connected_to_block_engine.store(false, Ordering::Relaxed);
-
-                            if let Err(e) = result {
-                                error!("error authenticating and connecting: {:?}", e);
-                                datapoint_error!("block_engine_relayer-error",
-                                    "block_engine_url" => &config.block_engine_url,
-                                    "auth_service_url" => &config.auth_service_url,
-                                    ("error", e.to_string(), String)
-                                );
-                                sleep(Duration::from_secs(2)).await;
+                        let mut tasks = Vec::with_capacity(block_engine_configs.len() + 2);
+                        let mut block_engine_senders =
+                            Vec::with_capacity(block_engine_configs.len());
+                        if block_engine_policy == BlockEnginePolicy::Fastest {
+                            tasks.push(tokio::spawn(Self::run_selector(
+                                block_engine_configs.clone(),
+                                block_engine_states.clone(),
+                                keypair.clone(),
+                                selector_config,
+                                exit.clone(),
+                            )));
+                        }
+                        for (config, state) in block_engine_configs
+                            .into_iter()
+                            .zip(block_engine_states.iter())
+                        {
+                            let (sender, receiver) =
+                                channel(Self::PER_BLOCK_ENGINE_DISPATCH_QUEUE_CAPACITY);
+                            block_engine_senders.push(sender);
+                            tasks.push(tokio::spawn(Self::run_block_engine(
+                                config,
+                                state.clone(),
+                                // shared so a restarted connection task picks up where the failed
+                                // one left off
+                                Arc::new(AsyncMutex::new(receiver)),
+                                keypair.clone(),
+                                exit.clone(),
+                                coalescer_config,
+                                address_lookup_table_cache.clone(),
+                                ofac_addresses.clone(),
+                                static_interest.clone(),
+                            )));
+                        }
+                        tasks.push(tokio::spawn(Self::run_dispatcher(
+                            block_engine_receiver,
+                            block_engine_senders,
+                            block_engine_states,
+                            block_engine_policy,
+                            replay_buffer_max_bytes,
+                            is_connected_to_block_engine,
+                            is_block_engine_failed,
+                            exit,
+                        )));
+                        for task in tasks {
+                            if let Err(e) = task.await {
+                                error!("block engine task failed: {e:?}");
                             }
                         }
                     });
@@ -154,6 +449,328 @@ impl BlockEngineRelayerHandler {
         });
         BlockEngineRelayerHandler {
             block_engine_forwarder,
+            handle,
+        }
+    }
+
+    pub fn handle(&self) -> BlockEngineHandle {
+        self.handle.clone()
+    }
+
+    /// Keeps a connection task running for one block engine, restarting it if it panics
+    #[allow(clippy::too_many_arguments)]
+    async fn run_block_engine(
+        config: BlockEngineConfig,
+        state: Arc<BlockEngineState>,
+        block_engine_receiver: Arc<AsyncMutex<Receiver<BlockEnginePackets>>>,
+        keypair: Arc<Keypair>,
+        exit: Arc<AtomicBool>,
+        coalescer_config: PacketCoalescerConfig,
+        address_lookup_table_cache: Arc<DashMap<Pubkey, AddressLookupTableAccount>>,
+        ofac_addresses: Arc<HashSet<Pubkey>>,
+        static_interest: StaticInterestHandle,
+    ) {
+        while !exit.load(Ordering::Relaxed) {
+            let connection_task = tokio::spawn(Self::run_connection_loop(
+                config.clone(),
+                block_engine_receiver.clone(),
+                keypair.clone(),
+                exit.clone(),
+                coalescer_config,
+                address_lookup_table_cache.clone(),
+                state.clone(),
+                ofac_addresses.clone(),
+                static_interest.clone(),
+            ));
+
+            if let Err(e) = connection_task.await {
+                state.is_connected.store(false, Ordering::Relaxed);
+                state.is_failed.store(true, Ordering::Relaxed);
+
+                error!("block engine connection task failed, restarting: {e:?}");
+                datapoint_error!("block_engine_relayer-task_failed",
+                    "block_engine_url" => &config.block_engine_url,
+                    "auth_service_url" => &config.auth_service_url,
+                    ("error", e.to_string(), String)
+                );
+                sleep(Duration::from_secs(2)).await;
+                state.is_failed.store(false, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Marks the block engines that get packets under the policy
+    fn update_active_block_engines(
+        block_engine_states: &[Arc<BlockEngineState>],
+        block_engine_policy: BlockEnginePolicy,
+    ) {
+        let mut has_active = false;
+        for state in block_engine_states {
+            let is_active = state.is_connected()
+                && state.is_selected()
+                && (block_engine_policy != BlockEnginePolicy::Failover || !has_active);
+            state.is_active.store(is_active, Ordering::Relaxed);
+            has_active |= is_active;
+        }
+    }
+
+    /// Hands packets to the queue of each active block engine, and to the replay buffer of
+    /// disconnected ones that are buffering packets for replay
+    #[allow(clippy::too_many_arguments)]
+    async fn run_dispatcher(
+        mut block_engine_receiver: Receiver<BlockEnginePackets>,
+        block_engine_senders: Vec<Sender<BlockEnginePackets>>,
+        block_engine_states: Vec<Arc<BlockEngineState>>,
+        block_engine_policy: BlockEnginePolicy,
+        replay_buffer_max_bytes: usize,
+        is_connected_to_block_engine: Arc<AtomicBool>,
+        is_block_engine_failed: Arc<AtomicBool>,
+        exit: Arc<AtomicBool>,
+    ) {
+        // (num_batches_sent, num_batches_dropped) per block engine
+        let mut dispatch_stats = vec![(0u64, 0u64); block_engine_senders.len()];
+        let mut num_batches_unrouted = 0u64;
+        let mut state_interval = interval(Duration::from_millis(100));
+        let mut metrics_interval = interval(Duration::from_secs(1));
+
+        while !exit.load(Ordering::Relaxed) {
+            select! {
+                maybe_packets = block_engine_receiver.recv() => {
+                    let Some(packets) = maybe_packets else {
+                        break;
+                    };
+                    Self::update_active_block_engines(&block_engine_states, block_engine_policy);
+                    let has_active = block_engine_states.iter().any(|state| state.is_active());
+
+                    let now = SystemTime::now();
+                    let mut is_routed = false;
+                    for (i, ((sender, state), (num_sent, num_dropped))) in block_engine_senders
+                        .iter()
+                        .zip(&block_engine_states)
+                        .zip(dispatch_stats.iter_mut())
+                        .enumerate()
+                    {
+                        if state.is_active() {
+                            is_routed = true;
+                            match sender.try_send(packets.clone()) {
+                                Ok(_) => *num_sent += 1,
+                                Err(_) => *num_dropped += 1,
+                            }
+                            continue;
+                        }
+                        // buffer packets for a disconnected block engine to replay once it
+                        // reconnects. under failover only the primary buffers, and only while
+                        // nothing else is connected.
+                        let is_buffering = replay_buffer_max_bytes > 0
+                            && state.is_selected()
+                            && !state.is_connected()
+                            && (block_engine_policy != BlockEnginePolicy::Failover
+                                || (i == 0 && !has_active));
+                        if is_buffering {
+                            state.replay_buffer.lock().unwrap().push(packets.clone(), now);
+                        }
+                    }
+                    if !is_routed {
+                        num_batches_unrouted += 1;
+                    }
+                }
+                _ = state_interval.tick() => {
+                    Self::update_active_block_engines(&block_engine_states, block_engine_policy);
+                    is_connected_to_block_engine.store(
+                        block_engine_states.iter().any(|state| state.is_connected()),
+                        Ordering::Relaxed,
+                    );
+                    is_block_engine_failed.store(
+                        block_engine_states.iter().all(|state| state.is_failed()),
+                        Ordering::Relaxed,
+                    );
+                }
+                _ = metrics_interval.tick() => {
+                    for (state, (num_sent, num_dropped)) in block_engine_states.iter().zip(dispatch_stats.iter_mut()) {
+                        datapoint_info!("block_engine_relayer-dispatch_stats",
+                            "block_engine_url" => &state.block_engine_url,
+                            ("num_batches_sent", *num_sent, i64),
+                            ("num_batches_dropped", *num_dropped, i64),
+                            ("is_connected", state.is_connected(), bool),
+                            ("is_active", state.is_active(), bool),
+                        );
+                        *num_sent = 0;
+                        *num_dropped = 0;
+                    }
+                    datapoint_info!("block_engine_relayer-dispatch_unrouted",
+                        ("num_batches_unrouted", num_batches_unrouted, i64),
+                    );
+                    num_batches_unrouted = 0;
+                }
+            }
+        }
+    }
+
+    /// Probes every block engine on an interval and moves the selection to the fastest healthy one
+    async fn run_selector(
+        block_engine_configs: Vec<BlockEngineConfig>,
+        block_engine_states: Vec<Arc<BlockEngineState>>,
+        keypair: Arc<Keypair>,
+        selector_config: BlockEngineSelectorConfig,
+        exit: Arc<AtomicBool>,
+    ) {
+        let mut selector = BlockEngineSelector::new(selector_config);
+        let mut probe_interval = interval(selector_config.probe_interval);
+
+        loop {
+            probe_interval.tick().await;
+            if exit.load(Ordering::Relaxed) {
+                break;
+            }
+
+            let probes: Vec<_> = block_engine_configs
+                .iter()
+                .map(|config| {
+                    tokio::spawn(timeout(
+                        selector_config.probe_timeout,
+                        Self::probe(config.clone(), keypair.clone()),
+                    ))
+                })
+                .collect();
+            let mut rtts = Vec::with_capacity(probes.len());
+            for (probe, state) in probes.into_iter().zip(&block_engine_states) {
+                let rtt = match probe.await {
+                    Ok(Ok(Ok(rtt))) => Some(rtt),
+                    Ok(Ok(Err(e))) => {
+                        warn!(
+                            "error probing block engine {}: {e:?}",
+                            state.block_engine_url
+                        );
+                        None
+                    }
+                    Ok(Err(_)) => {
+                        warn!("probe of block engine {} timed out", state.block_engine_url);
+                        None
+                    }
+                    Err(e) => {
+                        error!("block engine probe task failed: {e:?}");
+                        None
+                    }
+                };
+                let rtt_us = rtt.map_or(0, |rtt| (rtt.as_micros() as u64).max(1));
+                state.probe_rtt_us.store(rtt_us, Ordering::Relaxed);
+                datapoint_info!("block_engine_relayer-probe",
+                    "block_engine_url" => &state.block_engine_url,
+                    ("probe_ok", rtt.is_some(), bool),
+                    ("rtt_us", rtt_us, i64),
+                );
+                rtts.push(rtt);
+            }
+
+            if let Some(switch) = selector.update(&rtts, Instant::now()) {
+                let from_url = switch
+                    .from
+                    .map_or("none", |i| block_engine_states[i].block_engine_url.as_str());
+                let to_url = block_engine_states[switch.to].block_engine_url.as_str();
+                info!(
+                    "switching block engine from {from_url} to {to_url}, reason: {}",
+                    switch.reason
+                );
+                let rtt_us = |i: usize| rtts[i].map_or(0, |rtt| rtt.as_micros() as u64);
+                datapoint_info!("block_engine_relayer-switch",
+                    "from_block_engine_url" => from_url,
+                    "to_block_engine_url" => to_url,
+                    "reason" => switch.reason.to_string(),
+                    ("from_rtt_us", switch.from.map_or(0, rtt_us), i64),
+                    ("to_rtt_us", rtt_us(switch.to), i64),
+                );
+                for (i, state) in block_engine_states.iter().enumerate() {
+                    state.is_selected.store(i == switch.to, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+
+    /// Measures how long it takes to connect and authenticate with the auth service and connect
+    /// to the block engine
+    async fn probe(
+        config: BlockEngineConfig,
+        keypair: Arc<Keypair>,
+    ) -> BlockEngineResult<Duration> {
+        let start = Instant::now();
+
+        let mut auth_endpoint =
+            Endpoint::from_str(&config.auth_service_url).expect("valid auth url");
+        if config.auth_service_url.contains("https") {
+            auth_endpoint = auth_endpoint
+                .tls_config(tonic::transport::ClientTlsConfig::new())
+                .expect("invalid tls config");
+        }
+        let channel = auth_endpoint
+            .connect()
+            .await
+            .map_err(|e| BlockEngineError::AuthServiceFailure(e.to_string()))?;
+        Self::auth(&mut AuthServiceClient::new(channel), &keypair).await?;
+
+        let mut block_engine_endpoint =
+            Endpoint::from_str(&config.block_engine_url).expect("valid block engine url");
+        if config.block_engine_url.contains("https") {
+            block_engine_endpoint = block_engine_endpoint
+                .tls_config(tonic::transport::ClientTlsConfig::new())
+                .expect("invalid tls config");
+        }
+        block_engine_endpoint
+            .connect()
+            .await
+            .map_err(|e| BlockEngineError::BlockEngineFailure(e.to_string()))?;
+
+        Ok(start.elapsed())
+    }
+
+    /// Keeps the relayer authenticated and connected to the block engine until exit
+    #[allow(clippy::too_many_arguments)]
+    async fn run_connection_loop(
+        config: BlockEngineConfig,
+        block_engine_receiver: Arc<AsyncMutex<Receiver<BlockEnginePackets>>>,
+        keypair: Arc<Keypair>,
+        exit: Arc<AtomicBool>,
+        coalescer_config: PacketCoalescerConfig,
+        address_lookup_table_cache: Arc<DashMap<Pubkey, AddressLookupTableAccount>>,
+        state: Arc<BlockEngineState>,
+        ofac_addresses: Arc<HashSet<Pubkey>>,
+        static_interest: StaticInterestHandle,
+    ) {
+        let mut block_engine_receiver = block_engine_receiver.lock().await;
+        while !exit.load(Ordering::Relaxed) {
+            if !state.is_selected() {
+                sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+            let connect_start = Instant::now();
+            let result = Self::auth_and_connect(
+                &config.block_engine_url,
+                &config.auth_service_url,
+                &mut block_engine_receiver,
+                &keypair,
+                &exit,
+                coalescer_config,
+                &address_lookup_table_cache,
+                &state,
+                &ofac_addresses,
+                &static_interest,
+            )
+            .await;
+            let was_connected = state.is_connected.swap(false, Ordering::Relaxed);
+
+            if let Err(e) = result {
+                error!("error authenticating and connecting: {:?}", e);
+                datapoint_error!("block_engine_relayer-error",
+                    "block_engine_url" => &config.block_engine_url,
+                    "auth_service_url" => &config.auth_service_url,
+                    ("error", e.to_string(), String)
+                );
+                // a dropped stream is reconnected right away so packets buffered for replay
+                // haven't expired by the time it's back. failed connects and streams that drop
+                // right after connecting back off.
+                if !was_connected || connect_start.elapsed() < Self::RECONNECT_BACKOFF {
+                    sleep(Self::RECONNECT_BACKOFF).await;
+                }
+            }
         }
     }
 
@@ -223,10 +840,11 @@ impl BlockEngineRelayerHandler {
         block_engine_receiver: &mut Receiver<BlockEnginePackets>,
         keypair: &Arc<Keypair>,
         exit: &Arc<AtomicBool>,
-        aoi_cache_ttl_s: u64,
+        coalescer_config: PacketCoalescerConfig,
         address_lookup_table_cache: &Arc<DashMap<Pubkey, AddressLookupTableAccount>>,
-        is_connected_to_block_engine: &Arc<AtomicBool>,
+        state: &BlockEngineState,
         ofac_addresses: &HashSet<Pubkey>,
+        static_interest: &StaticInterestHandle,
     ) -> BlockEngineResult<()> {
         let mut auth_endpoint = Endpoint::from_str(auth_service_url).expect("valid auth url");
         if auth_service_url.contains("https") {
@@ -288,10 +906,11 @@ impl BlockEngineRelayerHandler {
             &mut refresh_token,
             shared_access_token,
             exit,
-            aoi_cache_ttl_s,
+            coalescer_config,
             address_lookup_table_cache,
-            is_connected_to_block_engine,
+            state,
             ofac_addresses,
+            static_interest,
         )
         .await
     }
@@ -310,19 +929,26 @@ impl BlockEngineRelayerHandler {
         refresh_token: &mut Token,
         shared_access_token: Arc<Mutex<Token>>,
         exit: &Arc<AtomicBool>,
-        aoi_cache_ttl_s: u64,
+        coalescer_config: PacketCoalescerConfig,
         address_lookup_table_cache: &Arc<DashMap<Pubkey, AddressLookupTableAccount>>,
-        is_connected_to_block_engine: &Arc<AtomicBool>,
+        state: &BlockEngineState,
         ofac_addresses: &HashSet<Pubkey>,
+        static_interest: &StaticInterestHandle,
     ) -> BlockEngineResult<()> {
-        let subscribe_aoi_stream = client
-            .subscribe_accounts_of_interest(AccountsOfInterestRequest {})
-            .await
-            .map_err(|e| BlockEngineError::BlockEngineFailure(e.to_string()))?;
-        let subscribe_poi_stream = client
-            .subscribe_programs_of_interest(ProgramsOfInterestRequest {})
-            .await
-            .map_err(|e| BlockEngineError::BlockEngineFailure(e.to_string()))?;
+        // forward-all block engines aren't filtered, so their interest isn't needed
+        let (subscribe_aoi_stream, subscribe_poi_stream) = if state.forward_all {
+            (None, None)
+        } else {
+            let subscribe_aoi_stream = client
+                .subscribe_accounts_of_interest(AccountsOfInterestRequest {})
+                .await
+                .map_err(|e| BlockEngineError::BlockEngineFailure(e.to_string()))?;
+            let subscribe_poi_stream = client
+                .subscribe_programs_of_interest(ProgramsOfInterestRequest {})
+                .await
+                .map_err(|e| BlockEngineError::BlockEngineFailure(e.to_string()))?;
+            (Some(subscribe_aoi_stream), Some(subscribe_poi_stream))
+        };
 
         // sender tracked as block_engine_relayer-loop_stats.block_engine_packet_sender_len
         let (block_engine_packet_sender, block_engine_packet_receiver) =
@@ -342,52 +968,57 @@ impl BlockEngineRelayerHandler {
             refresh_token,
             shared_access_token,
             exit,
-            aoi_cache_ttl_s,
+            coalescer_config,
             address_lookup_table_cache,
-            is_connected_to_block_engine,
+            state,
             ofac_addresses,
+            static_interest,
         )
         .await
     }
 
+    /// Streams packets to the block engine until exit or until it is no longer selected
     #[allow(clippy::too_many_arguments)]
     async fn handle_packet_stream(
         block_engine_packet_sender: Sender<PacketBatchUpdate>,
         block_engine_receiver: &mut Receiver<BlockEnginePackets>,
-        subscribe_aoi_stream: Response<Streaming<AccountsOfInterestUpdate>>,
-        subscribe_poi_stream: Response<Streaming<ProgramsOfInterestUpdate>>,
+        subscribe_aoi_stream: Option<Response<Streaming<AccountsOfInterestUpdate>>>,
+        subscribe_poi_stream: Option<Response<Streaming<ProgramsOfInterestUpdate>>>,
         mut auth_client: AuthServiceClient<Channel>,
         keypair: &Arc<Keypair>,
         refresh_token: &mut Token,
         shared_access_token: Arc<Mutex<Token>>,
         exit: &Arc<AtomicBool>,
-        aoi_cache_ttl_s: u64,
+        coalescer_config: PacketCoalescerConfig,
         address_lookup_table_cache: &Arc<DashMap<Pubkey, AddressLookupTableAccount>>,
-        is_connected_to_block_engine: &Arc<AtomicBool>,
+        state: &BlockEngineState,
         ofac_addresses: &HashSet<Pubkey>,
+        static_interest: &StaticInterestHandle,
     ) -> BlockEngineResult<()> {
-        let mut aoi_stream = subscribe_aoi_stream.into_inner();
-        let mut poi_stream = subscribe_poi_stream.into_inner();
+        let mut aoi_stream = subscribe_aoi_stream.map(Response::into_inner);
+        let mut poi_stream = subscribe_poi_stream.map(Response::into_inner);
 
-        // drain old buffered packets before streaming packets to the block engine
-        while block_engine_receiver.try_recv().is_ok() {}
-
-        is_connected_to_block_engine.store(true, Ordering::Relaxed);
+        let mut block_engine_stats = BlockEngineStats::default();
+        let mut packet_coalescer = PacketCoalescer::new(coalescer_config);
 
-        let mut accounts_of_interest: TimedCache<Pubkey, u8> =
-            TimedCache::with_lifespan_and_capacity(aoi_cache_ttl_s, 1_000_000);
+        state.is_connected.store(true, Ordering::Relaxed);
 
-        let mut programs_of_interest: TimedCache<Pubkey, u8> =
-            TimedCache::with_lifespan_and_capacity(aoi_cache_ttl_s, 1_000_000);
+        state.interest_cache.write().unwrap().clear();
 
-        let mut block_engine_stats = BlockEngineStats::default();
+        // packets buffered while disconnected, replayed once the first accounts and programs of
+        // interest arrive so they're filtered against populated caches, or right away when not
+        // subscribed to them. taken once connected so the dispatcher doesn't buffer any more.
+        let mut replay_batches =
+            Self::take_replay_batches(block_engine_receiver, state, &mut block_engine_stats);
+        let mut has_aoi = aoi_stream.is_none();
+        let mut has_poi = poi_stream.is_none();
 
         let mut heartbeat_interval = interval(Duration::from_millis(500));
         let mut auth_refresh_interval = interval(Duration::from_secs(60));
         let mut metrics_interval = interval(Duration::from_secs(1));
 
         let mut heartbeat_count = 0;
-        while !exit.load(Ordering::Relaxed) {
+        while !exit.load(Ordering::Relaxed) && state.is_selected() {
             select! {
                 _ = heartbeat_interval.tick() => {
                     trace!("sending heartbeat");
@@ -401,27 +1032,29 @@ impl BlockEngineRelayerHandler {
 
                     heartbeat_count += 1;
                 }
-                maybe_aoi = aoi_stream.message() => {
+                maybe_aoi = Self::next_message(&mut aoi_stream) => {
                     trace!("received aoi message");
 
                     let now = Instant::now();
 
-                    let num_pubkeys = Self::handle_aoi(maybe_aoi, &mut accounts_of_interest)?;
+                    let num_pubkeys = Self::handle_aoi(maybe_aoi, &state.interest_cache)?;
 
                     block_engine_stats.increment_aoi_update_elapsed_us(now.elapsed().as_micros() as u64);
                     block_engine_stats.increment_aoi_update_count(1);
                     block_engine_stats.increment_aoi_accounts_received(num_pubkeys as u64);
+                    has_aoi = true;
                 }
-                maybe_poi = poi_stream.message() => {
+                maybe_poi = Self::next_message(&mut poi_stream) => {
                     trace!("received poi message");
 
                     let now = Instant::now();
 
-                    let num_pubkeys = Self::handle_poi(maybe_poi, &mut programs_of_interest)?;
+                    let num_pubkeys = Self::handle_poi(maybe_poi, &state.interest_cache)?;
 
                     block_engine_stats.increment_poi_update_elapsed_us(now.elapsed().as_micros() as u64);
                     block_engine_stats.increment_poi_update_count(1);
                     block_engine_stats.increment_poi_accounts_received(num_pubkeys as u64);
+                    has_poi = true;
                 }
                 block_engine_batches = block_engine_receiver.recv() => {
                     trace!("received block engine batches");
@@ -434,15 +1067,15 @@ impl BlockEngineRelayerHandler {
                     let num_packets: u64 = block_engine_batches.banking_packet_batch.0.iter().map(|b|b.len() as u64).sum::<u64>();
                     block_engine_stats.increment_num_packets_received(num_packets);
 
-                    let filtered_packets = Self::filter_packets(block_engine_batches, num_packets, &mut accounts_of_interest, &mut programs_of_interest, address_lookup_table_cache, ofac_addresses);
+                    let filtered_packets = Self::filter_packets(&block_engine_batches, num_packets, state.forward_all, &state.interest_cache.read().unwrap(), address_lookup_table_cache, ofac_addresses, &static_interest.read());
                     block_engine_stats.increment_packet_filter_elapsed_us(now.elapsed().as_micros() as u64);
 
-                    if let Some(filtered_packets) = filtered_packets {
-                        let now = Instant::now();
-                        let packet_forward_count = Self::forward_packets(&block_engine_packet_sender, filtered_packets).await?;
-                        block_engine_stats.increment_packet_forward_count(packet_forward_count as u64);
-                        block_engine_stats.increment_packet_forward_elapsed_us(now.elapsed().as_micros() as u64);
-                    }
+                    let batches = packet_coalescer.push(block_engine_batches.stamp, block_engine_batches.expiration, filtered_packets, Instant::now());
+                    Self::forward_packets(&block_engine_packet_sender, batches, &mut block_engine_stats).await?;
+                }
+                _ = Self::sleep_until_flush(packet_coalescer.flush_deadline()) => {
+                    trace!("flushing coalesced packets");
+                    Self::forward_packets(&block_engine_packet_sender, packet_coalescer.flush(), &mut block_engine_stats).await?;
                 }
                 _ = auth_refresh_interval.tick() => {
                     trace!("refreshing auth interval");
@@ -460,24 +1093,63 @@ impl BlockEngineRelayerHandler {
 
                     // removes expired items from aoi cache
                     let flush_start = Instant::now();
-                    accounts_of_interest.flush();
-                    programs_of_interest.flush();
+                    let (accounts_of_interest_len, programs_of_interest_len) = {
+                        let mut interest_cache = state.interest_cache.write().unwrap();
+                        interest_cache.flush(flush_start);
+                        (interest_cache.len(InterestKind::Account), interest_cache.len(InterestKind::Program))
+                    };
 
                     block_engine_stats.increment_flush_elapsed_us(flush_start.elapsed().as_micros() as u64);
-                    block_engine_stats.increment_accounts_of_interest_len(accounts_of_interest.cache_size() as u64);
-                    block_engine_stats.increment_programs_of_interest_len(programs_of_interest.cache_size() as u64);
+                    block_engine_stats.increment_accounts_of_interest_len(accounts_of_interest_len as u64);
+                    block_engine_stats.increment_programs_of_interest_len(programs_of_interest_len as u64);
 
-                    block_engine_stats.report();
+                    block_engine_stats.report(&state.block_engine_url);
                     block_engine_stats = BlockEngineStats::default();
                 }
             }
 
+            if has_aoi && has_poi && !replay_batches.is_empty() {
+                // replayed packets are coalesced among themselves, not with live ones
+                Self::forward_packets(
+                    &block_engine_packet_sender,
+                    packet_coalescer.flush(),
+                    &mut block_engine_stats,
+                )
+                .await?;
+                Self::replay_packets(
+                    &block_engine_packet_sender,
+                    std::mem::take(&mut replay_batches),
+                    state.forward_all,
+                    &state.interest_cache,
+                    address_lookup_table_cache,
+                    ofac_addresses,
+                    static_interest,
+                    &mut packet_coalescer,
+                    &mut block_engine_stats,
+                )
+                .await?;
+                Self::forward_packets(
+                    &block_engine_packet_sender,
+                    packet_coalescer.flush(),
+                    &mut block_engine_stats,
+                )
+                .await?;
+            }
+
             block_engine_stats.update_block_engine_packet_sender_len(
                 (Self::BLOCK_ENGINE_PACKET_QUEUE_CAPACITY - block_engine_packet_sender.capacity())
                     as u64,
             );
         }
-        Ok(())
+
+        // the coalescer doesn't outlive this connection, pending packets would be dropped when
+        // deselected or exiting
+        Self::forward_packets(
+            &block_engine_packet_sender,
+            packet_coalescer.flush(),
+            &mut block_engine_stats,
+        )
+        .await
     }
 
     /// Refresh authentication tokens if they're about to expire
@@ -554,9 +1226,25 @@ impl BlockEngineRelayerHandler {
         }
     }
 
+    /// Sleeps until the coalesced packets are due, never resolves when nothing is pending
+    async fn sleep_until_flush(deadline: Option<Instant>) {
+        match deadline {
+            Some(deadline) => sleep_until(deadline.into()).await,
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Next message of an interest stream, never resolves when not subscribed
+    async fn next_message<T>(stream: &mut Option<Streaming<T>>) -> Result<Option<T>, Status> {
+        match stream {
+            Some(stream) => stream.message().await,
+            None => std::future::pending().await,
+        }
+    }
+
     fn handle_aoi(
         maybe_msg: Result<Option<AccountsOfInterestUpdate>, Status>,
-        accounts_of_interest: &mut TimedCache<Pubkey, u8>,
+        interest_cache: &RwLock<InterestCache>,
     ) -> BlockEngineResult<usize> {
         match maybe_msg {
             Ok(Some(aoi_update)) => {
@@ -568,9 +1256,11 @@ impl BlockEngineRelayerHandler {
 
                 let num_pubkeys = pubkeys.len();
 
-                pubkeys.into_iter().for_each(|pubkey| {
-                    accounts_of_interest.cache_set(pubkey, 0);
-                });
+                interest_cache.write().unwrap().insert(
+                    InterestKind::Account,
+                    pubkeys,
+                    Instant::now(),
+                );
 
                 Ok(num_pubkeys)
             }
@@ -583,7 +1273,7 @@ impl BlockEngineRelayerHandler {
 
     fn handle_poi(
         maybe_msg: Result<Option<ProgramsOfInterestUpdate>, Status>,
-        programs_of_interest: &mut TimedCache<Pubkey, u8>,
+        interest_cache: &RwLock<InterestCache>,
     ) -> BlockEngineResult<usize> {
         match maybe_msg {
             Ok(Some(poi_update)) => {
@@ -595,9 +1285,11 @@ impl BlockEngineRelayerHandler {
 
                 let num_pubkeys = pubkeys.len();
 
-                pubkeys.into_iter().for_each(|pubkey| {
-                    programs_of_interest.cache_set(pubkey, 0);
-                });
+                interest_cache.write().unwrap().insert(
+                    InterestKind::Program,
+                    pubkeys,
+                    Instant::now(),
+                );
 
                 Ok(num_pubkeys)
             }
@@ -608,38 +1300,128 @@ impl BlockEngineRelayerHandler {
         }
     }
 
+    /// Takes the packets buffered while disconnected. Batches still queued from the previous
+    /// connection are older than the buffered ones, so they're buffered first.
+    fn take_replay_batches(
+        block_engine_receiver: &mut Receiver<BlockEnginePackets>,
+        state: &BlockEngineState,
+        block_engine_stats: &mut BlockEngineStats,
+    ) -> Vec<BlockEnginePackets> {
+        let now = SystemTime::now();
+        let mut replay_buffer = state.replay_buffer.lock().unwrap();
+        let buffered = replay_buffer.take(now);
+        while let Ok(block_engine_batches) = block_engine_receiver.try_recv() {
+            replay_buffer.push(block_engine_batches, now);
+        }
+        for block_engine_batches in buffered.batches {
+            replay_buffer.push(block_engine_batches, now);
+        }
+        let replay_batches = replay_buffer.take(now);
+
+        block_engine_stats.increment_num_replay_packets_buffered(replay_batches.num_packets);
+        block_engine_stats.increment_num_replay_packets_evicted(
+            buffered.num_packets_evicted + replay_batches.num_packets_evicted,
+        );
+        block_engine_stats.increment_num_replay_packets_expired(
+            buffered.num_packets_expired + replay_batches.num_packets_expired,
+        );
+        replay_batches.batches
+    }
+
+    /// Forwards packets buffered while disconnected that haven't expired since reconnecting
+    #[allow(clippy::too_many_arguments)]
+    async fn replay_packets(
+        block_engine_packet_sender: &Sender<PacketBatchUpdate>,
+        replay_batches: Vec<BlockEnginePackets>,
+        forward_all: bool,
+        interest_cache: &RwLock<InterestCache>,
+        address_lookup_table_cache: &DashMap<Pubkey, AddressLookupTableAccount>,
+        ofac_addresses: &HashSet<Pubkey>,
+        static_interest: &StaticInterestHandle,
+        packet_coalescer: &mut PacketCoalescer,
+        block_engine_stats: &mut BlockEngineStats,
+    ) -> BlockEngineResult<()> {
+        let now = SystemTime::now();
+        for block_engine_batches in replay_batches {
+            let num_packets = block_engine_batches.num_unfiltered_packets();
+            if block_engine_batches.is_expired(now) {
+                block_engine_stats.increment_num_replay_packets_expired(num_packets);
+                continue;
+            }
+
+            let filtered_packets = Self::filter_packets(
+                &block_engine_batches,
+                num_packets,
+                forward_all,
+                &interest_cache.read().unwrap(),
+                address_lookup_table_cache,
+                ofac_addresses,
+                &static_interest.read(),
+            );
+            block_engine_stats
+                .increment_num_replay_packets_forwarded(filtered_packets.len() as u64);
+
+            let batches = packet_coalescer.push(
+                block_engine_batches.stamp,
+                block_engine_batches.expiration,
+                filtered_packets,
+                Instant::now(),
+            );
+            Self::forward_packets(block_engine_packet_sender, batches, block_engine_stats).await?;
+        }
+        Ok(())
+    }
+
     /// Forwards packets to the Block Engine
     async fn forward_packets(
         block_engine_packet_sender: &Sender<PacketBatchUpdate>,
-        batch: ExpiringPacketBatch,
-    ) -> BlockEngineResult<usize> {
-        let num_packets = batch.batch.as_ref().unwrap().packets.len();
+        batches: impl IntoIterator<Item = ExpiringPacketBatch>,
+        block_engine_stats: &mut BlockEngineStats,
+    ) -> BlockEngineResult<()> {
+        for batch in batches {
+            let now = Instant::now();
+            let num_packets = batch.batch.as_ref().unwrap().packets.len() as u64;
 
-        if let Err(e) = block_engine_packet_sender
-            .send(PacketBatchUpdate {
-                msg: Some(Msg::Batches(batch)),
-            })
-            .await
-        {
-            error!("error forwarding packets {}", e);
-            Err(BlockEngineError::BlockEngineFailure(
-                "error forwarding packets".to_string(),
-            ))
-        } else {
-            Ok(num_packets)
+            if let Err(e) = block_engine_packet_sender
+                .send(PacketBatchUpdate {
+                    msg: Some(Msg::Batches(batch)),
+                })
+                .await
+            {
+                error!("error forwarding packets {}", e);
+                return Err(BlockEngineError::BlockEngineFailure(
+                    "error forwarding packets".to_string(),
+                ));
+            }
+
+            block_engine_stats.increment_packet_forward_count(num_packets);
+            block_engine_stats.record_forwarded_batch_size(num_packets);
+            block_engine_stats
+                .increment_packet_forward_elapsed_us(now.elapsed().as_micros() as u64);
         }
+        Ok(())
     }
 
-    /// Filters out packets that aren't on list of interest
+    /// Filters out packets that aren't on list of interest, from the block engine or the operator's
+    /// static interest, and packets referencing the operator's denied accounts and programs. In
+    /// forward_all mode only the OFAC and deny list checks apply, and packets are only deserialized
+    /// when there's something to check.
+    #[allow(clippy::too_many_arguments)]
     fn filter_packets(
-        block_engine_batches: BlockEnginePackets,
+        block_engine_batches: &BlockEnginePackets,
         num_packets: u64,
-        accounts_of_interest: &mut TimedCache<Pubkey, u8>,
-        programs_of_interest: &mut TimedCache<Pubkey, u8>,
+        forward_all: bool,
+        interest_cache: &InterestCache,
         address_lookup_table_cache: &DashMap<Pubkey, AddressLookupTableAccount>,
         ofac_addresses: &HashSet<Pubkey>,
-    ) -> Option<ExpiringPacketBatch> {
+        static_interest: &StaticInterest,
+    ) -> Vec<ProtoPacket> {
         let mut filtered_packets = Vec::with_capacity(num_packets as usize);
+        let interest = Interest {
+            interest_cache,
+            static_interest,
+        };
+        let needs_tx = !forward_all || !ofac_addresses.is_empty() || static_interest.has_denies();
 
         for batch in &block_engine_batches.banking_packet_batch.0 {
             for packet in batch {
@@ -647,28 +1429,23 @@ impl BlockEngineRelayerHandler {
                     continue;
                 }
 
+                if !needs_tx {
+                    if let Some(packet) = packet_to_proto_packet(packet) {
+                        filtered_packets.push(packet)
+                    }
+                    continue;
+                }
+
                 if let Ok(tx) = packet.deserialize_slice::<VersionedTransaction, _>(..) {
-                    let is_forwardable = if ofac_addresses.is_empty() {
-                        is_aoi_in_static_keys(&tx, accounts_of_interest, programs_of_interest)
-                            || is_aoi_in_lookup_table(
-                                &tx,
-                                accounts_of_interest,
-                                programs_of_interest,
-                                address_lookup_table_cache,
-                            )
-                    } else {
-                        !is_tx_ofac_related(&tx, ofac_addresses, address_lookup_table_cache)
-                            && (is_aoi_in_static_keys(
-                                &tx,
-                                accounts_of_interest,
-                                programs_of_interest,
-                            ) || is_aoi_in_lookup_table(
-                                &tx,
-                                accounts_of_interest,
-                                programs_of_interest,
-                                address_lookup_table_cache,
-                            ))
-                    };
+                    let is_forwardable = (ofac_addresses.is_empty()
+                        || !is_tx_ofac_related(&tx, ofac_addresses, address_lookup_table_cache))
+                        && (!static_interest.has_denies()
+                            || !loaded_account_keys(&tx, address_lookup_table_cache)
+                                .iter()
+                                .any(|pubkey| static_interest.denies(pubkey)))
+                        && (forward_all
+                            || is_aoi_in_static_keys(&tx, &interest)
+                            || is_aoi_in_lookup_table(&tx, &interest, address_lookup_table_cache));
 
                     if is_forwardable {
                         if let Some(packet) = packet_to_proto_packet(packet) {
@@ -679,19 +1456,7 @@ impl BlockEngineRelayerHandler {
             }
         }
 
-        if !filtered_packets.is_empty() {
-            Some(ExpiringPacketBatch {
-                header: Some(Header {
-                    ts: Some(Timestamp::from(block_engine_batches.stamp)),
-                }),
-                batch: Some(ProtoPacketBatch {
-                    packets: filtered_packets,
-                }),
-                expiry_ms: block_engine_batches.expiration,
-            })
-        } else {
-            None
-        }
+        filtered_packets
     }
 
     /// Checks the heartbeat timeout and errors out if the heartbeat didn't come in time.
@@ -718,19 +1483,48 @@ impl BlockEngineRelayerHandler {
     }
 }
 
-fn is_aoi_in_static_keys(
+/// Accounts and programs of interest sent by a block engine, along with the operator's static
+/// interest
+struct Interest<'a> {
+    interest_cache: &'a InterestCache,
+    static_interest: &'a StaticInterest,
+}
+
+impl Interest<'_> {
+    fn contains(&self, kind: InterestKind, pubkey: &Pubkey) -> bool {
+        self.interest_cache.contains(kind, pubkey) || self.static_interest.allows(kind, pubkey)
+    }
+}
+
+/// Account keys of the transaction, including the ones loaded from cached lookup tables
+fn loaded_account_keys(
     tx: &VersionedTransaction,
-    accounts_of_interest: &mut TimedCache<Pubkey, u8>,
-    programs_of_interest: &mut TimedCache<Pubkey, u8>,
-) -> bool {
+    address_lookup_table_cache: &DashMap<Pubkey, AddressLookupTableAccount>,
+) -> Vec<Pubkey> {
+    let mut account_keys = tx.message.static_account_keys().to_vec();
+    for table in tx.message.address_table_lookups().unwrap_or_default() {
+        if let Some(lookup_info) = address_lookup_table_cache.get(&table.account_key) {
+            account_keys.extend(
+                table
+                    .writable_indexes
+                    .iter()
+                    .chain(&table.readonly_indexes)
+                    .filter_map(|idx| lookup_info.addresses.get(*idx as usize)),
+            );
+        }
+    }
+    account_keys
+}
+
+fn is_aoi_in_static_keys(tx: &VersionedTransaction, interest: &Interest) -> bool {
     tx.message
         .static_account_keys()
         .iter()
         .enumerate()
         .any(|(idx, acc)| {
-            (tx.message.is_maybe_writable(idx, None) && accounts_of_interest.cache_get(acc).is_some())
+            (tx.message.is_maybe_writable(idx, None) && interest.contains(InterestKind::Account, acc))
                 // note: can't detect CPIs without execution, so aggressively forward txs than contain account in POI
-                || programs_of_interest.cache_get(acc).is_some()
+                || interest.contains(InterestKind::Program, acc)
         })
 }
 
@@ -738,8 +1532,7 @@ fn is_aoi_in_static_keys(
 /// to find the address. Then determine if in accounts_of_interest
 fn is_aoi_in_lookup_table(
     tx: &VersionedTransaction,
-    accounts_of_interest: &mut TimedCache<Pubkey, u8>,
-    programs_of_interest: &mut TimedCache<Pubkey, u8>,
+    interest: &Interest,
     address_lookup_table_cache: &DashMap<Pubkey, AddressLookupTableAccount>,
 ) -> bool {
     if let Some(lookup_tables) = tx.message.address_table_lookups() {
@@ -747,10 +1540,10 @@ fn is_aoi_in_lookup_table(
             if let Some(lookup_info) = address_lookup_table_cache.get(&table.account_key) {
                 for idx in &table.writable_indexes {
                     if let Some(writable_account) = lookup_info.addresses.get(*idx as usize) {
-                        if accounts_of_interest.cache_get(writable_account).is_some()
+                        if interest.contains(InterestKind::Account, writable_account)
                             // note: can't detect CPIs without execution, so aggressively forward txs than contain account in POI
                             // also txs can say programs are write-locked, but they're demoted to read-locked when loaded. 
-                            || programs_of_interest.cache_get(writable_account).is_some()
+                            || interest.contains(InterestKind::Program, writable_account)
                         {
                             return true;
                         }
@@ -761,7 +1554,7 @@ fn is_aoi_in_lookup_table(
                     if let Some(readonly_account) = lookup_info.addresses.get(*idx as usize) {
                         // note: can't detect CPIs without execution, so aggressively forward txs than contain account in POI
                         // also txs can say programs are write-locked, but they're demoted to read-locked when loaded.
-                        if programs_of_interest.cache_get(readonly_account).is_some() {
+                        if interest.contains(InterestKind::Program, readonly_account) {
                             return true;
                         }
                     }
@@ -771,3 +1564,119 @@ fn is_aoi_in_lookup_table(
     }
     false
 }
+
+/// Collects every account and program of interest the transaction touches, following the same
+/// rules as [is_aoi_in_static_keys] and [is_aoi_in_lookup_table], along with the lookup tables that
+/// couldn't be resolved
+fn interest_matches(
+    tx: &VersionedTransaction,
+    interest: &Interest,
+    address_lookup_table_cache: &DashMap<Pubkey, AddressLookupTableAccount>,
+) -> (Vec<InterestMatch>, Vec<Pubkey>) {
+    let mut matches = Vec::new();
+    let mut push_match = |pubkey: &Pubkey, is_writable: bool, lookup_table: Option<Pubkey>| {
+        for kind in [InterestKind::Account, InterestKind::Program] {
+            if (is_writable || kind == InterestKind::Program) && interest.contains(kind, pubkey) {
+                matches.push(InterestMatch {
+                    pubkey: *pubkey,
+                    kind,
+                    lookup_table,
+                    is_static: !interest.interest_cache.contains(kind, pubkey),
+                });
+            }
+        }
+    };
+
+    for (idx, acc) in tx.message.static_account_keys().iter().enumerate() {
+        push_match(acc, tx.message.is_maybe_writable(idx, None), None);
+    }
+
+    let mut missing_lookup_tables = Vec::new();
+    for table in tx.message.address_table_lookups().unwrap_or_default() {
+        let Some(lookup_info) = address_lookup_table_cache.get(&table.account_key) else {
+            missing_lookup_tables.push(table.account_key);
+            continue;
+        };
+        for idx in &table.writable_indexes {
+            if let Some(writable_account) = lookup_info.addresses.get(*idx as usize) {
+                push_match(writable_account, true, Some(table.account_key));
+            }
+        }
+        for idx in &table.readonly_indexes {
+            if let Some(readonly_account) = lookup_info.addresses.get(*idx as usize) {
+                push_match(readonly_account, false, Some(table.account_key));
+            }
+        }
+    }
+
+    (matches, missing_lookup_tables)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        sync::Arc,
+        time::{Duration, SystemTime},
+    };
+
+    use dashmap::DashMap;
+    use solana_perf::packet::{Packet, PacketBatch};
+    use solana_sdk::{
+        hash::Hash, pubkey::Pubkey, signature::Keypair, system_transaction,
+        transaction::VersionedTransaction,
+    };
+
+    use crate::{
+        block_engine::{BlockEnginePackets, BlockEngineRelayerHandler},
+        interest_cache::InterestCache,
+        static_interest::{StaticInterest, StaticInterestConfig},
+    };
+
+    fn transfer_packet(to: &Pubkey) -> Packet {
+        let tx = VersionedTransaction::from(system_transaction::transfer(
+            &Keypair::new(),
+            to,
+            1,
+            Hash::default(),
+        ));
+        Packet::from_data(None, tx).unwrap()
+    }
+
+    #[test]
+    fn test_filter_packets_denied() {
+        let allowed = Pubkey::new_unique();
+        let denied = Pubkey::new_unique();
+        let block_engine_batches = BlockEnginePackets {
+            banking_packet_batch: Arc::new((
+                vec![PacketBatch::new(vec![
+                    transfer_packet(&allowed),
+                    transfer_packet(&denied),
+                ])],
+                None,
+            )),
+            stamp: SystemTime::now(),
+            expiration: 200,
+        };
+        let static_interest = StaticInterest::try_from(&StaticInterestConfig {
+            allow_accounts: vec![allowed.to_string(), denied.to_string()],
+            deny_accounts: vec![denied.to_string()],
+            ..StaticInterestConfig::default()
+        })
+        .unwrap();
+
+        // denied accounts are dropped even when allowed or forwarding everything
+        for forward_all in [false, true] {
+            let filtered_packets = BlockEngineRelayerHandler::filter_packets(
+                &block_engine_batches,
+                2,
+                forward_all,
+                &InterestCache::new(Duration::from_secs(60)),
+                &DashMap::new(),
+                &HashSet::new(),
+                &static_interest,
+            );
+            assert_eq!(filtered_packets.len(), 1);
+        }
+    }
+}
@@ -0,0 +1,197 @@
+//! Picks the block engine to connect to under the fastest policy. Candidates are probed
+//! periodically for their connect and auth round-trip time, and the selection only moves to a
+//! faster block engine once it has beaten the current one by the switch margin for the whole hold
+//! period, so probe jitter doesn't bounce the relayer between block engines.
+
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Copy, Debug)]
+pub struct BlockEngineSelectorConfig {
+    /// How often every candidate is probed
+    pub probe_interval: Duration,
+    /// Probes taking longer than this count as failed
+    pub probe_timeout: Duration,
+    /// How much faster a candidate must be than the selected block engine to be switched to
+    pub switch_margin: Duration,
+    /// How long a candidate must stay faster by the margin before switching to it
+    pub switch_hold: Duration,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SwitchReason {
+    /// Nothing was selected yet
+    Initial,
+    /// The probe of the selected block engine failed
+    SelectedUnhealthy,
+    /// Another block engine stayed faster for the hold period
+    Faster,
+}
+
+impl fmt::Display for SwitchReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SwitchReason::Initial => write!(f, "initial"),
+            SwitchReason::SelectedUnhealthy => write!(f, "selected_unhealthy"),
+            SwitchReason::Faster => write!(f, "faster"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Switch {
+    pub from: Option<usize>,
+    pub to: usize,
+    pub reason: SwitchReason,
+}
+
+#[derive(Debug)]
+pub struct BlockEngineSelector {
+    config: BlockEngineSelectorConfig,
+    selected: Option<usize>,
+    /// Candidate faster than the selected block engine and when it first was
+    challenger: Option<(usize, Instant)>,
+}
+
+impl BlockEngineSelector {
+    pub fn new(config: BlockEngineSelectorConfig) -> Self {
+        BlockEngineSelector {
+            config,
+            selected: None,
+            challenger: None,
+        }
+    }
+
+    pub fn selected(&self) -> Option<usize> {
+        self.selected
+    }
+
+    /// Takes the round-trip times of the latest probes, indexed like the candidates with None for
+    /// failed probes, and returns the switch to make if any
+    pub fn update(&mut self, rtts: &[Option<Duration>], now: Instant) -> Option<Switch> {
+        let Some((fastest, fastest_rtt)) = rtts
+            .iter()
+            .enumerate()
+            .filter_map(|(i, rtt)| Some((i, (*rtt)?)))
+            .min_by_key(|(_, rtt)| *rtt)
+        else {
+            // keep the current selection, its connection loop retries on its own
+            self.challenger = None;
+            return None;
+        };
+
+        let selected_rtt = self.selected.and_then(|i| rtts.get(i).copied().flatten());
+        let reason = match (self.selected, selected_rtt) {
+            (None, _) => SwitchReason::Initial,
+            (Some(_), None) => SwitchReason::SelectedUnhealthy,
+            (Some(selected), Some(selected_rtt)) => {
+                if fastest == selected || fastest_rtt + self.config.switch_margin >= selected_rtt {
+                    self.challenger = None;
+                    return None;
+                }
+                let since = match self.challenger {
+                    Some((challenger, since)) if challenger == fastest => since,
+                    _ => {
+                        self.challenger = Some((fastest, now));
+                        now
+                    }
+                };
+                if now.saturating_duration_since(since) < self.config.switch_hold {
+                    return None;
+                }
+                SwitchReason::Faster
+            }
+        };
+
+        let switch = Switch {
+            from: self.selected,
+            to: fastest,
+            reason,
+        };
+        self.selected = Some(fastest);
+        self.challenger = None;
+        Some(switch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::block_engine_selector::{
+        BlockEngineSelector, BlockEngineSelectorConfig, Switch, SwitchReason,
+    };
+
+    fn ms(ms: u64) -> Option<Duration> {
+        Some(Duration::from_millis(ms))
+    }
+
+    #[test]
+    fn test_block_engine_selector_update() {
+        let mut selector = BlockEngineSelector::new(BlockEngineSelectorConfig {
+            probe_interval: Duration::from_secs(1),
+            probe_timeout: Duration::from_millis(500),
+            switch_margin: Duration::from_millis(10),
+            switch_hold: Duration::from_secs(5),
+        });
+        let now = Instant::now();
+        let after = |secs: u64| now + Duration::from_secs(secs);
+
+        // nothing healthy, nothing selected
+        assert_eq!(selector.update(&[None, None], now), None);
+        assert_eq!(selector.selected(), None);
+
+        // the fastest healthy one is selected right away
+        assert_eq!(
+            selector.update(&[ms(50), ms(30)], now),
+            Some(Switch {
+                from: None,
+                to: 1,
+                reason: SwitchReason::Initial,
+            })
+        );
+
+        // faster but within the margin
+        assert_eq!(selector.update(&[ms(25), ms(30)], after(1)), None);
+        assert_eq!(selector.update(&[ms(25), ms(30)], after(10)), None);
+
+        // faster by the margin, switched to only once the hold has passed
+        assert_eq!(selector.update(&[ms(10), ms(30)], after(11)), None);
+        assert_eq!(selector.update(&[ms(10), ms(30)], after(15)), None);
+        assert_eq!(
+            selector.update(&[ms(10), ms(30)], after(16)),
+            Some(Switch {
+                from: Some(1),
+                to: 0,
+                reason: SwitchReason::Faster,
+            })
+        );
+        assert_eq!(selector.selected(), Some(0));
+
+        // a challenger that drops out during its hold starts over when it comes back
+        assert_eq!(selector.update(&[ms(30), ms(10)], after(17)), None);
+        assert_eq!(selector.update(&[ms(30), None], after(19)), None);
+        assert_eq!(selector.update(&[ms(30), ms(10)], after(20)), None);
+        assert_eq!(selector.update(&[ms(30), ms(10)], after(24)), None);
+        assert_eq!(
+            selector.update(&[ms(30), ms(10)], after(25)).map(|s| s.to),
+            Some(1)
+        );
+
+        // a failed probe of the selected one switches without waiting for the hold
+        assert_eq!(
+            selector.update(&[ms(30), None], after(26)),
+            Some(Switch {
+                from: Some(1),
+                to: 0,
+                reason: SwitchReason::SelectedUnhealthy,
+            })
+        );
+
+        // the selection is kept while every probe fails
+        assert_eq!(selector.update(&[None, None], after(27)), None);
+        assert_eq!(selector.selected(), Some(0));
+    }
+}
@@ -1,3 +1,4 @@
+use histogram::Histogram;
 use solana_metrics::datapoint_info;
 
 #[derive(Default)]
@@ -31,6 +32,16 @@ pub struct BlockEngineStats {
     accounts_of_interest_len: u64,
     programs_of_interest_len: u64,
     flush_elapsed_us: u64,
+
+    // packets queued while disconnected
+    num_replay_packets_buffered: u64,
+    num_replay_packets_expired: u64,
+    // dropped to make room for newer packets
+    num_replay_packets_evicted: u64,
+    num_replay_packets_forwarded: u64,
+
+    // packets per message sent to the block engine, after coalescing
+    forwarded_batch_sizes: Histogram,
 }
 
 impl BlockEngineStats {
@@ -111,9 +122,30 @@ impl BlockEngineStats {
         self.flush_elapsed_us = self.flush_elapsed_us.saturating_add(num)
     }
 
-    pub fn report(&self) {
+    pub fn increment_num_replay_packets_buffered(&mut self, num: u64) {
+        self.num_replay_packets_buffered = self.num_replay_packets_buffered.saturating_add(num)
+    }
+
+    pub fn increment_num_replay_packets_expired(&mut self, num: u64) {
+        self.num_replay_packets_expired = self.num_replay_packets_expired.saturating_add(num)
+    }
+
+    pub fn increment_num_replay_packets_evicted(&mut self, num: u64) {
+        self.num_replay_packets_evicted = self.num_replay_packets_evicted.saturating_add(num)
+    }
+
+    pub fn increment_num_replay_packets_forwarded(&mut self, num: u64) {
+        self.num_replay_packets_forwarded = self.num_replay_packets_forwarded.saturating_add(num)
+    }
+
+    pub fn record_forwarded_batch_size(&mut self, num: u64) {
+        let _ = self.forwarded_batch_sizes.increment(num);
+    }
+
+    pub fn report(&self, block_engine_url: &str) {
         datapoint_info!(
             "block_engine_relayer-loop_stats",
+            "block_engine_url" => block_engine_url,
             ("heartbeat_count", self.heartbeat_count, i64),
             ("heartbeat_elapsed_us", self.heartbeat_elapsed_us, i64),
             ("aoi_update_count", self.aoi_update_count, i64),
@@ -153,6 +185,62 @@ impl BlockEngineStats {
                 i64
             ),
             ("flush_elapsed_us", self.flush_elapsed_us, i64),
+            (
+                "num_replay_packets_buffered",
+                self.num_replay_packets_buffered,
+                i64
+            ),
+            (
+                "num_replay_packets_expired",
+                self.num_replay_packets_expired,
+                i64
+            ),
+            (
+                "num_replay_packets_evicted",
+                self.num_replay_packets_evicted,
+                i64
+            ),
+            (
+                "num_replay_packets_forwarded",
+                self.num_replay_packets_forwarded,
+                i64
+            ),
+            (
+                "num_forwarded_batches",
+                self.forwarded_batch_sizes.entries(),
+                i64
+            ),
+            (
+                "forwarded_batch_size_min",
+                self.forwarded_batch_sizes.minimum().unwrap_or_default(),
+                i64
+            ),
+            (
+                "forwarded_batch_size_max",
+                self.forwarded_batch_sizes.maximum().unwrap_or_default(),
+                i64
+            ),
+            (
+                "forwarded_batch_size_p50",
+                self.forwarded_batch_sizes
+                    .percentile(50.0)
+                    .unwrap_or_default(),
+                i64
+            ),
+            (
+                "forwarded_batch_size_p90",
+                self.forwarded_batch_sizes
+                    .percentile(90.0)
+                    .unwrap_or_default(),
+                i64
+            ),
+            (
+                "forwarded_batch_size_p99",
+                self.forwarded_batch_sizes
+                    .percentile(99.0)
+                    .unwrap_or_default(),
+                i64
+            ),
         )
     }
 }
@@ -0,0 +1,184 @@
+//! Accounts and programs of interest streamed from a block engine. Packets are only forwarded to a
+//! block engine when they touch something it's interested in. The cache is shared with the web
+//! server so operators can inspect what the relayer is filtering on.
+
+use std::{
+    collections::{BinaryHeap, HashMap},
+    time::{Duration, Instant},
+};
+
+use solana_sdk::pubkey::Pubkey;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InterestKind {
+    Account,
+    Program,
+}
+
+#[derive(Debug)]
+pub struct InterestCache {
+    ttl: Duration,
+    /// When each account was last received from the block engine
+    accounts: HashMap<Pubkey, Instant>,
+    /// When each program was last received from the block engine
+    programs: HashMap<Pubkey, Instant>,
+}
+
+impl InterestCache {
+    pub fn new(ttl: Duration) -> Self {
+        InterestCache {
+            ttl,
+            accounts: HashMap::new(),
+            programs: HashMap::new(),
+        }
+    }
+
+    fn entries(&self, kind: InterestKind) -> &HashMap<Pubkey, Instant> {
+        match kind {
+            InterestKind::Account => &self.accounts,
+            InterestKind::Program => &self.programs,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.accounts.clear();
+        self.programs.clear();
+    }
+
+    pub fn insert(
+        &mut self,
+        kind: InterestKind,
+        pubkeys: impl IntoIterator<Item = Pubkey>,
+        now: Instant,
+    ) {
+        let entries = match kind {
+            InterestKind::Account => &mut self.accounts,
+            InterestKind::Program => &mut self.programs,
+        };
+        entries.extend(pubkeys.into_iter().map(|pubkey| (pubkey, now)));
+    }
+
+    /// Removes entries past their TTL. Lookups don't check the TTL, so expired entries keep
+    /// matching until the next flush.
+    pub fn flush(&mut self, now: Instant) {
+        let ttl = self.ttl;
+        let is_live = |received_at: &Instant| now.saturating_duration_since(*received_at) < ttl;
+        self.accounts.retain(|_, received_at| is_live(received_at));
+        self.programs.retain(|_, received_at| is_live(received_at));
+    }
+
+    pub fn len(&self, kind: InterestKind) -> usize {
+        self.entries(kind).len()
+    }
+
+    pub fn contains(&self, kind: InterestKind, pubkey: &Pubkey) -> bool {
+        self.entries(kind).contains_key(pubkey)
+    }
+
+    /// Time until the entry expires, None if it isn't cached
+    pub fn ttl_remaining(
+        &self,
+        kind: InterestKind,
+        pubkey: &Pubkey,
+        now: Instant,
+    ) -> Option<Duration> {
+        self.entries(kind).get(pubkey).map(|received_at| {
+            self.ttl
+                .saturating_sub(now.saturating_duration_since(*received_at))
+        })
+    }
+
+    /// Returns up to limit entries ordered by pubkey, starting after the given pubkey, with the time
+    /// until each one expires
+    pub fn list(
+        &self,
+        kind: InterestKind,
+        after: Option<&Pubkey>,
+        limit: usize,
+        now: Instant,
+    ) -> Vec<(Pubkey, Duration)> {
+        if limit == 0 {
+            return Vec::new();
+        }
+
+        // max-heap holding the smallest pubkeys seen so far
+        let mut page = BinaryHeap::with_capacity(limit + 1);
+        for pubkey in self
+            .entries(kind)
+            .keys()
+            .filter(|pubkey| after.map_or(true, |after| *pubkey > after))
+        {
+            page.push(*pubkey);
+            if page.len() > limit {
+                page.pop();
+            }
+        }
+
+        page.into_sorted_vec()
+            .into_iter()
+            .map(|pubkey| {
+                let ttl_remaining = self.ttl_remaining(kind, &pubkey, now).unwrap_or_default();
+                (pubkey, ttl_remaining)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use solana_sdk::pubkey::Pubkey;
+
+    use crate::interest_cache::{InterestCache, InterestKind};
+
+    #[test]
+    fn test_interest_cache() {
+        let mut cache = InterestCache::new(Duration::from_secs(10));
+        let start = Instant::now();
+        let mut accounts: Vec<Pubkey> = (0..5).map(|_| Pubkey::new_unique()).collect();
+        accounts.sort();
+        let program = Pubkey::new_unique();
+
+        cache.insert(InterestKind::Account, accounts[..3].to_vec(), start);
+        cache.insert(
+            InterestKind::Account,
+            accounts[3..].to_vec(),
+            start + Duration::from_secs(5),
+        );
+        cache.insert(InterestKind::Program, [program], start);
+        assert!(cache.contains(InterestKind::Program, &program));
+        assert!(!cache.contains(InterestKind::Account, &program));
+
+        // pages are ordered by pubkey and continue after the last pubkey of the previous page
+        let now = start + Duration::from_secs(6);
+        let page = cache.list(InterestKind::Account, None, 2, now);
+        assert_eq!(
+            page,
+            vec![
+                (accounts[0], Duration::from_secs(4)),
+                (accounts[1], Duration::from_secs(4))
+            ]
+        );
+        let page = cache.list(InterestKind::Account, Some(&accounts[1]), 2, now);
+        assert_eq!(
+            page,
+            vec![
+                (accounts[2], Duration::from_secs(4)),
+                (accounts[3], Duration::from_secs(9))
+            ]
+        );
+
+        cache.flush(start + Duration::from_secs(10));
+        assert_eq!(cache.len(InterestKind::Account), 2);
+        assert_eq!(cache.len(InterestKind::Program), 0);
+        assert_eq!(
+            cache.ttl_remaining(
+                InterestKind::Account,
+                &accounts[4],
+                start + Duration::from_secs(10)
+            ),
+            Some(Duration::from_secs(5))
+        );
+    }
+}
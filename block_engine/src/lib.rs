@@ -1,2 +1,7 @@
 pub mod block_engine;
+pub mod block_engine_selector;
 pub mod block_engine_stats;
+pub mod interest_cache;
+pub mod packet_coalescer;
+pub mod replay_buffer;
+pub mod static_interest;
@@ -0,0 +1,166 @@
+//! Merges the filtered packets of consecutive banking batches into fewer, larger messages on the
+//! block engine stream. A merged batch is stamped with the oldest stamp of its packets and expires
+//! with the earliest of their expirations, so no packet is forwarded as live past its own
+//! expiration.
+
+use std::time::{Duration, Instant, SystemTime};
+
+use jito_protos::{
+    block_engine::ExpiringPacketBatch,
+    packet::{Packet as ProtoPacket, PacketBatch as ProtoPacketBatch},
+    shared::Header,
+};
+use prost_types::Timestamp;
+
+#[derive(Clone, Copy, Debug)]
+pub struct PacketCoalescerConfig {
+    /// Pending packets are flushed once there are this many, 0 or 1 disables coalescing
+    pub max_packets: usize,
+    /// Pending packets are flushed this long after the first of them was pushed
+    pub max_delay: Duration,
+}
+
+#[derive(Debug)]
+pub struct PacketCoalescer {
+    config: PacketCoalescerConfig,
+    packets: Vec<ProtoPacket>,
+    /// Oldest stamp of the pending packets
+    stamp: SystemTime,
+    /// Earliest expiration of the pending packets
+    expires_at: SystemTime,
+    /// When the first pending packet was pushed
+    pending_since: Option<Instant>,
+}
+
+impl PacketCoalescer {
+    pub fn new(config: PacketCoalescerConfig) -> Self {
+        PacketCoalescer {
+            config,
+            packets: Vec::new(),
+            stamp: SystemTime::UNIX_EPOCH,
+            expires_at: SystemTime::UNIX_EPOCH,
+            pending_since: None,
+        }
+    }
+
+    /// Adds packets stamped and expiring like the batch they came from, returning the batches to
+    /// forward. Pending packets are flushed first when the new ones don't fit.
+    pub fn push(
+        &mut self,
+        stamp: SystemTime,
+        expiration_ms: u32,
+        packets: Vec<ProtoPacket>,
+        now: Instant,
+    ) -> Vec<ExpiringPacketBatch> {
+        let mut batches = Vec::new();
+        if packets.is_empty() {
+            return batches;
+        }
+
+        if !self.packets.is_empty() && self.packets.len() + packets.len() > self.config.max_packets
+        {
+            batches.extend(self.flush());
+        }
+
+        let expires_at = stamp + Duration::from_millis(expiration_ms as u64);
+        if self.pending_since.is_none() {
+            self.stamp = stamp;
+            self.expires_at = expires_at;
+            self.pending_since = Some(now);
+        } else {
+            self.stamp = self.stamp.min(stamp);
+            self.expires_at = self.expires_at.min(expires_at);
+        }
+        self.packets.extend(packets);
+
+        if self.packets.len() >= self.config.max_packets {
+            batches.extend(self.flush());
+        }
+        batches
+    }
+
+    /// When the pending packets are due, None if nothing is pending
+    pub fn flush_deadline(&self) -> Option<Instant> {
+        self.pending_since
+            .map(|pending_since| pending_since + self.config.max_delay)
+    }
+
+    pub fn flush(&mut self) -> Option<ExpiringPacketBatch> {
+        self.pending_since.take()?;
+        let expiry_ms = self
+            .expires_at
+            .duration_since(self.stamp)
+            .unwrap_or_default()
+            .as_millis() as u32;
+        Some(ExpiringPacketBatch {
+            header: Some(Header {
+                ts: Some(Timestamp::from(self.stamp)),
+            }),
+            batch: Some(ProtoPacketBatch {
+                packets: std::mem::take(&mut self.packets),
+            }),
+            expiry_ms,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant, SystemTime};
+
+    use jito_protos::packet::Packet as ProtoPacket;
+    use prost_types::Timestamp;
+
+    use crate::packet_coalescer::{PacketCoalescer, PacketCoalescerConfig};
+
+    fn packets(num: usize) -> Vec<ProtoPacket> {
+        vec![ProtoPacket::default(); num]
+    }
+
+    #[test]
+    fn test_packet_coalescer() {
+        let mut coalescer = PacketCoalescer::new(PacketCoalescerConfig {
+            max_packets: 4,
+            max_delay: Duration::from_millis(5),
+        });
+        let now = Instant::now();
+        let stamp = SystemTime::now();
+
+        // stamped with the oldest packet, expiring with the earliest expiration
+        assert!(coalescer.push(stamp, 100, packets(2), now).is_empty());
+        assert!(coalescer
+            .push(stamp + Duration::from_millis(10), 50, packets(1), now)
+            .is_empty());
+        assert_eq!(
+            coalescer.flush_deadline(),
+            Some(now + Duration::from_millis(5))
+        );
+        let batch = coalescer.flush().unwrap();
+        assert_eq!(batch.header.unwrap().ts, Some(Timestamp::from(stamp)));
+        assert_eq!(batch.expiry_ms, 60);
+        assert_eq!(batch.batch.unwrap().packets.len(), 3);
+        assert_eq!(coalescer.flush_deadline(), None);
+        assert!(coalescer.flush().is_none());
+
+        // pending packets go out on their own when the new ones don't fit, then full batches are
+        // flushed right away
+        assert!(coalescer.push(stamp, 100, packets(3), now).is_empty());
+        let batches = coalescer.push(stamp, 100, packets(5), now);
+        assert_eq!(
+            batches
+                .into_iter()
+                .map(|b| b.batch.unwrap().packets.len())
+                .collect::<Vec<_>>(),
+            vec![3, 5]
+        );
+        assert_eq!(coalescer.flush_deadline(), None);
+
+        // disabled coalescing forwards every batch as it comes
+        let mut coalescer = PacketCoalescer::new(PacketCoalescerConfig {
+            max_packets: 0,
+            max_delay: Duration::from_millis(5),
+        });
+        assert_eq!(coalescer.push(stamp, 100, packets(2), now).len(), 1);
+        assert_eq!(coalescer.flush_deadline(), None);
+    }
+}
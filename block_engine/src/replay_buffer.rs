@@ -0,0 +1,166 @@
+//! Holds the newest packets dispatched to a disconnected block engine so they can be forwarded
+//! once it reconnects. The buffer is bounded in bytes of unfiltered packets and drops the oldest
+//! batches to make room, since those are the closest to expiring.
+
+use std::{collections::VecDeque, time::SystemTime};
+
+use crate::block_engine::BlockEnginePackets;
+
+/// Batches taken from the replay buffer and what was dropped since the previous take
+#[derive(Default)]
+pub struct ReplayBatches {
+    /// Unexpired batches, oldest first
+    pub batches: Vec<BlockEnginePackets>,
+    pub num_packets: u64,
+    /// Packets dropped to make room for newer ones
+    pub num_packets_evicted: u64,
+    pub num_packets_expired: u64,
+}
+
+pub struct ReplayBuffer {
+    max_bytes: usize,
+    /// Batches oldest first, with their number of unfiltered packets and bytes
+    batches: VecDeque<(BlockEnginePackets, u64, usize)>,
+    num_bytes: usize,
+    num_packets_evicted: u64,
+    num_packets_expired: u64,
+}
+
+impl ReplayBuffer {
+    pub fn new(max_bytes: usize) -> Self {
+        ReplayBuffer {
+            max_bytes,
+            batches: VecDeque::new(),
+            num_bytes: 0,
+            num_packets_evicted: 0,
+            num_packets_expired: 0,
+        }
+    }
+
+    /// Buffers the batch, dropping expired batches and then the oldest ones until it fits. A batch
+    /// bigger than the whole buffer is dropped itself.
+    pub fn push(&mut self, block_engine_batches: BlockEnginePackets, now: SystemTime) {
+        let num_packets = block_engine_batches.num_unfiltered_packets();
+        let num_bytes = block_engine_batches.num_unfiltered_bytes();
+        if num_bytes > self.max_bytes {
+            self.num_packets_evicted += num_packets;
+            return;
+        }
+
+        self.remove_expired(now);
+        while self.num_bytes + num_bytes > self.max_bytes {
+            let Some((_, evicted_packets, evicted_bytes)) = self.batches.pop_front() else {
+                break;
+            };
+            self.num_bytes -= evicted_bytes;
+            self.num_packets_evicted += evicted_packets;
+        }
+        self.num_bytes += num_bytes;
+        self.batches
+            .push_back((block_engine_batches, num_packets, num_bytes));
+    }
+
+    /// Empties the buffer, returning the batches that haven't expired
+    pub fn take(&mut self, now: SystemTime) -> ReplayBatches {
+        self.remove_expired(now);
+        let num_packets = self
+            .batches
+            .iter()
+            .map(|(_, num_packets, _)| num_packets)
+            .sum();
+        let replay_batches = ReplayBatches {
+            batches: self
+                .batches
+                .drain(..)
+                .map(|(block_engine_batches, _, _)| block_engine_batches)
+                .collect(),
+            num_packets,
+            num_packets_evicted: std::mem::take(&mut self.num_packets_evicted),
+            num_packets_expired: std::mem::take(&mut self.num_packets_expired),
+        };
+        self.num_bytes = 0;
+        replay_batches
+    }
+
+    /// Batches are stamped when dispatched, so they're pushed in stamp order but their
+    /// expirations may differ
+    fn remove_expired(&mut self, now: SystemTime) {
+        let num_bytes = &mut self.num_bytes;
+        let num_packets_expired = &mut self.num_packets_expired;
+        self.batches
+            .retain(|(block_engine_batches, num_packets, batch_bytes)| {
+                let is_expired = block_engine_batches.is_expired(now);
+                if is_expired {
+                    *num_bytes -= batch_bytes;
+                    *num_packets_expired += num_packets;
+                }
+                !is_expired
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::{Duration, SystemTime},
+    };
+
+    use solana_perf::packet::{Packet, PacketBatch};
+
+    use crate::{block_engine::BlockEnginePackets, replay_buffer::ReplayBuffer};
+
+    fn batch(stamp: SystemTime, expiration_ms: u32, num_packets: usize) -> BlockEnginePackets {
+        let mut packet = Packet::default();
+        packet.meta_mut().size = 100;
+        BlockEnginePackets {
+            banking_packet_batch: Arc::new((
+                vec![PacketBatch::new(vec![packet; num_packets])],
+                None,
+            )),
+            stamp,
+            expiration: expiration_ms,
+        }
+    }
+
+    #[test]
+    fn test_replay_buffer() {
+        let now = SystemTime::now();
+        let mut replay_buffer = ReplayBuffer::new(500);
+
+        // the oldest batches are evicted to make room for the newest
+        replay_buffer.push(batch(now, 1_000, 2), now);
+        replay_buffer.push(batch(now, 1_000, 2), now);
+        replay_buffer.push(batch(now, 1_000, 3), now);
+        let replay_batches = replay_buffer.take(now);
+        assert_eq!(
+            replay_batches
+                .batches
+                .iter()
+                .map(|b| b.num_unfiltered_packets())
+                .collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+        assert_eq!(replay_batches.num_packets, 5);
+        assert_eq!(replay_batches.num_packets_evicted, 2);
+        assert_eq!(replay_batches.num_packets_expired, 0);
+
+        // expired batches are dropped before anything unexpired is evicted, and on take
+        replay_buffer.push(batch(now, 100, 3), now);
+        replay_buffer.push(batch(now, 1_000, 1), now);
+        let later = now + Duration::from_millis(200);
+        replay_buffer.push(batch(later, 1_000, 4), later);
+        let replay_batches = replay_buffer.take(now + Duration::from_millis(1_100));
+        assert_eq!(replay_batches.num_packets, 4);
+        assert_eq!(replay_batches.num_packets_evicted, 0);
+        assert_eq!(replay_batches.num_packets_expired, 4);
+
+        // a batch bigger than the buffer doesn't evict anything
+        replay_buffer.push(batch(now, 1_000, 1), now);
+        replay_buffer.push(batch(now, 1_000, 6), now);
+        let replay_batches = replay_buffer.take(now);
+        assert_eq!(replay_batches.num_packets, 1);
+        assert_eq!(replay_batches.num_packets_evicted, 6);
+        assert!(replay_buffer.take(now).batches.is_empty());
+    }
+}
@@ -0,0 +1,235 @@
+//! Operator configured accounts and programs of interest. Allowed pubkeys are forwarded to block
+//! engines as if the block engine had sent them as accounts or programs of interest, and
+//! transactions referencing a denied pubkey are never forwarded to block engines. The config file
+//! is polled and reloaded when it changes, without reconnecting to the block engines.
+
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock, RwLockReadGuard,
+    },
+    thread,
+    thread::{sleep, Builder, JoinHandle},
+    time::{Duration, SystemTime},
+};
+
+use log::{error, info};
+use serde::Deserialize;
+use solana_metrics::datapoint_info;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::interest_cache::InterestKind;
+
+const RELOAD_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Static interest as written in the operator's yaml file
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct StaticInterestConfig {
+    #[serde(default)]
+    pub allow_accounts: Vec<String>,
+    #[serde(default)]
+    pub allow_programs: Vec<String>,
+    #[serde(default)]
+    pub deny_accounts: Vec<String>,
+    #[serde(default)]
+    pub deny_programs: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StaticInterest {
+    allow_accounts: HashSet<Pubkey>,
+    allow_programs: HashSet<Pubkey>,
+    /// Denied accounts and programs, both match any account key of a transaction
+    deny: HashSet<Pubkey>,
+}
+
+fn parse_pubkeys(pubkeys: &[String]) -> Result<HashSet<Pubkey>, String> {
+    pubkeys
+        .iter()
+        .map(|p| Pubkey::from_str(p.trim()).map_err(|e| format!("invalid pubkey {p}: {e}")))
+        .collect()
+}
+
+impl TryFrom<&StaticInterestConfig> for StaticInterest {
+    type Error = String;
+
+    fn try_from(config: &StaticInterestConfig) -> Result<Self, Self::Error> {
+        let mut deny = parse_pubkeys(&config.deny_accounts)?;
+        deny.extend(parse_pubkeys(&config.deny_programs)?);
+        Ok(StaticInterest {
+            allow_accounts: parse_pubkeys(&config.allow_accounts)?,
+            allow_programs: parse_pubkeys(&config.allow_programs)?,
+            deny,
+        })
+    }
+}
+
+impl StaticInterest {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let file = fs::File::open(path).map_err(|e| format!("failed to open {path:?}: {e}"))?;
+        let config: StaticInterestConfig =
+            serde_yaml::from_reader(file).map_err(|e| format!("failed to read {path:?}: {e}"))?;
+        StaticInterest::try_from(&config)
+    }
+
+    pub fn allows(&self, kind: InterestKind, pubkey: &Pubkey) -> bool {
+        match kind {
+            InterestKind::Account => self.allow_accounts.contains(pubkey),
+            InterestKind::Program => self.allow_programs.contains(pubkey),
+        }
+    }
+
+    pub fn denies(&self, pubkey: &Pubkey) -> bool {
+        self.deny.contains(pubkey)
+    }
+
+    pub fn has_denies(&self) -> bool {
+        !self.deny.is_empty()
+    }
+}
+
+pub struct StaticInterestUpdater {
+    static_interest: Arc<RwLock<StaticInterest>>,
+
+    /// Reloads the config file when its modification time changes
+    reload_thread: JoinHandle<()>,
+}
+
+/// Access handle to the operator's static interest, empty when no config file is given
+#[derive(Clone, Default)]
+pub struct StaticInterestHandle {
+    static_interest: Arc<RwLock<StaticInterest>>,
+}
+
+impl StaticInterestHandle {
+    pub fn read(&self) -> RwLockReadGuard<'_, StaticInterest> {
+        self.static_interest.read().unwrap()
+    }
+}
+
+impl StaticInterestUpdater {
+    /// Fails if the config file can't be loaded initially, later reload failures keep the last
+    /// loaded config
+    pub fn new(path: PathBuf, exit: &Arc<AtomicBool>) -> Result<StaticInterestUpdater, String> {
+        let modified = Self::modified(&path);
+        let static_interest = Arc::new(RwLock::new(StaticInterest::load(&path)?));
+        let reload_thread = Self::reload_thread(static_interest.clone(), path, modified, exit);
+        Ok(StaticInterestUpdater {
+            static_interest,
+            reload_thread,
+        })
+    }
+
+    pub fn handle(&self) -> StaticInterestHandle {
+        StaticInterestHandle {
+            static_interest: self.static_interest.clone(),
+        }
+    }
+
+    pub fn join(self) -> thread::Result<()> {
+        self.reload_thread.join()
+    }
+
+    fn modified(path: &Path) -> Option<SystemTime> {
+        fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    fn reload_thread(
+        static_interest: Arc<RwLock<StaticInterest>>,
+        path: PathBuf,
+        mut last_modified: Option<SystemTime>,
+        exit: &Arc<AtomicBool>,
+    ) -> JoinHandle<()> {
+        let exit = exit.clone();
+        Builder::new()
+            .name("block_engine_static_interest-reload".to_string())
+            .spawn(move || {
+                while !exit.load(Ordering::Relaxed) {
+                    sleep(RELOAD_POLL_INTERVAL);
+
+                    let modified = Self::modified(&path);
+                    if modified == last_modified {
+                        continue;
+                    }
+                    last_modified = modified;
+
+                    match StaticInterest::load(&path) {
+                        Ok(new_static_interest) => {
+                            info!("reloaded block engine static interest from {path:?}");
+                            datapoint_info!(
+                                "block_engine_static_interest",
+                                ("reload_ok_count", 1, i64),
+                                (
+                                    "num_allow_accounts",
+                                    new_static_interest.allow_accounts.len(),
+                                    i64
+                                ),
+                                (
+                                    "num_allow_programs",
+                                    new_static_interest.allow_programs.len(),
+                                    i64
+                                ),
+                                ("num_deny", new_static_interest.deny.len(), i64),
+                            );
+                            *static_interest.write().unwrap() = new_static_interest;
+                        }
+                        Err(e) => {
+                            error!("error reloading block engine static interest: {e}");
+                            datapoint_info!(
+                                "block_engine_static_interest",
+                                ("reload_fail_count", 1, i64),
+                            );
+                        }
+                    }
+                }
+            })
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::pubkey::Pubkey;
+
+    use crate::{
+        interest_cache::InterestKind,
+        static_interest::{StaticInterest, StaticInterestConfig},
+    };
+
+    #[test]
+    fn test_static_interest() {
+        let account = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let denied_account = Pubkey::new_unique();
+        let denied_program = Pubkey::new_unique();
+        let config = StaticInterestConfig {
+            allow_accounts: vec![account.to_string()],
+            allow_programs: vec![format!(" {program} ")],
+            deny_accounts: vec![denied_account.to_string()],
+            deny_programs: vec![denied_program.to_string()],
+        };
+        let static_interest = StaticInterest::try_from(&config).unwrap();
+
+        assert!(static_interest.allows(InterestKind::Account, &account));
+        assert!(!static_interest.allows(InterestKind::Program, &account));
+        assert!(static_interest.allows(InterestKind::Program, &program));
+        assert!(!static_interest.allows(InterestKind::Account, &denied_account));
+
+        // denied accounts and programs both match any account key
+        assert!(static_interest.has_denies());
+        assert!(static_interest.denies(&denied_account));
+        assert!(static_interest.denies(&denied_program));
+        assert!(!static_interest.denies(&account));
+        assert!(!StaticInterest::default().has_denies());
+
+        let config = StaticInterestConfig {
+            deny_programs: vec![denied_program.to_string(), "not a pubkey".to_string()],
+            ..config
+        };
+        assert!(StaticInterest::try_from(&config).is_err());
+    }
+}
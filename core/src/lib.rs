@@ -13,17 +13,36 @@ use log::*;
 
 mod fetch_stage;
 pub mod ofac;
+pub mod receive_stamp_stage;
 mod staked_nodes_updater_service;
 pub mod tpu;
 
-/// Returns an exit boolean to let other threads gracefully shut down
-pub fn graceful_panic(callback: Option<fn(&PanicInfo)>) -> Arc<AtomicBool> {
+/// Returns an exit boolean to let other threads gracefully shut down.
+/// Panics on threads whose name starts with one of recoverable_thread_prefixes are reported but
+/// don't exit the process, whoever owns those threads is responsible for restarting the failed
+/// work.
+pub fn graceful_panic(
+    callback: Option<fn(&PanicInfo)>,
+    recoverable_thread_prefixes: &'static [&'static str],
+) -> Arc<AtomicBool> {
     let exit = Arc::new(AtomicBool::new(false));
     // Fail fast!
     let panic_hook = panic::take_hook();
     {
         let exit = exit.clone();
         panic::set_hook(Box::new(move |panic_info| {
+            let thread = std::thread::current();
+            let thread_name = thread.name().unwrap_or_default();
+            if recoverable_thread_prefixes
+                .iter()
+                .any(|prefix| thread_name.starts_with(prefix))
+            {
+                error!("thread {thread_name} panicked, not exiting: {panic_info}");
+                // still print the panic and backtrace, only skip exiting
+                panic_hook(panic_info);
+                return;
+            }
+
             error!("process panicked: {}", panic_info);
             if let Some(f) = callback {
                 f(panic_info);
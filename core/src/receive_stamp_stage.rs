@@ -0,0 +1,207 @@
+//! The `receive_stamp_stage` sits between the QUIC servers and sigverify and remembers when
+//! packets were received, so latency can be measured from QUIC receipt instead of from the
+//! sigverify output. Sigverify may dedup and compact batches, so lookups are best effort. The stage
+//! is only built when tracing is enabled.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, Builder, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crossbeam_channel::RecvTimeoutError;
+use solana_metrics::datapoint_info;
+use solana_perf::packet::PacketBatch;
+use solana_sdk::{
+    packet::Packet,
+    signature::{Signature, SIGNATURE_BYTES},
+};
+use solana_streamer::streamer::{PacketBatchReceiver, PacketBatchSender};
+
+/// Receive times that were never looked up (e.g. deduped packets) are forgotten after this long
+const RECEIVE_TIME_RETENTION: Duration = Duration::from_secs(10);
+
+/// Packets received while this many receive times are remembered aren't recorded, so spam that
+/// sigverify drops can't grow the map without bound
+const MAX_RECEIVE_TIMES: usize = 100_000;
+
+/// Returns the first signature of a transaction packet without deserializing it. Assumes fewer
+/// than 128 signatures so the length prefix is a single byte.
+fn first_signature(packet: &Packet) -> Option<Signature> {
+    packet
+        .data(1..1 + SIGNATURE_BYTES)
+        .and_then(|bytes| Signature::try_from(bytes).ok())
+}
+
+/// Shared record of when packets were received over QUIC, keyed on their first signature.
+/// Every packet is recorded since tracer packets are only flagged later, by sigverify.
+#[derive(Clone, Default)]
+pub struct QuicReceiveTimes {
+    receive_times: Arc<Mutex<HashMap<Signature, Instant>>>,
+}
+
+impl QuicReceiveTimes {
+    /// Returns the number of packets that weren't recorded because the map is full
+    fn record(&self, packet_batch: &PacketBatch, received: Instant) -> u64 {
+        let mut l_receive_times = self.receive_times.lock().unwrap();
+        let mut num_dropped = 0;
+        for signature in packet_batch
+            .iter()
+            .filter(|p| !p.meta().discard())
+            .filter_map(first_signature)
+        {
+            if l_receive_times.len() >= MAX_RECEIVE_TIMES {
+                num_dropped += 1;
+                continue;
+            }
+            l_receive_times.insert(signature, received);
+        }
+        num_dropped
+    }
+
+    fn purge(&self, now: Instant) -> usize {
+        let mut l_receive_times = self.receive_times.lock().unwrap();
+        l_receive_times
+            .retain(|_, received| now.duration_since(*received) < RECEIVE_TIME_RETENTION);
+        l_receive_times.len()
+    }
+
+    /// Removes the receive times recorded for sigverified packets. Returns the oldest receive
+    /// time found and the receive time of each tracer packet.
+    pub fn take(
+        &self,
+        packet_batches: &[PacketBatch],
+    ) -> (Option<Instant>, Vec<(Signature, Option<Instant>)>) {
+        let mut l_receive_times = self.receive_times.lock().unwrap();
+
+        let mut oldest: Option<Instant> = None;
+        let mut tracer_packets = Vec::new();
+        for packet_batch in packet_batches {
+            for packet in packet_batch.iter() {
+                let Some(signature) = first_signature(packet) else {
+                    continue;
+                };
+                let received = l_receive_times.remove(&signature);
+                if let Some(received) = received {
+                    oldest = Some(oldest.map_or(received, |oldest| oldest.min(received)));
+                }
+                if packet.meta().is_tracer_packet() {
+                    tracer_packets.push((signature, received));
+                }
+            }
+        }
+        (oldest, tracer_packets)
+    }
+}
+
+pub struct ReceiveStampStage {
+    thread_hdl: JoinHandle<()>,
+}
+
+impl ReceiveStampStage {
+    pub fn new(
+        quic_receiver: PacketBatchReceiver,
+        sigverify_sender: PacketBatchSender,
+        quic_receive_times: QuicReceiveTimes,
+        exit: Arc<AtomicBool>,
+    ) -> Self {
+        let thread_hdl = Builder::new()
+            .name("receive_stamp_stage".to_string())
+            .spawn(move || {
+                let metrics_interval = Duration::from_secs(1);
+                let mut last_metrics_upload = Instant::now();
+                let mut num_batches = 0u64;
+                let mut num_receive_times_dropped = 0u64;
+                let mut sigverify_sender_max_len = 0usize;
+
+                while !exit.load(Ordering::Relaxed) {
+                    match quic_receiver.recv_timeout(Duration::from_millis(100)) {
+                        Ok(packet_batch) => {
+                            num_receive_times_dropped +=
+                                quic_receive_times.record(&packet_batch, Instant::now());
+                            num_batches += 1;
+                            if sigverify_sender.send(packet_batch).is_err() {
+                                break;
+                            }
+                        }
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                    sigverify_sender_max_len =
+                        std::cmp::max(sigverify_sender_max_len, sigverify_sender.len());
+
+                    if last_metrics_upload.elapsed() >= metrics_interval {
+                        let num_receive_times = quic_receive_times.purge(Instant::now());
+                        datapoint_info!(
+                            "receive_stamp_stage-stats",
+                            ("num_batches", num_batches, i64),
+                            ("num_receive_times", num_receive_times, i64),
+                            ("num_receive_times_dropped", num_receive_times_dropped, i64),
+                            ("sigverify_sender_len", sigverify_sender_max_len, i64),
+                        );
+                        num_batches = 0;
+                        num_receive_times_dropped = 0;
+                        sigverify_sender_max_len = 0;
+                        last_metrics_upload = Instant::now();
+                    }
+                }
+            })
+            .unwrap();
+
+        Self { thread_hdl }
+    }
+
+    pub fn join(self) -> thread::Result<()> {
+        self.thread_hdl.join()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use solana_perf::packet::PacketBatch;
+    use solana_sdk::{
+        hash::Hash, packet::Packet, signature::Signer, signer::keypair::Keypair,
+        system_transaction::transfer,
+    };
+
+    use crate::receive_stamp_stage::QuicReceiveTimes;
+
+    #[test]
+    fn test_tracer_packet_receive_time() {
+        let quic_receive_times = QuicReceiveTimes::default();
+        let payer = Keypair::new();
+        let txs: Vec<_> = (0..3)
+            .map(|lamports| transfer(&payer, &payer.pubkey(), lamports, Hash::default()))
+            .collect();
+        let mut packet_batch = PacketBatch::new(
+            txs.iter()
+                .map(|tx| Packet::from_data(None, tx).unwrap())
+                .collect(),
+        );
+
+        // tracer packets aren't flagged yet when received over QUIC
+        let received = Instant::now();
+        quic_receive_times.record(&packet_batch, received);
+
+        // sigverify flags the tracer packet, which isn't first in its batch
+        packet_batch[1].meta_mut().set_tracer(true);
+        let (oldest, tracer_packets) = quic_receive_times.take(&[packet_batch.clone()]);
+        assert_eq!(oldest, Some(received));
+        assert_eq!(tracer_packets, vec![(txs[1].signatures[0], Some(received))]);
+
+        // receive times are only handed out once
+        let (oldest, tracer_packets) = quic_receive_times.take(&[packet_batch]);
+        assert_eq!(oldest, None);
+        assert_eq!(tracer_packets, vec![(txs[1].signatures[0], None)]);
+        assert_eq!(
+            quic_receive_times.purge(received + Duration::from_secs(60)),
+            0
+        );
+    }
+}
@@ -24,7 +24,11 @@ use solana_streamer::{
     streamer::StakedNodes,
 };
 
-use crate::{fetch_stage::FetchStage, staked_nodes_updater_service::StakedNodesUpdaterService};
+use crate::{
+    fetch_stage::FetchStage,
+    receive_stamp_stage::{QuicReceiveTimes, ReceiveStampStage},
+    staked_nodes_updater_service::StakedNodesUpdaterService,
+};
 
 pub const DEFAULT_TPU_COALESCE_MS: u64 = 5;
 
@@ -40,6 +44,9 @@ pub struct TpuSockets {
 
 pub struct Tpu {
     fetch_stage: FetchStage,
+    receive_stamp_stage: Option<ReceiveStampStage>,
+    quic_receive_times: Option<QuicReceiveTimes>,
+    staked_nodes: Arc<RwLock<StakedNodes>>,
     staked_nodes_updater_service: StakedNodesUpdaterService,
     sigverify_stage: SigVerifyStage,
     thread_handles: Vec<JoinHandle<()>>,
@@ -48,6 +55,9 @@ pub struct Tpu {
 impl Tpu {
     pub const TPU_QUEUE_CAPACITY: usize = 10_000;
 
+    /// When record_quic_receive_times is set, the QUIC receive time of every packet is recorded
+    /// on its way to sigverify so latencies can be traced from QUIC receipt
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         sockets: TpuSockets,
         exit: &Arc<AtomicBool>,
@@ -56,6 +66,7 @@ impl Tpu {
         max_unstaked_quic_connections: usize,
         max_staked_quic_connections: usize,
         staked_nodes_overrides: HashMap<Pubkey, u64>,
+        record_quic_receive_times: bool,
     ) -> (Self, Receiver<BankingPacketBatch>) {
         let TpuSockets {
             transactions_quic_sockets,
@@ -129,10 +140,31 @@ impl Tpu {
 
         let fetch_stage = FetchStage::new(tpu_forwards_receiver, tpu_sender, exit.clone());
 
+        let (sigverify_receiver, receive_stamp_stage, quic_receive_times) =
+            if record_quic_receive_times {
+                // sender tracked as receive_stamp_stage-stats.sigverify_sender_len
+                let (sigverify_sender, sigverify_receiver) =
+                    crossbeam_channel::bounded(Tpu::TPU_QUEUE_CAPACITY);
+                let quic_receive_times = QuicReceiveTimes::default();
+                let receive_stamp_stage = ReceiveStampStage::new(
+                    tpu_receiver,
+                    sigverify_sender,
+                    quic_receive_times.clone(),
+                    exit.clone(),
+                );
+                (
+                    sigverify_receiver,
+                    Some(receive_stamp_stage),
+                    Some(quic_receive_times),
+                )
+            } else {
+                (tpu_receiver, None, None)
+            };
+
         let (banking_packet_sender, banking_packet_receiver) =
             BankingTracer::new_disabled().create_channel_non_vote();
         let sigverify_stage = SigVerifyStage::new(
-            tpu_receiver,
+            sigverify_receiver,
             TransactionSigVerifier::new(banking_packet_sender),
             "tpu-verifier",
             "tpu-verifier",
@@ -141,6 +173,9 @@ impl Tpu {
         (
             Tpu {
                 fetch_stage,
+                receive_stamp_stage,
+                quic_receive_times,
+                staked_nodes,
                 staked_nodes_updater_service,
                 sigverify_stage,
                 thread_handles: quic_tasks,
@@ -149,8 +184,22 @@ impl Tpu {
         )
     }
 
+    /// Handle to the QUIC receive times of packets, used to trace latency through the pipeline.
+    /// None unless recording them was enabled.
+    pub fn quic_receive_times(&self) -> Option<QuicReceiveTimes> {
+        self.quic_receive_times.clone()
+    }
+
+    /// Stakes of the nodes allowed to send over QUIC, refreshed from RPC
+    pub fn staked_nodes(&self) -> Arc<RwLock<StakedNodes>> {
+        self.staked_nodes.clone()
+    }
+
     pub fn join(self) -> thread::Result<()> {
         self.fetch_stage.join()?;
+        if let Some(receive_stamp_stage) = self.receive_stamp_stage {
+            receive_stamp_stage.join()?;
+        }
         self.staked_nodes_updater_service.join()?;
         self.sigverify_stage.join()?;
         for t in self.thread_handles {
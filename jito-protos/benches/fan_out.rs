@@ -0,0 +1,105 @@
+//! Compares fanning a packet batch out to many subscribers by cloning the protobuf message for
+//! each one against encoding it once and sharing the encoded bytes.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use jito_protos::{
+    packet::{Meta, Packet, PacketBatch, PacketFlags},
+    relayer::{subscribe_packets_response::Msg, SubscribePacketsResponse},
+    shared::Header,
+    subscribe_packets::EncodedPacketBatch,
+};
+use prost::Message;
+
+const NUM_SUBSCRIBERS: usize = 50;
+const NUM_PACKETS: usize = 4;
+const PACKET_SIZE: usize = 1232;
+
+/// Counts heap allocations so the benchmark can report them next to the timings
+struct CountingAllocator;
+
+static NUM_ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        NUM_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn packet_batch() -> PacketBatch {
+    PacketBatch {
+        packets: (0..NUM_PACKETS)
+            .map(|i| Packet {
+                data: vec![i as u8; PACKET_SIZE],
+                meta: Some(Meta {
+                    size: PACKET_SIZE as u64,
+                    addr: "127.0.0.1".to_string(),
+                    port: 8001,
+                    flags: Some(PacketFlags::default()),
+                    sender_stake: 0,
+                }),
+            })
+            .collect(),
+    }
+}
+
+/// Previous behavior: the full protobuf is cloned for, and encoded by, every subscriber
+fn fan_out_cloned(batch: &PacketBatch, buf: &mut Vec<u8>) {
+    for _ in 0..NUM_SUBSCRIBERS {
+        // cloning the generated message deep copies every packet
+        let cloned = black_box(batch.clone());
+        buf.clear();
+        Header::default().encode(buf).unwrap();
+        cloned.encode(buf).unwrap();
+    }
+}
+
+/// Encode once, every subscriber shares the encoded bytes
+fn fan_out_shared(batch: &PacketBatch, buf: &mut Vec<u8>) {
+    let encoded_batch = EncodedPacketBatch::encode(batch);
+    for _ in 0..NUM_SUBSCRIBERS {
+        let response = SubscribePacketsResponse {
+            header: Some(Header::default()),
+            msg: Some(Msg::Batch(encoded_batch.clone())),
+        };
+        buf.clear();
+        response.encode(buf).unwrap();
+    }
+}
+
+fn count_allocations(f: impl FnOnce()) -> usize {
+    let before = NUM_ALLOCATIONS.load(Ordering::Relaxed);
+    f();
+    NUM_ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+fn bench_fan_out(c: &mut Criterion) {
+    let batch = packet_batch();
+    let mut buf = Vec::with_capacity(NUM_PACKETS * PACKET_SIZE * 2);
+
+    println!(
+        "allocations per fan-out to {NUM_SUBSCRIBERS} subscribers: cloned={} shared={}",
+        count_allocations(|| fan_out_cloned(&batch, &mut buf)),
+        count_allocations(|| fan_out_shared(&batch, &mut buf)),
+    );
+
+    let mut group = c.benchmark_group("fan_out");
+    group.bench_function("cloned", |b| b.iter(|| fan_out_cloned(&batch, &mut buf)));
+    group.bench_function("shared", |b| b.iter(|| fan_out_shared(&batch, &mut buf)));
+    group.finish();
+}
+
+criterion_group!(benches, bench_fan_out);
+criterion_main!(benches);
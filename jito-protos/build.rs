@@ -8,6 +8,11 @@ fn main() {
     }
 
     configure()
+        // packet batches are pre-encoded once and shared across subscribers, see subscribe_packets.rs
+        .extern_path(
+            ".relayer.SubscribePacketsResponse",
+            "crate::subscribe_packets::SubscribePacketsResponse",
+        )
         .compile(
             &[
                 "protos/auth.proto",
@@ -1,4 +1,5 @@
 pub mod convert;
+pub mod subscribe_packets;
 
 pub mod auth {
     tonic::include_proto!("auth");
@@ -22,6 +23,8 @@ pub mod packet {
 
 pub mod relayer {
     tonic::include_proto!("relayer");
+
+    pub use crate::subscribe_packets::{subscribe_packets_response, SubscribePacketsResponse};
 }
 
 pub mod searcher {
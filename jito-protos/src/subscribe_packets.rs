@@ -0,0 +1,205 @@
+//! Hand-written replacement for the generated `relayer.SubscribePacketsResponse`, wired in through
+//! `extern_path` in build.rs. It's wire compatible with the generated message, but carries packet
+//! batches pre-encoded so a batch is encoded once and shared by every subscriber instead of being
+//! cloned and encoded per subscriber.
+
+use bytes::{Buf, BufMut, Bytes};
+use prost::{
+    encoding::{self, message, skip_field, DecodeContext, WireType},
+    DecodeError, Message,
+};
+
+use crate::{
+    packet::PacketBatch,
+    shared::{Header, Heartbeat},
+};
+
+const HEADER_TAG: u32 = 1;
+const HEARTBEAT_TAG: u32 = 2;
+const BATCH_TAG: u32 = 3;
+
+/// A `packet.PacketBatch` that's already protobuf encoded. Cloning only bumps a refcount.
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct EncodedPacketBatch {
+    bytes: Bytes,
+    num_packets: usize,
+}
+
+impl EncodedPacketBatch {
+    pub fn encode(packet_batch: &PacketBatch) -> EncodedPacketBatch {
+        EncodedPacketBatch {
+            bytes: packet_batch.encode_to_vec().into(),
+            num_packets: packet_batch.packets.len(),
+        }
+    }
+
+    pub fn decode(&self) -> Result<PacketBatch, DecodeError> {
+        PacketBatch::decode(self.bytes.clone())
+    }
+
+    pub fn num_packets(&self) -> usize {
+        self.num_packets
+    }
+
+    pub fn bytes(&self) -> &Bytes {
+        &self.bytes
+    }
+}
+
+pub mod subscribe_packets_response {
+    use crate::{shared::Heartbeat, subscribe_packets::EncodedPacketBatch};
+
+    #[derive(Clone, PartialEq, Debug)]
+    pub enum Msg {
+        Heartbeat(Heartbeat),
+        Batch(EncodedPacketBatch),
+    }
+}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct SubscribePacketsResponse {
+    pub header: Option<Header>,
+    pub msg: Option<subscribe_packets_response::Msg>,
+}
+
+impl Message for SubscribePacketsResponse {
+    fn encode_raw<B>(&self, buf: &mut B)
+    where
+        B: BufMut,
+        Self: Sized,
+    {
+        if let Some(header) = &self.header {
+            message::encode(HEADER_TAG, header, buf);
+        }
+        match &self.msg {
+            Some(subscribe_packets_response::Msg::Heartbeat(heartbeat)) => {
+                message::encode(HEARTBEAT_TAG, heartbeat, buf)
+            }
+            // a nested message is encoded the same as its bytes
+            Some(subscribe_packets_response::Msg::Batch(batch)) => {
+                encoding::bytes::encode(BATCH_TAG, &batch.bytes, buf)
+            }
+            None => {}
+        }
+    }
+
+    fn merge_field<B>(
+        &mut self,
+        tag: u32,
+        wire_type: WireType,
+        buf: &mut B,
+        ctx: DecodeContext,
+    ) -> Result<(), DecodeError>
+    where
+        B: Buf,
+        Self: Sized,
+    {
+        match tag {
+            HEADER_TAG => message::merge(
+                wire_type,
+                self.header.get_or_insert_with(Header::default),
+                buf,
+                ctx,
+            ),
+            HEARTBEAT_TAG => {
+                let mut heartbeat = match self.msg.take() {
+                    Some(subscribe_packets_response::Msg::Heartbeat(heartbeat)) => heartbeat,
+                    _ => Heartbeat::default(),
+                };
+                message::merge(wire_type, &mut heartbeat, buf, ctx)?;
+                self.msg = Some(subscribe_packets_response::Msg::Heartbeat(heartbeat));
+                Ok(())
+            }
+            BATCH_TAG => {
+                let mut bytes = Bytes::new();
+                encoding::bytes::merge(wire_type, &mut bytes, buf, ctx)?;
+                let num_packets = PacketBatch::decode(bytes.clone())?.packets.len();
+                self.msg = Some(subscribe_packets_response::Msg::Batch(EncodedPacketBatch {
+                    bytes,
+                    num_packets,
+                }));
+                Ok(())
+            }
+            _ => skip_field(wire_type, tag, buf, ctx),
+        }
+    }
+
+    fn encoded_len(&self) -> usize {
+        self.header
+            .as_ref()
+            .map_or(0, |header| message::encoded_len(HEADER_TAG, header))
+            + match &self.msg {
+                Some(subscribe_packets_response::Msg::Heartbeat(heartbeat)) => {
+                    message::encoded_len(HEARTBEAT_TAG, heartbeat)
+                }
+                Some(subscribe_packets_response::Msg::Batch(batch)) => {
+                    encoding::bytes::encoded_len(BATCH_TAG, &batch.bytes)
+                }
+                None => 0,
+            }
+    }
+
+    fn clear(&mut self) {
+        *self = SubscribePacketsResponse::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+
+    use crate::{
+        packet::{Meta, Packet, PacketBatch},
+        shared::{Header, Heartbeat},
+        subscribe_packets::{
+            subscribe_packets_response::Msg, EncodedPacketBatch, SubscribePacketsResponse,
+        },
+    };
+
+    /// Mirrors the generated message to check the hand-written one is wire compatible
+    #[derive(Clone, PartialEq, Message)]
+    struct GeneratedSubscribePacketsResponse {
+        #[prost(message, optional, tag = "1")]
+        header: Option<Header>,
+        #[prost(message, optional, tag = "2")]
+        heartbeat: Option<Heartbeat>,
+        #[prost(message, optional, tag = "3")]
+        batch: Option<PacketBatch>,
+    }
+
+    #[test]
+    fn test_wire_compatible() {
+        let batch = PacketBatch {
+            packets: vec![Packet {
+                data: vec![1, 2, 3],
+                meta: Some(Meta {
+                    size: 3,
+                    ..Meta::default()
+                }),
+            }],
+        };
+        let response = SubscribePacketsResponse {
+            header: Some(Header::default()),
+            msg: Some(Msg::Batch(EncodedPacketBatch::encode(&batch))),
+        };
+
+        let encoded = response.encode_to_vec();
+        assert_eq!(encoded.len(), response.encoded_len());
+
+        let generated = GeneratedSubscribePacketsResponse::decode(encoded.as_slice()).unwrap();
+        assert_eq!(generated.batch, Some(batch));
+        assert_eq!(
+            SubscribePacketsResponse::decode(generated.encode_to_vec().as_slice()).unwrap(),
+            response
+        );
+
+        let heartbeat = SubscribePacketsResponse {
+            header: None,
+            msg: Some(Msg::Heartbeat(Heartbeat { count: 7 })),
+        };
+        let generated =
+            GeneratedSubscribePacketsResponse::decode(heartbeat.encode_to_vec().as_slice())
+                .unwrap();
+        assert_eq!(generated.heartbeat, Some(Heartbeat { count: 7 }));
+    }
+}
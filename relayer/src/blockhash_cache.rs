@@ -0,0 +1,192 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    thread,
+    thread::{sleep, Builder, JoinHandle},
+    time::Duration,
+};
+
+use jito_rpc::load_balancer::LoadBalancer;
+use log::error;
+use solana_metrics::datapoint_info;
+use solana_sdk::{clock::MAX_PROCESSING_AGE, commitment_config::CommitmentConfig, hash::Hash};
+
+/// How many blocks past expiration a blockhash is remembered for, so that stale retries can be
+/// detected instead of being treated as unknown (~10 minutes).
+const EXPIRED_BLOCKHASH_RETENTION_BLOCKS: u64 = 1_500;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum BlockhashStatus {
+    /// The blockhash is recent enough for the transaction to land
+    Valid,
+    /// The blockhash is past its last valid block height
+    Expired,
+    /// The blockhash hasn't been observed. It's either newer than the last refresh, missed in
+    /// between refreshes, or older than the retention window.
+    Unknown,
+}
+
+#[derive(Default)]
+struct RecentBlockhashes {
+    /// Maps blockhash to the last block height a transaction using it can land in
+    last_valid_block_heights: HashMap<Hash, u64>,
+    /// Block height of the most recent refresh
+    block_height: u64,
+}
+
+impl RecentBlockhashes {
+    fn insert(&mut self, blockhash: Hash, last_valid_block_height: u64) {
+        self.last_valid_block_heights
+            .insert(blockhash, last_valid_block_height);
+        self.block_height = std::cmp::max(
+            self.block_height,
+            last_valid_block_height.saturating_sub(MAX_PROCESSING_AGE as u64),
+        );
+
+        let block_height = self.block_height;
+        self.last_valid_block_heights.retain(|_, last_valid| {
+            last_valid.saturating_add(EXPIRED_BLOCKHASH_RETENTION_BLOCKS) >= block_height
+        });
+    }
+
+    fn status(&self, blockhash: &Hash) -> BlockhashStatus {
+        match self.last_valid_block_heights.get(blockhash) {
+            Some(last_valid) if *last_valid < self.block_height => BlockhashStatus::Expired,
+            Some(_) => BlockhashStatus::Valid,
+            None => BlockhashStatus::Unknown,
+        }
+    }
+}
+
+pub struct BlockhashCacheUpdater {
+    blockhashes: Arc<RwLock<RecentBlockhashes>>,
+
+    /// Polls RPC for the latest blockhash
+    refresh_thread: JoinHandle<()>,
+}
+
+/// Access handle to a constantly updating set of recent blockhashes
+#[derive(Clone)]
+pub struct BlockhashCacheHandle {
+    blockhashes: Arc<RwLock<RecentBlockhashes>>,
+}
+
+impl BlockhashCacheHandle {
+    pub fn status(&self, blockhash: &Hash) -> BlockhashStatus {
+        self.blockhashes.read().unwrap().status(blockhash)
+    }
+}
+
+impl BlockhashCacheUpdater {
+    pub fn new(
+        load_balancer: &Arc<LoadBalancer>,
+        refresh_interval: Duration,
+        exit: &Arc<AtomicBool>,
+    ) -> BlockhashCacheUpdater {
+        let blockhashes = Arc::new(RwLock::new(RecentBlockhashes::default()));
+        let refresh_thread = Self::refresh_thread(
+            blockhashes.clone(),
+            load_balancer.clone(),
+            refresh_interval,
+            exit,
+        );
+        BlockhashCacheUpdater {
+            blockhashes,
+            refresh_thread,
+        }
+    }
+
+    /// Gets a handle to the constantly updating recent blockhashes
+    pub fn handle(&self) -> BlockhashCacheHandle {
+        BlockhashCacheHandle {
+            blockhashes: self.blockhashes.clone(),
+        }
+    }
+
+    pub fn join(self) -> thread::Result<()> {
+        self.refresh_thread.join()
+    }
+
+    fn refresh_thread(
+        blockhashes: Arc<RwLock<RecentBlockhashes>>,
+        load_balancer: Arc<LoadBalancer>,
+        refresh_interval: Duration,
+        exit: &Arc<AtomicBool>,
+    ) -> JoinHandle<()> {
+        let exit = exit.clone();
+        Builder::new()
+            .name("blockhash-cache-refresh".to_string())
+            .spawn(move || {
+                while !exit.load(Ordering::Relaxed) {
+                    let rpc_client = load_balancer.rpc_client();
+                    match rpc_client
+                        .get_latest_blockhash_with_commitment(CommitmentConfig::processed())
+                    {
+                        Ok((blockhash, last_valid_block_height)) => {
+                            let mut l_blockhashes = blockhashes.write().unwrap();
+                            l_blockhashes.insert(blockhash, last_valid_block_height);
+                            datapoint_info!(
+                                "blockhash-cache-update",
+                                ("block_height", l_blockhashes.block_height, i64),
+                                (
+                                    "num_blockhashes",
+                                    l_blockhashes.last_valid_block_heights.len(),
+                                    i64
+                                ),
+                            );
+                        }
+                        Err(e) => {
+                            error!("error fetching latest blockhash: {e:?}");
+                        }
+                    }
+
+                    sleep(refresh_interval);
+                }
+            })
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::{clock::MAX_PROCESSING_AGE, hash::Hash};
+
+    use crate::blockhash_cache::{
+        BlockhashStatus, RecentBlockhashes, EXPIRED_BLOCKHASH_RETENTION_BLOCKS,
+    };
+
+    #[test]
+    fn test_blockhash_status() {
+        let mut blockhashes = RecentBlockhashes::default();
+        let max_age = MAX_PROCESSING_AGE as u64;
+
+        let old_blockhash = Hash::new_unique();
+        blockhashes.insert(old_blockhash, 1_000 + max_age);
+        assert_eq!(blockhashes.status(&old_blockhash), BlockhashStatus::Valid);
+        assert_eq!(
+            blockhashes.status(&Hash::new_unique()),
+            BlockhashStatus::Unknown
+        );
+
+        // still valid at its last valid block height
+        let blockhash = Hash::new_unique();
+        blockhashes.insert(blockhash, 1_000 + 2 * max_age);
+        assert_eq!(blockhashes.status(&old_blockhash), BlockhashStatus::Valid);
+
+        // expires once the chain moves past its last valid block height
+        blockhashes.insert(Hash::new_unique(), 1_001 + 2 * max_age);
+        assert_eq!(blockhashes.status(&old_blockhash), BlockhashStatus::Expired);
+        assert_eq!(blockhashes.status(&blockhash), BlockhashStatus::Valid);
+
+        // forgotten once outside the retention window
+        blockhashes.insert(
+            Hash::new_unique(),
+            1_001 + 2 * max_age + EXPIRED_BLOCKHASH_RETENTION_BLOCKS,
+        );
+        assert_eq!(blockhashes.status(&old_blockhash), BlockhashStatus::Unknown);
+        assert_eq!(blockhashes.status(&blockhash), BlockhashStatus::Expired);
+    }
+}
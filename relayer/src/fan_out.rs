@@ -0,0 +1,1024 @@
+//! Packet fan-out to subscribed validators, kept off the relayer's control thread so a large
+//! burst of packets can't delay heartbeats or subscription handling.
+//!
+//! A dispatcher thread applies OFAC checks and encodes each batch once, then hands it to a pool of
+//! workers. Validators are sharded across the workers by identity, so all sessions of a validator
+//! are served by the same worker and per-session ordering is kept.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    thread,
+    thread::{Builder, JoinHandle},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crossbeam_channel::{bounded, Receiver, RecvTimeoutError, Sender};
+use dashmap::DashMap;
+use histogram::Histogram;
+use jito_core::ofac::is_tx_ofac_related;
+use jito_protos::{
+    convert::packet_to_proto_packet,
+    packet::PacketBatch as ProtoPacketBatch,
+    relayer::{subscribe_packets_response, SubscribePacketsResponse},
+    shared::Header,
+    subscribe_packets::EncodedPacketBatch,
+};
+use log::*;
+use prost_types::Timestamp;
+use solana_metrics::datapoint_info;
+use solana_sdk::{
+    address_lookup_table::AddressLookupTableAccount, packet::Packet, pubkey::Pubkey,
+    saturating_add_assign, transaction::VersionedTransaction,
+};
+use tokio::sync::mpsc::error::TrySendError;
+
+use crate::{
+    leader_fallback::LeaderFallbackSender,
+    packet_filter::FilterReason,
+    packet_quota::{PacketQuotas, QuotaTracker},
+    relayer::{
+        PacketSubscriptions, RelayerCounters, RelayerImpl, RelayerPacketBatches,
+        SubscriptionSession,
+    },
+    session_log::DisconnectReason,
+    slow_consumer::{ConsumerTransition, SlowConsumerPolicy},
+};
+
+/// Batches queued per worker before the dispatcher blocks
+const WORKER_QUEUE_CAPACITY: usize = 1_000;
+
+const METRICS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A packet batch ready to be fanned out, shared by every worker
+pub(crate) struct FanOutBatch {
+    packet_batches: RelayerPacketBatches,
+    /// (batch index, packet index) of the packets that passed the discard and OFAC checks
+    packet_indexes: Vec<(usize, usize)>,
+    encoded_batches: Vec<EncodedPacketBatch>,
+}
+
+impl FanOutBatch {
+    pub(crate) fn packets(&self) -> impl Iterator<Item = &Packet> {
+        let batches = &self.packet_batches.banking_packet_batch.0;
+        self.packet_indexes
+            .iter()
+            .map(|(batch_idx, packet_idx)| &batches[*batch_idx][*packet_idx])
+    }
+}
+
+#[derive(Default)]
+struct PacketForwardStats {
+    num_packets_forwarded: u64,
+    num_packets_dropped: u64,
+    num_packets_filtered_program: u64,
+    num_packets_filtered_account: u64,
+    num_packets_filtered_forwarded: u64,
+    num_packets_filtered_undecodable: u64,
+    max_queue_depth: usize,
+    max_batch_size: usize,
+    num_packets_over_quota: u64,
+    /// Packets per second the validator may be sent, 0 is unlimited
+    quota_pps: u64,
+}
+
+struct DispatcherMetrics {
+    num_batches: u64,
+    num_packets: u64,
+    packet_latencies_us: Histogram,
+    quic_to_sigverify_latencies_us: Histogram,
+    sigverify_to_release_latencies_us: Histogram,
+    dispatch_latencies_us: Histogram,
+    delay_packet_receiver_max_len: usize,
+    delay_packet_receiver_capacity: usize,
+    worker_queue_max_len: usize,
+}
+
+impl DispatcherMetrics {
+    fn new(delay_packet_receiver_capacity: usize) -> Self {
+        DispatcherMetrics {
+            num_batches: 0,
+            num_packets: 0,
+            packet_latencies_us: Histogram::default(),
+            quic_to_sigverify_latencies_us: Histogram::default(),
+            sigverify_to_release_latencies_us: Histogram::default(),
+            dispatch_latencies_us: Histogram::default(),
+            delay_packet_receiver_max_len: 0,
+            delay_packet_receiver_capacity,
+            worker_queue_max_len: 0,
+        }
+    }
+
+    fn report(&self) {
+        datapoint_info!(
+            "relayer_fan_out_dispatch_metrics",
+            ("num_batches", self.num_batches, i64),
+            ("num_packets", self.num_packets, i64),
+            (
+                "packet_latencies_us_min",
+                self.packet_latencies_us.minimum().unwrap_or_default(),
+                i64
+            ),
+            (
+                "packet_latencies_us_max",
+                self.packet_latencies_us.maximum().unwrap_or_default(),
+                i64
+            ),
+            (
+                "packet_latencies_us_p50",
+                self.packet_latencies_us
+                    .percentile(50.0)
+                    .unwrap_or_default(),
+                i64
+            ),
+            (
+                "packet_latencies_us_p90",
+                self.packet_latencies_us
+                    .percentile(90.0)
+                    .unwrap_or_default(),
+                i64
+            ),
+            (
+                "packet_latencies_us_p99",
+                self.packet_latencies_us
+                    .percentile(99.0)
+                    .unwrap_or_default(),
+                i64
+            ),
+            (
+                "quic_to_sigverify_latencies_us_p50",
+                self.quic_to_sigverify_latencies_us
+                    .percentile(50.0)
+                    .unwrap_or_default(),
+                i64
+            ),
+            (
+                "quic_to_sigverify_latencies_us_p90",
+                self.quic_to_sigverify_latencies_us
+                    .percentile(90.0)
+                    .unwrap_or_default(),
+                i64
+            ),
+            (
+                "quic_to_sigverify_latencies_us_p99",
+                self.quic_to_sigverify_latencies_us
+                    .percentile(99.0)
+                    .unwrap_or_default(),
+                i64
+            ),
+            (
+                "quic_to_sigverify_latencies_us_max",
+                self.quic_to_sigverify_latencies_us
+                    .maximum()
+                    .unwrap_or_default(),
+                i64
+            ),
+            (
+                "sigverify_to_release_latencies_us_p50",
+                self.sigverify_to_release_latencies_us
+                    .percentile(50.0)
+                    .unwrap_or_default(),
+                i64
+            ),
+            (
+                "sigverify_to_release_latencies_us_p90",
+                self.sigverify_to_release_latencies_us
+                    .percentile(90.0)
+                    .unwrap_or_default(),
+                i64
+            ),
+            (
+                "sigverify_to_release_latencies_us_p99",
+                self.sigverify_to_release_latencies_us
+                    .percentile(99.0)
+                    .unwrap_or_default(),
+                i64
+            ),
+            (
+                "sigverify_to_release_latencies_us_max",
+                self.sigverify_to_release_latencies_us
+                    .maximum()
+                    .unwrap_or_default(),
+                i64
+            ),
+            (
+                "dispatch_latencies_us_p50",
+                self.dispatch_latencies_us
+                    .percentile(50.0)
+                    .unwrap_or_default(),
+                i64
+            ),
+            (
+                "dispatch_latencies_us_p90",
+                self.dispatch_latencies_us
+                    .percentile(90.0)
+                    .unwrap_or_default(),
+                i64
+            ),
+            (
+                "dispatch_latencies_us_p99",
+                self.dispatch_latencies_us
+                    .percentile(99.0)
+                    .unwrap_or_default(),
+                i64
+            ),
+            (
+                "delay_packet_receiver_len",
+                self.delay_packet_receiver_max_len,
+                i64
+            ),
+            (
+                "delay_packet_receiver_capacity",
+                self.delay_packet_receiver_capacity,
+                i64
+            ),
+            ("worker_queue_len", self.worker_queue_max_len, i64),
+        );
+    }
+}
+
+struct WorkerMetrics {
+    num_batches: u64,
+    release_to_send_latencies_us: Histogram,
+    end_to_end_latencies_us: Histogram,
+    send_latencies_us: Histogram,
+    num_sessions_became_slow: u64,
+    num_sessions_recovered: u64,
+    num_sessions_evicted: u64,
+    packet_stats_per_validator: HashMap<Pubkey, PacketForwardStats>,
+}
+
+impl WorkerMetrics {
+    fn new() -> Self {
+        WorkerMetrics {
+            num_batches: 0,
+            release_to_send_latencies_us: Histogram::default(),
+            end_to_end_latencies_us: Histogram::default(),
+            send_latencies_us: Histogram::default(),
+            num_sessions_became_slow: 0,
+            num_sessions_recovered: 0,
+            num_sessions_evicted: 0,
+            packet_stats_per_validator: HashMap::new(),
+        }
+    }
+
+    fn increment_packets_forwarded(&mut self, validator_id: &Pubkey, num_packets: u64) {
+        let stats = self
+            .packet_stats_per_validator
+            .entry(*validator_id)
+            .or_default();
+        saturating_add_assign!(stats.num_packets_forwarded, num_packets);
+    }
+
+    fn increment_packets_dropped(&mut self, validator_id: &Pubkey, num_packets: u64) {
+        let stats = self
+            .packet_stats_per_validator
+            .entry(*validator_id)
+            .or_default();
+        saturating_add_assign!(stats.num_packets_dropped, num_packets);
+    }
+
+    fn update_queue_depth(&mut self, validator_id: &Pubkey, queue_depth: usize, batch_size: usize) {
+        let stats = self
+            .packet_stats_per_validator
+            .entry(*validator_id)
+            .or_default();
+        stats.max_queue_depth = stats.max_queue_depth.max(queue_depth);
+        stats.max_batch_size = stats.max_batch_size.max(batch_size);
+    }
+
+    fn increment_packets_over_quota(&mut self, validator_id: &Pubkey, num_packets: u64) {
+        let stats = self
+            .packet_stats_per_validator
+            .entry(*validator_id)
+            .or_default();
+        saturating_add_assign!(stats.num_packets_over_quota, num_packets);
+    }
+
+    fn update_quota(&mut self, validator_id: &Pubkey, quota_pps: u64) {
+        self.packet_stats_per_validator
+            .entry(*validator_id)
+            .or_default()
+            .quota_pps = quota_pps;
+    }
+
+    fn increment_packets_filtered(&mut self, validator_id: &Pubkey, reason: FilterReason) {
+        let stats = self
+            .packet_stats_per_validator
+            .entry(*validator_id)
+            .or_default();
+        match reason {
+            FilterReason::ExcludedProgram => {
+                saturating_add_assign!(stats.num_packets_filtered_program, 1)
+            }
+            FilterReason::ExcludedAccount => {
+                saturating_add_assign!(stats.num_packets_filtered_account, 1)
+            }
+            FilterReason::Forwarded => {
+                saturating_add_assign!(stats.num_packets_filtered_forwarded, 1)
+            }
+            FilterReason::Undecodable => {
+                saturating_add_assign!(stats.num_packets_filtered_undecodable, 1)
+            }
+        }
+    }
+
+    fn report(&self, shard: usize) {
+        datapoint_info!(
+            "relayer_fan_out_worker_metrics",
+            "shard" => shard.to_string(),
+            ("num_batches", self.num_batches, i64),
+            ("num_sessions_became_slow", self.num_sessions_became_slow, i64),
+            ("num_sessions_recovered", self.num_sessions_recovered, i64),
+            ("num_sessions_evicted", self.num_sessions_evicted, i64),
+            (
+                "release_to_send_latencies_us_p50",
+                self.release_to_send_latencies_us
+                    .percentile(50.0)
+                    .unwrap_or_default(),
+                i64
+            ),
+            (
+                "release_to_send_latencies_us_p90",
+                self.release_to_send_latencies_us
+                    .percentile(90.0)
+                    .unwrap_or_default(),
+                i64
+            ),
+            (
+                "release_to_send_latencies_us_p99",
+                self.release_to_send_latencies_us
+                    .percentile(99.0)
+                    .unwrap_or_default(),
+                i64
+            ),
+            (
+                "release_to_send_latencies_us_max",
+                self.release_to_send_latencies_us
+                    .maximum()
+                    .unwrap_or_default(),
+                i64
+            ),
+            (
+                "end_to_end_latencies_us_p50",
+                self.end_to_end_latencies_us
+                    .percentile(50.0)
+                    .unwrap_or_default(),
+                i64
+            ),
+            (
+                "end_to_end_latencies_us_p90",
+                self.end_to_end_latencies_us
+                    .percentile(90.0)
+                    .unwrap_or_default(),
+                i64
+            ),
+            (
+                "end_to_end_latencies_us_p99",
+                self.end_to_end_latencies_us
+                    .percentile(99.0)
+                    .unwrap_or_default(),
+                i64
+            ),
+            (
+                "end_to_end_latencies_us_max",
+                self.end_to_end_latencies_us.maximum().unwrap_or_default(),
+                i64
+            ),
+            (
+                "send_latencies_us_p50",
+                self.send_latencies_us.percentile(50.0).unwrap_or_default(),
+                i64
+            ),
+            (
+                "send_latencies_us_p90",
+                self.send_latencies_us.percentile(90.0).unwrap_or_default(),
+                i64
+            ),
+            (
+                "send_latencies_us_p99",
+                self.send_latencies_us.percentile(99.0).unwrap_or_default(),
+                i64
+            ),
+        );
+        for (pubkey, stats) in &self.packet_stats_per_validator {
+            datapoint_info!("relayer_validator_metrics",
+                "pubkey" => pubkey.to_string(),
+                ("num_packets_forwarded", stats.num_packets_forwarded, i64),
+                ("num_packets_dropped", stats.num_packets_dropped, i64),
+                (
+                    "num_packets_filtered_program",
+                    stats.num_packets_filtered_program,
+                    i64
+                ),
+                (
+                    "num_packets_filtered_account",
+                    stats.num_packets_filtered_account,
+                    i64
+                ),
+                (
+                    "num_packets_filtered_forwarded",
+                    stats.num_packets_filtered_forwarded,
+                    i64
+                ),
+                (
+                    "num_packets_filtered_undecodable",
+                    stats.num_packets_filtered_undecodable,
+                    i64
+                ),
+                ("max_queue_depth", stats.max_queue_depth, i64),
+                ("max_batch_size", stats.max_batch_size, i64),
+                ("num_packets_over_quota", stats.num_packets_over_quota, i64),
+                ("quota_pps", stats.quota_pps, i64),
+                (
+                    "quota_used_percent",
+                    if stats.quota_pps == 0 {
+                        0.0
+                    } else {
+                        stats.num_packets_forwarded as f64 * 100.0
+                            / METRICS_INTERVAL.as_secs_f64()
+                            / stats.quota_pps as f64
+                    },
+                    f64
+                ),
+            );
+        }
+    }
+}
+
+/// Returns the worker responsible for a validator identity
+fn shard_for(pubkey: &Pubkey, num_shards: usize) -> usize {
+    let bytes = pubkey.to_bytes();
+    u64::from_le_bytes(bytes[..8].try_into().unwrap()) as usize % num_shards
+}
+
+pub struct FanOutStage {
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl FanOutStage {
+    /// Sessions that fail to send are reported on failed_session_sender so the control thread can
+    /// drop them. If set, every batch is also queued on leader_fallback_sender for leaders that
+    /// aren't subscribed.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        delay_packet_receiver: Receiver<RelayerPacketBatches>,
+        packet_subscriptions: &PacketSubscriptions,
+        slot_leaders: &Arc<RwLock<HashSet<Pubkey>>>,
+        failed_session_sender: Sender<(Pubkey, u64, DisconnectReason)>,
+        counters: &Arc<RelayerCounters>,
+        ofac_addresses: HashSet<Pubkey>,
+        address_lookup_table_cache: Arc<DashMap<Pubkey, AddressLookupTableAccount>>,
+        validator_packet_batch_size: usize,
+        forward_all: bool,
+        slow_consumer_policy: SlowConsumerPolicy,
+        packet_quotas: PacketQuotas,
+        trace_tracer_packets: bool,
+        num_workers: usize,
+        shadow_mode: bool,
+        leader_fallback_sender: Option<LeaderFallbackSender>,
+        exit: &Arc<AtomicBool>,
+    ) -> FanOutStage {
+        let num_workers = num_workers.max(1);
+
+        let mut threads = Vec::with_capacity(num_workers + 1);
+        let mut worker_senders = Vec::with_capacity(num_workers);
+        for shard in 0..num_workers {
+            let (worker_sender, worker_receiver) = bounded(WORKER_QUEUE_CAPACITY);
+            worker_senders.push(worker_sender);
+
+            let packet_subscriptions = packet_subscriptions.clone();
+            let slot_leaders = slot_leaders.clone();
+            let failed_session_sender = failed_session_sender.clone();
+            let counters = counters.clone();
+            let address_lookup_table_cache = address_lookup_table_cache.clone();
+            let packet_quotas = packet_quotas.clone();
+            threads.push(
+                Builder::new()
+                    .name(format!("relayer_fan_out-{shard}"))
+                    .spawn(move || {
+                        Self::run_worker(
+                            shard,
+                            num_workers,
+                            worker_receiver,
+                            &packet_subscriptions,
+                            &slot_leaders,
+                            &failed_session_sender,
+                            &counters,
+                            &address_lookup_table_cache,
+                            validator_packet_batch_size,
+                            forward_all,
+                            slow_consumer_policy,
+                            packet_quotas,
+                            trace_tracer_packets,
+                            shadow_mode,
+                        )
+                    })
+                    .unwrap(),
+            );
+        }
+
+        let exit = exit.clone();
+        threads.push(
+            Builder::new()
+                .name("relayer_fan_out-dispatch".to_string())
+                .spawn(move || {
+                    Self::run_dispatcher(
+                        delay_packet_receiver,
+                        worker_senders,
+                        leader_fallback_sender,
+                        &ofac_addresses,
+                        &address_lookup_table_cache,
+                        validator_packet_batch_size,
+                        &exit,
+                    )
+                })
+                .unwrap(),
+        );
+
+        FanOutStage { threads }
+    }
+
+    fn run_dispatcher(
+        delay_packet_receiver: Receiver<RelayerPacketBatches>,
+        worker_senders: Vec<Sender<Arc<FanOutBatch>>>,
+        leader_fallback_sender: Option<LeaderFallbackSender>,
+        ofac_addresses: &HashSet<Pubkey>,
+        address_lookup_table_cache: &Arc<DashMap<Pubkey, AddressLookupTableAccount>>,
+        validator_packet_batch_size: usize,
+        exit: &Arc<AtomicBool>,
+    ) {
+        let delay_packet_receiver_capacity = delay_packet_receiver.capacity().unwrap_or_default();
+        let mut metrics = DispatcherMetrics::new(delay_packet_receiver_capacity);
+        let mut last_metrics_upload = Instant::now();
+
+        while !exit.load(Ordering::Relaxed) {
+            match delay_packet_receiver.recv_timeout(Duration::from_millis(100)) {
+                Ok(packet_batches) => {
+                    let start = Instant::now();
+                    let stamps = packet_batches.stamps;
+                    let _ = metrics
+                        .packet_latencies_us
+                        .increment(stamps.sigverified.elapsed().as_micros() as u64);
+                    if let Some(quic_received) = stamps.quic_received {
+                        let _ = metrics.quic_to_sigverify_latencies_us.increment(
+                            stamps.sigverified.duration_since(quic_received).as_micros() as u64,
+                        );
+                    }
+                    if let Some(released) = stamps.released {
+                        let _ = metrics.sigverify_to_release_latencies_us.increment(
+                            released.duration_since(stamps.sigverified).as_micros() as u64,
+                        );
+                    }
+
+                    let batch = Arc::new(Self::prepare_batch(
+                        packet_batches,
+                        ofac_addresses,
+                        address_lookup_table_cache,
+                        validator_packet_batch_size,
+                    ));
+                    metrics.num_batches += 1;
+                    metrics.num_packets += batch.packet_indexes.len() as u64;
+
+                    for worker_sender in &worker_senders {
+                        if worker_sender.send(batch.clone()).is_err() {
+                            warn!("fan-out worker exited, stopping dispatcher");
+                            return;
+                        }
+                        metrics.worker_queue_max_len =
+                            std::cmp::max(metrics.worker_queue_max_len, worker_sender.len());
+                    }
+                    if let Some(leader_fallback_sender) = &leader_fallback_sender {
+                        leader_fallback_sender.send(&batch);
+                    }
+                    let _ = metrics
+                        .dispatch_latencies_us
+                        .increment(start.elapsed().as_micros() as u64);
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            metrics.delay_packet_receiver_max_len = std::cmp::max(
+                metrics.delay_packet_receiver_max_len,
+                delay_packet_receiver.len(),
+            );
+
+            if last_metrics_upload.elapsed() >= METRICS_INTERVAL {
+                metrics.report();
+                metrics = DispatcherMetrics::new(delay_packet_receiver_capacity);
+                last_metrics_upload = Instant::now();
+            }
+        }
+    }
+
+    /// Removes discards and OFAC related packets, then encodes what's left once for every worker
+    fn prepare_batch(
+        packet_batches: RelayerPacketBatches,
+        ofac_addresses: &HashSet<Pubkey>,
+        address_lookup_table_cache: &Arc<DashMap<Pubkey, AddressLookupTableAccount>>,
+        validator_packet_batch_size: usize,
+    ) -> FanOutBatch {
+        let packet_indexes: Vec<(usize, usize)> = packet_batches
+            .banking_packet_batch
+            .0
+            .iter()
+            .enumerate()
+            .flat_map(|(batch_idx, batch)| {
+                batch
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, p)| !p.meta().discard())
+                    .filter(|(_, packet)| {
+                        if ofac_addresses.is_empty() {
+                            return true;
+                        }
+                        packet
+                            .deserialize_slice::<VersionedTransaction, _>(..)
+                            .is_ok_and(|tx| {
+                                !is_tx_ofac_related(&tx, ofac_addresses, address_lookup_table_cache)
+                            })
+                    })
+                    .map(move |(packet_idx, _)| (batch_idx, packet_idx))
+            })
+            .collect();
+
+        let mut batch = FanOutBatch {
+            packet_batches,
+            packet_indexes,
+            encoded_batches: Vec::new(),
+        };
+        batch.encoded_batches =
+            to_proto_packet_batches(batch.packets(), validator_packet_batch_size);
+        batch
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn run_worker(
+        shard: usize,
+        num_shards: usize,
+        worker_receiver: Receiver<Arc<FanOutBatch>>,
+        packet_subscriptions: &PacketSubscriptions,
+        slot_leaders: &Arc<RwLock<HashSet<Pubkey>>>,
+        failed_session_sender: &Sender<(Pubkey, u64, DisconnectReason)>,
+        counters: &RelayerCounters,
+        address_lookup_table_cache: &Arc<DashMap<Pubkey, AddressLookupTableAccount>>,
+        validator_packet_batch_size: usize,
+        forward_all: bool,
+        slow_consumer_policy: SlowConsumerPolicy,
+        packet_quotas: PacketQuotas,
+        trace_tracer_packets: bool,
+        shadow_mode: bool,
+    ) {
+        let mut metrics = WorkerMetrics::new();
+        let mut last_metrics_upload = Instant::now();
+        let mut quota_tracker = QuotaTracker::new(packet_quotas);
+        let mut round: usize = 0;
+
+        loop {
+            match worker_receiver.recv_timeout(Duration::from_millis(100)) {
+                Ok(batch) => {
+                    let start = Instant::now();
+                    let (num_sessions, failed_sessions) = Self::forward_packets(
+                        &batch,
+                        |pubkey| shard_for(pubkey, num_shards) == shard,
+                        packet_subscriptions,
+                        slot_leaders,
+                        &mut metrics,
+                        counters,
+                        address_lookup_table_cache,
+                        validator_packet_batch_size,
+                        forward_all,
+                        &slow_consumer_policy,
+                        &mut quota_tracker,
+                        round,
+                        shadow_mode,
+                    );
+                    round = round.wrapping_add(1);
+                    for failed_session in failed_sessions {
+                        if failed_session_sender.send(failed_session).is_err() {
+                            return;
+                        }
+                    }
+                    if trace_tracer_packets && num_sessions > 0 {
+                        report_tracer_packets(&batch.packet_batches, shard, num_sessions);
+                    }
+                    metrics.num_batches += 1;
+                    let _ = metrics
+                        .send_latencies_us
+                        .increment(start.elapsed().as_micros() as u64);
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+
+            if last_metrics_upload.elapsed() >= METRICS_INTERVAL {
+                metrics.report(shard);
+                metrics = WorkerMetrics::new();
+                quota_tracker.remove_idle(Instant::now());
+                last_metrics_upload = Instant::now();
+            }
+        }
+    }
+
+    /// Sends the batch to the active sessions of this worker's validators. Returns the number of
+    /// sessions sent to and the (pubkey, session id) of the sessions that failed to send.
+    ///
+    /// Sessions are visited in pubkey order starting one further along every round, so the time
+    /// spent encoding and queueing for the sessions before it is spread evenly instead of always
+    /// delaying the same validators.
+    #[allow(clippy::too_many_arguments)]
+    fn forward_packets(
+        batch: &FanOutBatch,
+        is_in_shard: impl Fn(&Pubkey) -> bool,
+        subscriptions: &PacketSubscriptions,
+        slot_leaders: &Arc<RwLock<HashSet<Pubkey>>>,
+        metrics: &mut WorkerMetrics,
+        counters: &RelayerCounters,
+        address_lookup_table_cache: &Arc<DashMap<Pubkey, AddressLookupTableAccount>>,
+        validator_packet_batch_size: usize,
+        forward_all: bool,
+        slow_consumer_policy: &SlowConsumerPolicy,
+        quota_tracker: &mut QuotaTracker,
+        round: usize,
+        shadow_mode: bool,
+    ) -> (usize, Vec<(Pubkey, u64, DisconnectReason)>) {
+        let stamps = batch.packet_batches.stamps;
+        let l_subscriptions = subscriptions.read().unwrap();
+
+        let mut senders: Vec<(&Pubkey, &SubscriptionSession)> = if forward_all {
+            l_subscriptions
+                .iter()
+                .filter(|(pubkey, _)| is_in_shard(pubkey))
+                .flat_map(|(pubkey, subscription)| {
+                    subscription
+                        .active_sessions()
+                        .iter()
+                        .map(move |session| (pubkey, session))
+                })
+                .collect()
+        } else {
+            let l_slot_leaders = slot_leaders.read().unwrap();
+            l_slot_leaders
+                .iter()
+                .filter(|pubkey| is_in_shard(pubkey))
+                .filter_map(|pubkey| l_subscriptions.get_key_value(pubkey))
+                .flat_map(|(pubkey, subscription)| {
+                    subscription
+                        .active_sessions()
+                        .iter()
+                        .map(move |session| (pubkey, session))
+                })
+                .collect()
+        };
+        senders.sort_by_key(|(pubkey, session)| (**pubkey, session.session_id));
+        if !senders.is_empty() {
+            let start = round % senders.len();
+            senders.rotate_left(start);
+        }
+
+        let now = Instant::now();
+        // unfiltered batches re-encoded at a larger batch size for lagging sessions
+        let mut resized_packet_batches: HashMap<usize, Vec<EncodedPacketBatch>> = HashMap::new();
+        let mut failed_forwards = Vec::new();
+        for (pubkey, session) in &senders {
+            let queue_depth = RelayerImpl::SUBSCRIBER_QUEUE_CAPACITY - session.sender.capacity();
+            let batch_size = slow_consumer_policy.batch_size(
+                validator_packet_batch_size,
+                queue_depth,
+                RelayerImpl::SUBSCRIBER_QUEUE_CAPACITY,
+            );
+            metrics.update_queue_depth(pubkey, queue_depth, batch_size);
+            if let Some(quota_pps) = quota_tracker.quota_for(pubkey) {
+                metrics.update_quota(pubkey, quota_pps);
+            }
+
+            let filtered_packet_batches;
+            let session_packet_batches = match &session.filter {
+                None if batch_size == validator_packet_batch_size => &batch.encoded_batches,
+                None => resized_packet_batches
+                    .entry(batch_size)
+                    .or_insert_with(|| to_proto_packet_batches(batch.packets(), batch_size)),
+                Some(filter) => {
+                    let session_packets = batch.packets().filter(|packet| {
+                        match filter.check(packet, address_lookup_table_cache) {
+                            None => true,
+                            Some(reason) => {
+                                counters
+                                    .num_packets_filtered
+                                    .fetch_add(1, Ordering::Relaxed);
+                                metrics.increment_packets_filtered(pubkey, reason);
+                                false
+                            }
+                        }
+                    });
+                    filtered_packet_batches = to_proto_packet_batches(session_packets, batch_size);
+                    &filtered_packet_batches
+                }
+            };
+
+            let mut is_channel_full = false;
+            let mut is_closed = false;
+            for encoded_batch in session_packet_batches {
+                // NOTE: this is important to avoid divide-by-0 inside the validator if packets
+                // get routed to sigverify under the assumption theres > 0 packets in the batch
+                if encoded_batch.num_packets() == 0 {
+                    continue;
+                }
+
+                let num_packets = encoded_batch.num_packets() as u64;
+                if !quota_tracker.try_take(pubkey, num_packets, now) {
+                    metrics.increment_packets_over_quota(pubkey, num_packets);
+                    continue;
+                }
+
+                // shadow mode runs everything up to the send, the stream only gets heartbeats
+                if shadow_mode {
+                    Self::record_forward(pubkey, session, encoded_batch, metrics, counters);
+                    continue;
+                }
+
+                // try send because it's a bounded channel and we don't want to block if the channel is full
+                match session.sender.try_send(Ok(SubscribePacketsResponse {
+                    header: Some(Header {
+                        ts: Some(Timestamp::from(SystemTime::now())),
+                    }),
+                    msg: Some(subscribe_packets_response::Msg::Batch(
+                        encoded_batch.clone(),
+                    )),
+                })) {
+                    Ok(_) => {
+                        let sent = Instant::now();
+                        if let Some(released) = stamps.released {
+                            let _ = metrics
+                                .release_to_send_latencies_us
+                                .increment(sent.duration_since(released).as_micros() as u64);
+                        }
+                        let _ = metrics.end_to_end_latencies_us.increment(
+                            sent.duration_since(stamps.quic_received.unwrap_or(stamps.sigverified))
+                                .as_micros() as u64,
+                        );
+                        Self::record_forward(pubkey, session, encoded_batch, metrics, counters);
+                    }
+                    Err(TrySendError::Full(_)) => {
+                        // the validator wasn't sent these, so they don't count against its quota
+                        quota_tracker.refund(pubkey, num_packets);
+                        is_channel_full = true;
+                        session
+                            .num_packets_dropped
+                            .fetch_add(num_packets, Ordering::Relaxed);
+                        counters
+                            .num_packets_dropped
+                            .fetch_add(num_packets, Ordering::Relaxed);
+                        metrics.increment_packets_dropped(pubkey, num_packets);
+                    }
+                    Err(TrySendError::Closed(_)) => {
+                        quota_tracker.refund(pubkey, num_packets);
+                        error!(
+                            "channel is closed for pubkey: {:?} session: {}",
+                            pubkey, session.session_id
+                        );
+                        failed_forwards.push((
+                            **pubkey,
+                            session.session_id,
+                            DisconnectReason::Closed,
+                        ));
+                        is_closed = true;
+                        break;
+                    }
+                }
+            }
+            if is_closed {
+                continue;
+            }
+
+            let is_slow = is_channel_full
+                || slow_consumer_policy
+                    .is_slow(queue_depth, RelayerImpl::SUBSCRIBER_QUEUE_CAPACITY);
+            match session
+                .consumer_state
+                .update(slow_consumer_policy, is_slow, now)
+            {
+                ConsumerTransition::None => {}
+                ConsumerTransition::BecameSlow => {
+                    warn!(
+                        "subscriber is falling behind, pubkey: {:?} session: {} queue depth: {}",
+                        pubkey, session.session_id, queue_depth
+                    );
+                    metrics.num_sessions_became_slow += 1;
+                }
+                ConsumerTransition::Recovered => {
+                    info!(
+                        "subscriber caught up, pubkey: {:?} session: {}",
+                        pubkey, session.session_id
+                    );
+                    metrics.num_sessions_recovered += 1;
+                }
+                ConsumerTransition::Evict => {
+                    error!(
+                        "evicting slow subscriber, pubkey: {:?} session: {} queue depth: {}",
+                        pubkey, session.session_id, queue_depth
+                    );
+                    metrics.num_sessions_evicted += 1;
+                    failed_forwards.push((
+                        **pubkey,
+                        session.session_id,
+                        DisconnectReason::ChannelFull,
+                    ));
+                }
+            }
+        }
+
+        (senders.len(), failed_forwards)
+    }
+
+    fn record_forward(
+        pubkey: &Pubkey,
+        session: &SubscriptionSession,
+        encoded_batch: &EncodedPacketBatch,
+        metrics: &mut WorkerMetrics,
+        counters: &RelayerCounters,
+    ) {
+        let num_packets = encoded_batch.num_packets() as u64;
+        session
+            .num_packets_forwarded
+            .fetch_add(num_packets, Ordering::Relaxed);
+        counters
+            .num_batches_forwarded
+            .fetch_add(1, Ordering::Relaxed);
+        counters
+            .num_packets_forwarded
+            .fetch_add(num_packets, Ordering::Relaxed);
+        metrics.increment_packets_forwarded(pubkey, num_packets);
+    }
+
+    pub fn join(self) -> thread::Result<()> {
+        for t in self.threads {
+            t.join()?;
+        }
+        Ok(())
+    }
+}
+
+/// Encodes packets into protobuf batches of up to validator_packet_batch_size packets.
+/// Batches are encoded once here and shared by every subscriber they're sent to.
+fn to_proto_packet_batches<'a>(
+    packets: impl Iterator<Item = &'a Packet>,
+    validator_packet_batch_size: usize,
+) -> Vec<EncodedPacketBatch> {
+    let mut packets = packets.filter_map(packet_to_proto_packet).peekable();
+    let mut proto_packet_batches = Vec::new();
+    while packets.peek().is_some() {
+        let batch = ProtoPacketBatch {
+            packets: packets.by_ref().take(validator_packet_batch_size).collect(),
+        };
+        proto_packet_batches.push(EncodedPacketBatch::encode(&batch));
+    }
+    proto_packet_batches
+}
+
+fn report_tracer_packets(
+    packet_batches: &RelayerPacketBatches,
+    shard: usize,
+    num_subscribers: usize,
+) {
+    let stamps = &packet_batches.stamps;
+    let now = Instant::now();
+    for tracer_packet in &packet_batches.tracer_packets {
+        datapoint_info!(
+            "relayer-tracer_packet",
+            ("signature", tracer_packet.signature.to_string(), String),
+            (
+                "quic_to_sigverify_us",
+                tracer_packet
+                    .quic_received
+                    .map(|t| stamps.sigverified.duration_since(t).as_micros() as i64),
+                Option<i64>
+            ),
+            (
+                "sigverify_to_release_us",
+                stamps
+                    .released
+                    .map(|t| t.duration_since(stamps.sigverified).as_micros() as i64),
+                Option<i64>
+            ),
+            (
+                "release_to_send_us",
+                stamps
+                    .released
+                    .map(|t| now.duration_since(t).as_micros() as i64),
+                Option<i64>
+            ),
+            (
+                "end_to_end_us",
+                now.duration_since(tracer_packet.quic_received.unwrap_or(stamps.sigverified))
+                    .as_micros() as i64,
+                i64
+            ),
+            ("shard", shard, i64),
+            ("num_subscribers", num_subscribers, i64),
+        );
+    }
+}
@@ -12,10 +12,80 @@ use crossbeam_channel::{select, tick, Receiver, Sender};
 use solana_metrics::datapoint_info;
 use solana_sdk::clock::Slot;
 
-#[derive(PartialEq, Eq, Copy, Clone)]
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
 pub enum HealthState {
     Unhealthy = 0,
     Healthy = 1,
+    /// Slots stopped arriving or only just resumed. Existing streams are kept, but new validators
+    /// are refused.
+    Degraded = 2,
+}
+
+impl HealthState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HealthState::Unhealthy => "unhealthy",
+            HealthState::Healthy => "healthy",
+            HealthState::Degraded => "degraded",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct HealthPolicy {
+    /// Slots missing for this long make the relayer degraded
+    pub missing_slot_threshold: Duration,
+    /// How long the relayer may stay degraded before becoming unhealthy
+    pub degraded_grace_period: Duration,
+    /// How long slots must arrive on time before the relayer is healthy again
+    pub recovery_period: Duration,
+}
+
+/// Moves between health states on slot freshness, with a grace period before becoming unhealthy
+/// and a recovery period before becoming healthy again
+struct HealthTracker {
+    policy: HealthPolicy,
+    state: HealthState,
+    stale_since: Option<Instant>,
+    fresh_since: Option<Instant>,
+}
+
+impl HealthTracker {
+    fn new(policy: HealthPolicy) -> Self {
+        HealthTracker {
+            policy,
+            state: HealthState::Unhealthy,
+            stale_since: None,
+            fresh_since: None,
+        }
+    }
+
+    fn update(&mut self, last_slot_update: Instant, now: Instant) -> HealthState {
+        let is_fresh =
+            now.saturating_duration_since(last_slot_update) <= self.policy.missing_slot_threshold;
+        if is_fresh {
+            self.stale_since = None;
+            let fresh_since = *self.fresh_since.get_or_insert(now);
+            self.state = if self.state == HealthState::Healthy
+                || now.saturating_duration_since(fresh_since) >= self.policy.recovery_period
+            {
+                HealthState::Healthy
+            } else {
+                HealthState::Degraded
+            };
+        } else {
+            self.fresh_since = None;
+            let stale_since = *self.stale_since.get_or_insert(now);
+            self.state = if self.state == HealthState::Unhealthy
+                || now.saturating_duration_since(stale_since) >= self.policy.degraded_grace_period
+            {
+                HealthState::Unhealthy
+            } else {
+                HealthState::Degraded
+            };
+        }
+        self.state
+    }
 }
 
 pub struct HealthManager {
@@ -29,7 +99,7 @@ impl HealthManager {
     pub fn new(
         slot_receiver: Receiver<Slot>,
         slot_sender: Sender<Slot>,
-        missing_slot_unhealthy_threshold: Duration,
+        health_policy: HealthPolicy,
         exit: Arc<AtomicBool>,
     ) -> HealthManager {
         let health_state = Arc::new(RwLock::new(HealthState::Unhealthy));
@@ -41,16 +111,14 @@ impl HealthManager {
                     let mut last_update = Instant::now();
                     let mut slot_sender_max_len = 0usize;
                     let channel_len_tick = tick(Duration::from_secs(5));
-                    let check_and_metrics_tick = tick(missing_slot_unhealthy_threshold / 2);
+                    let check_and_metrics_tick = tick(health_policy.missing_slot_threshold / 2);
+                    let mut health_tracker = HealthTracker::new(health_policy);
 
                     while !exit.load(Ordering::Relaxed) {
                         select! {
                             recv(check_and_metrics_tick) -> _ => {
                                 let new_health_state =
-                                    match last_update.elapsed() <= missing_slot_unhealthy_threshold {
-                                        true => HealthState::Healthy,
-                                        false => HealthState::Unhealthy,
-                                    };
+                                    health_tracker.update(last_update, Instant::now());
                                 *health_state.write().unwrap() = new_health_state;
                                 datapoint_info!(
                                     "relayer-health-state",
@@ -87,3 +155,37 @@ impl HealthManager {
         self.manager_thread.join()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::health_manager::{HealthPolicy, HealthState, HealthTracker};
+
+    #[test]
+    fn test_health_tracker() {
+        let mut tracker = HealthTracker::new(HealthPolicy {
+            missing_slot_threshold: Duration::from_secs(2),
+            degraded_grace_period: Duration::from_secs(5),
+            recovery_period: Duration::from_secs(3),
+        });
+        let start = Instant::now();
+        let secs = |s| start + Duration::from_secs(s);
+
+        // slots arriving from startup still have to last through the recovery period
+        assert_eq!(tracker.update(secs(0), secs(0)), HealthState::Degraded);
+        assert_eq!(tracker.update(secs(3), secs(3)), HealthState::Healthy);
+
+        // a short gap only degrades
+        assert_eq!(tracker.update(secs(3), secs(6)), HealthState::Degraded);
+        assert_eq!(tracker.update(secs(3), secs(10)), HealthState::Degraded);
+        assert_eq!(tracker.update(secs(11), secs(11)), HealthState::Degraded);
+        assert_eq!(tracker.update(secs(14), secs(14)), HealthState::Healthy);
+
+        // a gap past the grace period is unhealthy, and recovers through degraded
+        assert_eq!(tracker.update(secs(14), secs(17)), HealthState::Degraded);
+        assert_eq!(tracker.update(secs(14), secs(22)), HealthState::Unhealthy);
+        assert_eq!(tracker.update(secs(23), secs(23)), HealthState::Degraded);
+        assert_eq!(tracker.update(secs(26), secs(26)), HealthState::Healthy);
+    }
+}
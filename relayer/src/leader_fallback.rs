@@ -0,0 +1,291 @@
+//! Sends packets straight to the TPU QUIC port of upcoming leaders that have no packet
+//! subscription to this relayer, which would otherwise never see them. Connections are signed with
+//! the relayer's identity so leaders treat them as staked. Leader contact info is refreshed from
+//! RPC getClusterNodes.
+
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    thread,
+    thread::{sleep, Builder, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crossbeam_channel::{bounded, Receiver, RecvTimeoutError, Sender, TrySendError};
+use jito_rpc::load_balancer::LoadBalancer;
+use log::*;
+use solana_client::connection_cache::ConnectionCache;
+use solana_connection_cache::client_connection::ClientConnection;
+use solana_metrics::datapoint_info;
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+
+use crate::{fan_out::FanOutBatch, relayer::PacketSubscriptions};
+
+/// Batches queued for the fallback sender before new ones are dropped
+const LEADER_FALLBACK_QUEUE_CAPACITY: usize = 1_000;
+
+const CONTACT_INFO_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+const METRICS_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct LeaderFallbackConfig {
+    /// Identity used for the QUIC client certificate, so leaders apply the relayer's stake
+    pub keypair: Arc<Keypair>,
+    pub rpc_load_balancer: Arc<LoadBalancer>,
+    /// Leaders that never get packets directly, even when they aren't subscribed
+    pub opt_out: HashSet<Pubkey>,
+}
+
+/// Queues batches for the fallback sender without blocking the caller
+#[derive(Clone)]
+pub(crate) struct LeaderFallbackSender {
+    sender: Sender<Arc<FanOutBatch>>,
+    num_batches_dropped: Arc<AtomicU64>,
+}
+
+impl LeaderFallbackSender {
+    pub(crate) fn send(&self, batch: &Arc<FanOutBatch>) {
+        if let Err(TrySendError::Full(_)) = self.sender.try_send(batch.clone()) {
+            self.num_batches_dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[derive(Default)]
+struct LeaderFallbackMetrics {
+    num_batches: u64,
+    num_packets_sent: u64,
+    num_send_errors: u64,
+    /// Leader sends skipped because the leader is on the opt-out list
+    num_leaders_opted_out: u64,
+    /// Leader sends skipped because the leader has no known TPU QUIC address
+    num_leaders_missing_contact_info: u64,
+    num_leaders_sent: u64,
+    queue_max_len: usize,
+}
+
+impl LeaderFallbackMetrics {
+    fn report(&self, num_batches_dropped: u64) {
+        datapoint_info!(
+            "relayer_leader_fallback_metrics",
+            ("num_batches", self.num_batches, i64),
+            ("num_batches_dropped", num_batches_dropped, i64),
+            ("num_packets_sent", self.num_packets_sent, i64),
+            ("num_send_errors", self.num_send_errors, i64),
+            ("num_leaders_opted_out", self.num_leaders_opted_out, i64),
+            (
+                "num_leaders_missing_contact_info",
+                self.num_leaders_missing_contact_info,
+                i64
+            ),
+            ("num_leaders_sent", self.num_leaders_sent, i64),
+            ("queue_max_len", self.queue_max_len, i64),
+        );
+    }
+}
+
+pub(crate) struct LeaderFallbackStage {
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl LeaderFallbackStage {
+    pub(crate) fn new(
+        config: LeaderFallbackConfig,
+        public_ip: IpAddr,
+        packet_subscriptions: &PacketSubscriptions,
+        slot_leaders: &Arc<RwLock<HashSet<Pubkey>>>,
+        exit: &Arc<AtomicBool>,
+    ) -> (LeaderFallbackStage, LeaderFallbackSender) {
+        let (sender, receiver) = bounded(LEADER_FALLBACK_QUEUE_CAPACITY);
+        let num_batches_dropped = Arc::new(AtomicU64::new(0));
+        let contact_infos = Arc::new(RwLock::new(HashMap::new()));
+
+        let refresh_thread = {
+            let contact_infos = contact_infos.clone();
+            let rpc_load_balancer = config.rpc_load_balancer.clone();
+            let exit = exit.clone();
+            Builder::new()
+                .name("relayer_leader_fallback-contact_info".to_string())
+                .spawn(move || {
+                    let mut last_refresh: Option<Instant> = None;
+                    while !exit.load(Ordering::Relaxed) {
+                        if last_refresh
+                            .map_or(true, |t| t.elapsed() >= CONTACT_INFO_REFRESH_INTERVAL)
+                        {
+                            Self::refresh_contact_infos(&rpc_load_balancer, &contact_infos);
+                            last_refresh = Some(Instant::now());
+                        }
+                        sleep(Duration::from_millis(100));
+                    }
+                })
+                .unwrap()
+        };
+
+        let connection_cache = ConnectionCache::new_with_client_options(
+            "relayer_leader_fallback",
+            1,
+            None,
+            Some((&config.keypair, public_ip)),
+            None,
+        );
+        let send_thread = {
+            let packet_subscriptions = packet_subscriptions.clone();
+            let slot_leaders = slot_leaders.clone();
+            let num_batches_dropped = num_batches_dropped.clone();
+            let identity = config.keypair.pubkey();
+            let opt_out = config.opt_out;
+            let exit = exit.clone();
+            Builder::new()
+                .name("relayer_leader_fallback-send".to_string())
+                .spawn(move || {
+                    Self::run_sender(
+                        receiver,
+                        &connection_cache,
+                        &contact_infos,
+                        &packet_subscriptions,
+                        &slot_leaders,
+                        &identity,
+                        &opt_out,
+                        &num_batches_dropped,
+                        &exit,
+                    )
+                })
+                .unwrap()
+        };
+
+        (
+            LeaderFallbackStage {
+                threads: vec![refresh_thread, send_thread],
+            },
+            LeaderFallbackSender {
+                sender,
+                num_batches_dropped,
+            },
+        )
+    }
+
+    fn refresh_contact_infos(
+        rpc_load_balancer: &Arc<LoadBalancer>,
+        contact_infos: &Arc<RwLock<HashMap<Pubkey, SocketAddr>>>,
+    ) {
+        match rpc_load_balancer.rpc_client().get_cluster_nodes() {
+            Ok(nodes) => {
+                let new_contact_infos: HashMap<Pubkey, SocketAddr> = nodes
+                    .into_iter()
+                    .filter_map(|node| Some((Pubkey::from_str(&node.pubkey).ok()?, node.tpu_quic?)))
+                    .collect();
+                datapoint_info!(
+                    "relayer_leader_fallback_contact_info",
+                    ("update_ok_count", 1, i64),
+                    ("num_contact_infos", new_contact_infos.len(), i64),
+                );
+                *contact_infos.write().unwrap() = new_contact_infos;
+            }
+            Err(e) => {
+                error!("error fetching cluster nodes for leader fallback: {e}");
+                datapoint_info!(
+                    "relayer_leader_fallback_contact_info",
+                    ("update_fail_count", 1, i64),
+                );
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn run_sender(
+        receiver: Receiver<Arc<FanOutBatch>>,
+        connection_cache: &ConnectionCache,
+        contact_infos: &Arc<RwLock<HashMap<Pubkey, SocketAddr>>>,
+        packet_subscriptions: &PacketSubscriptions,
+        slot_leaders: &Arc<RwLock<HashSet<Pubkey>>>,
+        identity: &Pubkey,
+        opt_out: &HashSet<Pubkey>,
+        num_batches_dropped: &Arc<AtomicU64>,
+        exit: &Arc<AtomicBool>,
+    ) {
+        let mut metrics = LeaderFallbackMetrics::default();
+        let mut last_metrics_upload = Instant::now();
+
+        while !exit.load(Ordering::Relaxed) {
+            match receiver.recv_timeout(Duration::from_millis(100)) {
+                Ok(batch) => {
+                    metrics.num_batches += 1;
+                    metrics.queue_max_len = std::cmp::max(metrics.queue_max_len, receiver.len());
+
+                    let unsubscribed_leaders: Vec<Pubkey> = {
+                        let l_subscriptions = packet_subscriptions.read().unwrap();
+                        slot_leaders
+                            .read()
+                            .unwrap()
+                            .iter()
+                            .filter(|leader| {
+                                *leader != identity && !l_subscriptions.contains_key(*leader)
+                            })
+                            .copied()
+                            .collect()
+                    };
+                    if unsubscribed_leaders.is_empty() {
+                        continue;
+                    }
+
+                    let wire_transactions: Vec<Vec<u8>> = batch
+                        .packets()
+                        .filter_map(|packet| packet.data(..).map(<[u8]>::to_vec))
+                        .collect();
+                    if wire_transactions.is_empty() {
+                        continue;
+                    }
+
+                    let l_contact_infos = contact_infos.read().unwrap();
+                    for leader in unsubscribed_leaders {
+                        if opt_out.contains(&leader) {
+                            metrics.num_leaders_opted_out += 1;
+                            continue;
+                        }
+                        let Some(tpu_quic) = l_contact_infos.get(&leader) else {
+                            metrics.num_leaders_missing_contact_info += 1;
+                            continue;
+                        };
+
+                        metrics.num_leaders_sent += 1;
+                        match connection_cache
+                            .get_connection(tpu_quic)
+                            .send_data_batch_async(wire_transactions.clone())
+                        {
+                            Ok(_) => metrics.num_packets_sent += wire_transactions.len() as u64,
+                            Err(e) => {
+                                metrics.num_send_errors += 1;
+                                debug!(
+                                    "error sending packets to leader {leader} at {tpu_quic}: {e}"
+                                );
+                            }
+                        }
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+
+            if last_metrics_upload.elapsed() >= METRICS_INTERVAL {
+                metrics.report(num_batches_dropped.swap(0, Ordering::Relaxed));
+                metrics = LeaderFallbackMetrics::default();
+                last_metrics_upload = Instant::now();
+            }
+        }
+    }
+
+    pub(crate) fn join(self) -> thread::Result<()> {
+        for t in self.threads {
+            t.join()?;
+        }
+        Ok(())
+    }
+}
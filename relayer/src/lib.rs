@@ -1,6 +1,14 @@
 mod auth_challenges;
 pub mod auth_interceptor;
 pub mod auth_service;
+pub mod blockhash_cache;
+mod fan_out;
 pub mod health_manager;
+pub mod leader_fallback;
+pub mod packet_filter;
+pub mod packet_quota;
 pub mod relayer;
 pub mod schedule_cache;
+pub mod session_log;
+pub mod slow_consumer;
+pub mod tpu_port_assigner;
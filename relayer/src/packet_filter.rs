@@ -0,0 +1,265 @@
+//! Per-subscription packet filters. Filters are configured by the relayer operator per validator
+//! identity or sent by the validator in the subscribe request metadata, and only ever narrow the
+//! stream a session receives.
+
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
+
+use dashmap::DashMap;
+use serde::Deserialize;
+use solana_sdk::{
+    address_lookup_table::AddressLookupTableAccount, packet::Packet, pubkey::Pubkey,
+    transaction::VersionedTransaction,
+};
+use tonic::metadata::MetadataMap;
+
+/// Comma separated program ids to exclude
+pub const EXCLUDE_PROGRAMS_METADATA_KEY: &str = "x-exclude-programs";
+/// Comma separated account keys to exclude
+pub const EXCLUDE_ACCOUNTS_METADATA_KEY: &str = "x-exclude-accounts";
+/// Set to true to only receive packets that weren't forwarded by another validator
+pub const NON_FORWARDED_ONLY_METADATA_KEY: &str = "x-non-forwarded-only";
+
+/// Filter settings as written in the operator's filter file, keyed on validator identity
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct PacketFilterConfig {
+    #[serde(default)]
+    pub exclude_programs: Vec<String>,
+    #[serde(default)]
+    pub exclude_accounts: Vec<String>,
+    #[serde(default)]
+    pub non_forwarded_only: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PacketFilter {
+    exclude_programs: HashSet<Pubkey>,
+    exclude_accounts: HashSet<Pubkey>,
+    non_forwarded_only: bool,
+}
+
+/// Reason a packet was filtered out, used for per-filter drop counters
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FilterReason {
+    ExcludedProgram,
+    ExcludedAccount,
+    Forwarded,
+    /// Couldn't be deserialized to check the program and account exclusions
+    Undecodable,
+}
+
+fn parse_pubkeys<'a>(pubkeys: impl Iterator<Item = &'a str>) -> Result<HashSet<Pubkey>, String> {
+    pubkeys
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(|p| Pubkey::from_str(p).map_err(|e| format!("invalid pubkey {p}: {e}")))
+        .collect()
+}
+
+impl TryFrom<&PacketFilterConfig> for PacketFilter {
+    type Error = String;
+
+    fn try_from(config: &PacketFilterConfig) -> Result<Self, Self::Error> {
+        Ok(PacketFilter {
+            exclude_programs: parse_pubkeys(config.exclude_programs.iter().map(String::as_str))?,
+            exclude_accounts: parse_pubkeys(config.exclude_accounts.iter().map(String::as_str))?,
+            non_forwarded_only: config.non_forwarded_only,
+        })
+    }
+}
+
+impl PacketFilter {
+    /// Parses operator filter configs keyed on validator identity
+    pub fn from_configs(
+        configs: &HashMap<String, PacketFilterConfig>,
+    ) -> Result<HashMap<Pubkey, PacketFilter>, String> {
+        configs
+            .iter()
+            .map(|(pubkey, config)| {
+                let pubkey = Pubkey::from_str(pubkey)
+                    .map_err(|e| format!("invalid pubkey {pubkey}: {e}"))?;
+                Ok((pubkey, PacketFilter::try_from(config)?))
+            })
+            .collect()
+    }
+
+    /// Parses a filter out of subscribe request metadata, returns None if no filter was requested
+    pub fn from_metadata(metadata: &MetadataMap) -> Result<Option<PacketFilter>, String> {
+        let get = |key: &str| -> Result<Option<&str>, String> {
+            metadata
+                .get(key)
+                .map(|v| v.to_str().map_err(|e| format!("invalid {key}: {e}")))
+                .transpose()
+        };
+
+        let exclude_programs = parse_pubkeys(
+            get(EXCLUDE_PROGRAMS_METADATA_KEY)?
+                .unwrap_or_default()
+                .split(','),
+        )?;
+        let exclude_accounts = parse_pubkeys(
+            get(EXCLUDE_ACCOUNTS_METADATA_KEY)?
+                .unwrap_or_default()
+                .split(','),
+        )?;
+        let non_forwarded_only = match get(NON_FORWARDED_ONLY_METADATA_KEY)? {
+            None => false,
+            Some(v) => bool::from_str(v)
+                .map_err(|e| format!("invalid {NON_FORWARDED_ONLY_METADATA_KEY}: {e}"))?,
+        };
+
+        let filter = PacketFilter {
+            exclude_programs,
+            exclude_accounts,
+            non_forwarded_only,
+        };
+        Ok((!filter.is_empty()).then_some(filter))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self == &PacketFilter::default()
+    }
+
+    /// Combines two filters, a packet passes the result only if it passes both
+    pub fn merge(mut self, other: PacketFilter) -> PacketFilter {
+        self.exclude_programs.extend(other.exclude_programs);
+        self.exclude_accounts.extend(other.exclude_accounts);
+        self.non_forwarded_only |= other.non_forwarded_only;
+        self
+    }
+
+    /// Returns the reason the packet should be dropped, or None if it passes the filter
+    pub fn check(
+        &self,
+        packet: &Packet,
+        address_lookup_table_cache: &DashMap<Pubkey, AddressLookupTableAccount>,
+    ) -> Option<FilterReason> {
+        if self.non_forwarded_only && packet.meta().forwarded() {
+            return Some(FilterReason::Forwarded);
+        }
+        if self.exclude_programs.is_empty() && self.exclude_accounts.is_empty() {
+            return None;
+        }
+
+        // drop what can't be inspected rather than let an excluded transaction through
+        let Ok(tx) = packet.deserialize_slice::<VersionedTransaction, _>(..) else {
+            return Some(FilterReason::Undecodable);
+        };
+        let static_keys = tx.message.static_account_keys();
+
+        if !self.exclude_programs.is_empty()
+            && tx.message.instructions().iter().any(|ix| {
+                static_keys
+                    .get(ix.program_id_index as usize)
+                    .is_some_and(|program_id| self.exclude_programs.contains(program_id))
+            })
+        {
+            return Some(FilterReason::ExcludedProgram);
+        }
+
+        if !self.exclude_accounts.is_empty() {
+            if static_keys
+                .iter()
+                .any(|key| self.exclude_accounts.contains(key))
+            {
+                return Some(FilterReason::ExcludedAccount);
+            }
+            for table in tx.message.address_table_lookups().unwrap_or_default() {
+                let Some(lookup_info) = address_lookup_table_cache.get(&table.account_key) else {
+                    continue;
+                };
+                if table
+                    .writable_indexes
+                    .iter()
+                    .chain(table.readonly_indexes.iter())
+                    .filter_map(|idx| lookup_info.addresses.get(*idx as usize))
+                    .any(|account| self.exclude_accounts.contains(account))
+                {
+                    return Some(FilterReason::ExcludedAccount);
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dashmap::DashMap;
+    use solana_sdk::{
+        hash::Hash,
+        instruction::{AccountMeta, Instruction},
+        packet::{Packet, PacketFlags},
+        pubkey::Pubkey,
+        signature::{Keypair, Signer},
+        transaction::{Transaction, VersionedTransaction},
+    };
+    use tonic::metadata::MetadataMap;
+
+    use crate::packet_filter::{
+        FilterReason, PacketFilter, EXCLUDE_ACCOUNTS_METADATA_KEY, EXCLUDE_PROGRAMS_METADATA_KEY,
+        NON_FORWARDED_ONLY_METADATA_KEY,
+    };
+
+    fn packet(program_id: Pubkey, account: Pubkey) -> Packet {
+        let payer = Keypair::new();
+        let tx = Transaction::new_signed_with_payer(
+            &[Instruction::new_with_bytes(
+                program_id,
+                &[],
+                vec![AccountMeta::new(account, false)],
+            )],
+            Some(&payer.pubkey()),
+            &[&payer],
+            Hash::default(),
+        );
+        Packet::from_data(None, VersionedTransaction::from(tx)).unwrap()
+    }
+
+    #[test]
+    fn test_packet_filter() {
+        let lookup_tables = DashMap::new();
+        let (program_id, account) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+        let mut metadata = MetadataMap::new();
+        assert_eq!(PacketFilter::from_metadata(&metadata), Ok(None));
+
+        metadata.insert(
+            EXCLUDE_PROGRAMS_METADATA_KEY,
+            program_id.to_string().parse().unwrap(),
+        );
+        let filter = PacketFilter::from_metadata(&metadata).unwrap().unwrap();
+        assert_eq!(
+            filter.check(&packet(program_id, account), &lookup_tables),
+            Some(FilterReason::ExcludedProgram)
+        );
+        assert_eq!(
+            filter.check(&packet(Pubkey::new_unique(), account), &lookup_tables),
+            None
+        );
+
+        metadata.insert(
+            EXCLUDE_ACCOUNTS_METADATA_KEY,
+            account.to_string().parse().unwrap(),
+        );
+        metadata.insert(NON_FORWARDED_ONLY_METADATA_KEY, "true".parse().unwrap());
+        let filter = PacketFilter::from_metadata(&metadata).unwrap().unwrap();
+        let mut forwarded = packet(Pubkey::new_unique(), Pubkey::new_unique());
+        assert_eq!(filter.check(&forwarded, &lookup_tables), None);
+        forwarded.meta_mut().flags.insert(PacketFlags::FORWARDED);
+        assert_eq!(
+            filter.check(&forwarded, &lookup_tables),
+            Some(FilterReason::Forwarded)
+        );
+        assert_eq!(
+            filter.check(&packet(Pubkey::new_unique(), account), &lookup_tables),
+            Some(FilterReason::ExcludedAccount)
+        );
+        assert_eq!(
+            filter.check(&Packet::from_data(None, [0u8; 8]).unwrap(), &lookup_tables),
+            Some(FilterReason::Undecodable)
+        );
+    }
+}
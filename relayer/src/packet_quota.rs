@@ -0,0 +1,154 @@
+//! Per-validator packet rate quotas. Each validator identity gets a token bucket refilled at its
+//! quota in packets per second, holding up to one second of packets. Packets sent to any of the
+//! validator's sessions draw from the same bucket.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use solana_sdk::pubkey::Pubkey;
+
+#[derive(Clone, Debug, Default)]
+pub struct PacketQuotas {
+    /// Packets per second for validators without an override, None is unlimited
+    pub default_pps: Option<u64>,
+    pub overrides: HashMap<Pubkey, u64>,
+}
+
+impl PacketQuotas {
+    pub fn quota_for(&self, pubkey: &Pubkey) -> Option<u64> {
+        self.overrides.get(pubkey).copied().or(self.default_pps)
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    pps: u64,
+    /// May go negative when a batch larger than the bucket is let through, the debt is repaid
+    /// by later refills
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(pps: u64, now: Instant) -> Self {
+        TokenBucket {
+            pps,
+            tokens: pps as f64,
+            last_refill: now,
+        }
+    }
+
+    fn try_take(&mut self, num_packets: u64, now: Instant) -> bool {
+        let capacity = self.pps as f64;
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * capacity).min(capacity);
+        self.last_refill = now;
+
+        // a batch larger than the bucket goes through once the bucket is full
+        if self.tokens >= (num_packets as f64).min(capacity) {
+            self.tokens -= num_packets as f64;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn refund(&mut self, num_packets: u64) {
+        self.tokens = (self.tokens + num_packets as f64).min(self.pps as f64);
+    }
+}
+
+/// Tracks quota usage of the validators served by one fan-out worker
+#[derive(Debug)]
+pub struct QuotaTracker {
+    quotas: PacketQuotas,
+    buckets: HashMap<Pubkey, TokenBucket>,
+}
+
+impl QuotaTracker {
+    /// Buckets of validators not seen for this long are dropped
+    const IDLE_BUCKET_TTL: Duration = Duration::from_secs(60);
+
+    pub fn new(quotas: PacketQuotas) -> Self {
+        QuotaTracker {
+            quotas,
+            buckets: HashMap::new(),
+        }
+    }
+
+    pub fn quota_for(&self, pubkey: &Pubkey) -> Option<u64> {
+        self.quotas.quota_for(pubkey)
+    }
+
+    /// Returns whether num_packets can be sent to the validator, taking them from its quota
+    pub fn try_take(&mut self, pubkey: &Pubkey, num_packets: u64, now: Instant) -> bool {
+        let Some(pps) = self.quotas.quota_for(pubkey) else {
+            return true;
+        };
+        self.buckets
+            .entry(*pubkey)
+            .or_insert_with(|| TokenBucket::new(pps, now))
+            .try_take(num_packets, now)
+    }
+
+    /// Gives back packets taken from the validator's quota that couldn't be sent after all
+    pub fn refund(&mut self, pubkey: &Pubkey, num_packets: u64) {
+        if let Some(bucket) = self.buckets.get_mut(pubkey) {
+            bucket.refund(num_packets);
+        }
+    }
+
+    pub fn remove_idle(&mut self, now: Instant) {
+        self.buckets.retain(|_, bucket| {
+            now.saturating_duration_since(bucket.last_refill) < Self::IDLE_BUCKET_TTL
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        time::{Duration, Instant},
+    };
+
+    use solana_sdk::pubkey::Pubkey;
+
+    use crate::packet_quota::{PacketQuotas, QuotaTracker};
+
+    #[test]
+    fn test_quota_tracker() {
+        let limited = Pubkey::new_unique();
+        let other = Pubkey::new_unique();
+        let mut tracker = QuotaTracker::new(PacketQuotas {
+            default_pps: None,
+            overrides: HashMap::from([(limited, 100)]),
+        });
+        let start = Instant::now();
+
+        assert!(tracker.try_take(&limited, 60, start));
+        assert!(!tracker.try_take(&limited, 60, start));
+        // validators without a quota are never limited
+        assert!(tracker.try_take(&other, 1_000_000, start));
+
+        // refills at 100 packets per second
+        assert!(!tracker.try_take(&limited, 60, start + Duration::from_millis(100)));
+        assert!(tracker.try_take(&limited, 60, start + Duration::from_millis(300)));
+
+        // a batch over the quota goes through once the bucket is full, then has to be paid off
+        assert!(tracker.try_take(&limited, 150, start + Duration::from_secs(2)));
+        assert!(!tracker.try_take(&limited, 1, start + Duration::from_millis(2_400)));
+        assert!(tracker.try_take(&limited, 1, start + Duration::from_millis(2_600)));
+
+        // refunded packets can be taken again, up to the bucket size
+        let later = start + Duration::from_secs(10);
+        assert!(tracker.try_take(&limited, 80, later));
+        assert!(!tracker.try_take(&limited, 80, later));
+        tracker.refund(&limited, 80);
+        tracker.refund(&limited, 1_000);
+        assert!(tracker.try_take(&limited, 100, later));
+        assert!(!tracker.try_take(&limited, 1, later));
+    }
+}
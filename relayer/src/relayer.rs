@@ -1,6 +1,7 @@
 use std::{
     collections::{hash_map::Entry, HashMap, HashSet},
     net::IpAddr,
+    str::FromStr,
     sync::{
         atomic::{AtomicBool, AtomicU64, Ordering},
         Arc, RwLock,
@@ -10,40 +11,40 @@ use std::{
     time::{Duration, Instant, SystemTime},
 };
 
-use crossbeam_channel::{bounded, Receiver, RecvError, Sender};
+use crossbeam_channel::{bounded, unbounded, Receiver, RecvError, Sender};
 use dashmap::DashMap;
 use histogram::Histogram;
-use jito_core::ofac::is_tx_ofac_related;
 use jito_protos::{
-    convert::packet_to_proto_packet,
-    packet::PacketBatch as ProtoPacketBatch,
     relayer::{
         relayer_server::Relayer, subscribe_packets_response, GetTpuConfigsRequest,
         GetTpuConfigsResponse, SubscribePacketsRequest, SubscribePacketsResponse,
     },
-    shared::{Header, Heartbeat, Socket},
+    shared::{Heartbeat, Socket},
 };
 use jito_rpc::load_balancer::LoadBalancer;
 use log::*;
-use prost_types::Timestamp;
 use solana_core::banking_trace::BankingPacketBatch;
 use solana_metrics::datapoint_info;
 use solana_sdk::{
     address_lookup_table::AddressLookupTableAccount, clock::Slot, pubkey::Pubkey,
-    saturating_add_assign, transaction::VersionedTransaction,
+    signature::Signature,
 };
 use thiserror::Error;
 use tokio::sync::mpsc::{channel, error::TrySendError, Sender as TokioSender};
 use tokio_stream::wrappers::ReceiverStream;
-use tonic::{Request, Response, Status};
-
-use crate::{health_manager::HealthState, schedule_cache::LeaderScheduleUpdatingHandle};
-
-#[derive(Default)]
-struct PacketForwardStats {
-    num_packets_forwarded: u64,
-    num_packets_dropped: u64,
-}
+use tonic::{metadata::MetadataValue, Request, Response, Status};
+
+use crate::{
+    fan_out::FanOutStage,
+    health_manager::HealthState,
+    leader_fallback::{LeaderFallbackConfig, LeaderFallbackStage},
+    packet_filter::PacketFilter,
+    packet_quota::PacketQuotas,
+    schedule_cache::LeaderScheduleUpdatingHandle,
+    session_log::{unix_ms, DisconnectReason, SessionLog, SessionRecord},
+    slow_consumer::{ConsumerState, SlowConsumerPolicy},
+    tpu_port_assigner::TpuPortAssigner,
+};
 
 struct RelayerMetrics {
     pub highest_slot: u64,
@@ -54,31 +55,22 @@ struct RelayerMetrics {
     pub max_heartbeat_tick_latency_us: u64,
     pub metrics_latency_us: u64,
     pub num_try_send_channel_full: u64,
-    pub packet_latencies_us: Histogram,
-
     pub crossbeam_slot_receiver_processing_us: Histogram,
-    pub crossbeam_delay_packet_receiver_processing_us: Histogram,
     pub crossbeam_subscription_receiver_processing_us: Histogram,
     pub crossbeam_heartbeat_tick_processing_us: Histogram,
     pub crossbeam_metrics_tick_processing_us: Histogram,
+    pub crossbeam_failed_session_receiver_processing_us: Histogram,
 
     // channel stats
     pub slot_receiver_max_len: usize,
     pub slot_receiver_capacity: usize,
     pub subscription_receiver_max_len: usize,
     pub subscription_receiver_capacity: usize,
-    pub delay_packet_receiver_max_len: usize,
-    pub delay_packet_receiver_capacity: usize,
     pub packet_subscriptions_total_queued: usize, // sum of all items currently queued
-    packet_stats_per_validator: HashMap<Pubkey, PacketForwardStats>,
 }
 
 impl RelayerMetrics {
-    fn new(
-        slot_receiver_capacity: usize,
-        subscription_receiver_capacity: usize,
-        delay_packet_receiver_capacity: usize,
-    ) -> Self {
+    fn new(slot_receiver_capacity: usize, subscription_receiver_capacity: usize) -> Self {
         RelayerMetrics {
             highest_slot: 0,
             num_added_connections: 0,
@@ -88,82 +80,40 @@ impl RelayerMetrics {
             max_heartbeat_tick_latency_us: 0,
             metrics_latency_us: 0,
             num_try_send_channel_full: 0,
-            packet_latencies_us: Histogram::default(),
             crossbeam_slot_receiver_processing_us: Histogram::default(),
-            crossbeam_delay_packet_receiver_processing_us: Histogram::default(),
             crossbeam_subscription_receiver_processing_us: Histogram::default(),
             crossbeam_heartbeat_tick_processing_us: Histogram::default(),
             crossbeam_metrics_tick_processing_us: Histogram::default(),
+            crossbeam_failed_session_receiver_processing_us: Histogram::default(),
             slot_receiver_max_len: 0,
             slot_receiver_capacity,
             subscription_receiver_max_len: 0,
             subscription_receiver_capacity,
-            delay_packet_receiver_max_len: 0,
-            delay_packet_receiver_capacity,
             packet_subscriptions_total_queued: 0,
-            packet_stats_per_validator: HashMap::new(),
         }
     }
 
-    fn update_max_len(
-        &mut self,
-        slot_receiver_len: usize,
-        subscription_receiver_len: usize,
-        delay_packet_receiver_len: usize,
-    ) {
+    fn update_max_len(&mut self, slot_receiver_len: usize, subscription_receiver_len: usize) {
         self.slot_receiver_max_len = std::cmp::max(self.slot_receiver_max_len, slot_receiver_len);
         self.subscription_receiver_max_len = std::cmp::max(
             self.subscription_receiver_max_len,
             subscription_receiver_len,
         );
-        self.delay_packet_receiver_max_len = std::cmp::max(
-            self.delay_packet_receiver_max_len,
-            delay_packet_receiver_len,
-        );
     }
 
     fn update_packet_subscription_total_capacity(
         &mut self,
-        packet_subscriptions: &HashMap<
-            Pubkey,
-            TokioSender<Result<SubscribePacketsResponse, Status>>,
-        >,
+        packet_subscriptions: &HashMap<Pubkey, ValidatorSubscription>,
     ) {
         let packet_subscriptions_total_queued = packet_subscriptions
             .values()
-            .map(|x| RelayerImpl::SUBSCRIBER_QUEUE_CAPACITY - x.capacity())
+            .flat_map(|subscription| subscription.sessions.iter())
+            .map(|session| RelayerImpl::SUBSCRIBER_QUEUE_CAPACITY - session.sender.capacity())
             .sum::<usize>();
         self.packet_subscriptions_total_queued = packet_subscriptions_total_queued;
     }
 
-    fn increment_packets_forwarded(&mut self, validator_id: &Pubkey, num_packets: u64) {
-        self.packet_stats_per_validator
-            .entry(*validator_id)
-            .and_modify(|entry| saturating_add_assign!(entry.num_packets_forwarded, num_packets))
-            .or_insert(PacketForwardStats {
-                num_packets_forwarded: num_packets,
-                num_packets_dropped: 0,
-            });
-    }
-
-    fn increment_packets_dropped(&mut self, validator_id: &Pubkey, num_packets: u64) {
-        self.packet_stats_per_validator
-            .entry(*validator_id)
-            .and_modify(|entry| saturating_add_assign!(entry.num_packets_dropped, num_packets))
-            .or_insert(PacketForwardStats {
-                num_packets_forwarded: 0,
-                num_packets_dropped: num_packets,
-            });
-    }
-
     fn report(&self) {
-        for (pubkey, stats) in &self.packet_stats_per_validator {
-            datapoint_info!("relayer_validator_metrics",
-                "pubkey" => pubkey.to_string(),
-                ("num_packets_forwarded", stats.num_packets_forwarded, i64),
-                ("num_packets_dropped", stats.num_packets_dropped, i64),
-            );
-        }
         datapoint_info!(
             "relayer_metrics",
             ("highest_slot", self.highest_slot, i64),
@@ -182,38 +132,6 @@ impl RelayerMetrics {
                 self.max_heartbeat_tick_latency_us,
                 i64
             ),
-            // packet latencies
-            (
-                "packet_latencies_us_min",
-                self.packet_latencies_us.minimum().unwrap_or_default(),
-                i64
-            ),
-            (
-                "packet_latencies_us_max",
-                self.packet_latencies_us.maximum().unwrap_or_default(),
-                i64
-            ),
-            (
-                "packet_latencies_us_p50",
-                self.packet_latencies_us
-                    .percentile(50.0)
-                    .unwrap_or_default(),
-                i64
-            ),
-            (
-                "packet_latencies_us_p90",
-                self.packet_latencies_us
-                    .percentile(90.0)
-                    .unwrap_or_default(),
-                i64
-            ),
-            (
-                "packet_latencies_us_p99",
-                self.packet_latencies_us
-                    .percentile(99.0)
-                    .unwrap_or_default(),
-                i64
-            ),
             // crossbeam arm latencies
             (
                 "crossbeam_subscription_receiver_processing_us_p50",
@@ -279,22 +197,22 @@ impl RelayerMetrics {
                 i64
             ),
             (
-                "crossbeam_delay_packet_receiver_processing_us_p50",
-                self.crossbeam_delay_packet_receiver_processing_us
+                "crossbeam_failed_session_receiver_processing_us_p50",
+                self.crossbeam_failed_session_receiver_processing_us
                     .percentile(50.0)
                     .unwrap_or_default(),
                 i64
             ),
             (
-                "crossbeam_delay_packet_receiver_processing_us_p90",
-                self.crossbeam_delay_packet_receiver_processing_us
+                "crossbeam_failed_session_receiver_processing_us_p90",
+                self.crossbeam_failed_session_receiver_processing_us
                     .percentile(90.0)
                     .unwrap_or_default(),
                 i64
             ),
             (
-                "crossbeam_delay_packet_receiver_processing_us_p99",
-                self.crossbeam_delay_packet_receiver_processing_us
+                "crossbeam_failed_session_receiver_processing_us_p99",
+                self.crossbeam_failed_session_receiver_processing_us
                     .percentile(99.0)
                     .unwrap_or_default(),
                 i64
@@ -333,16 +251,6 @@ impl RelayerMetrics {
                 self.subscription_receiver_capacity,
                 i64
             ),
-            (
-                "delay_packet_receiver_len",
-                self.delay_packet_receiver_max_len,
-                i64
-            ),
-            (
-                "delay_packet_receiver_capacity",
-                self.delay_packet_receiver_capacity,
-                i64
-            ),
             (
                 "packet_subscriptions_total_queued",
                 self.packet_subscriptions_total_queued,
@@ -352,18 +260,127 @@ impl RelayerMetrics {
     }
 }
 
+/// Timestamps taken as a batch moves through the pipeline
+#[derive(Clone, Copy, Debug)]
+pub struct PacketBatchStamps {
+    /// Oldest QUIC receive time found for the batch's packets. Best effort since sigverify may
+    /// dedup and compact batches.
+    pub quic_received: Option<Instant>,
+    /// When the forwarder received the batch from sigverify
+    pub sigverified: Instant,
+    /// When the forwarder released the batch to the relayer after the packet delay
+    pub released: Option<Instant>,
+}
+
+/// A packet flagged as a tracer packet by the sender, traced individually through the pipeline
+#[derive(Clone, Debug)]
+pub struct TracerPacket {
+    pub signature: Signature,
+    pub quic_received: Option<Instant>,
+}
+
 pub struct RelayerPacketBatches {
-    pub stamp: Instant,
+    pub stamps: PacketBatchStamps,
     pub banking_packet_batch: BankingPacketBatch,
+    pub tracer_packets: Vec<TracerPacket>,
 }
 
 pub enum Subscription {
     ValidatorPacketSubscription {
         pubkey: Pubkey,
+        session_id: u64,
         sender: TokioSender<Result<SubscribePacketsResponse, Status>>,
+        remote_ip: Option<IpAddr>,
+        filter: Option<PacketFilter>,
     },
 }
 
+/// How packets are fanned out to the sessions of a validator identity that subscribed more than
+/// once, e.g. a hot-standby setup sharing an identity.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionPolicy {
+    /// Forward packets to every session
+    All,
+    /// Forward packets to the most recent session, older sessions only get heartbeats
+    NewestOnly,
+    /// Forward packets to the oldest session, newer sessions are backups that take over once it
+    /// disconnects
+    PrimaryBackup,
+}
+
+impl FromStr for SessionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(SessionPolicy::All),
+            "newest-only" => Ok(SessionPolicy::NewestOnly),
+            "primary-backup" => Ok(SessionPolicy::PrimaryBackup),
+            _ => Err(format!(
+                "invalid session policy {s}, expected one of: all, newest-only, primary-backup"
+            )),
+        }
+    }
+}
+
+pub struct SubscriptionSession {
+    pub session_id: u64,
+    pub sender: TokioSender<Result<SubscribePacketsResponse, Status>>,
+    pub connected_at: SystemTime,
+    pub remote_ip: Option<IpAddr>,
+    pub filter: Option<PacketFilter>,
+    pub num_packets_forwarded: AtomicU64,
+    pub num_packets_dropped: AtomicU64,
+    pub consumer_state: ConsumerState,
+}
+
+impl SubscriptionSession {
+    fn to_record(&self, pubkey: &Pubkey, reason: DisconnectReason) -> SessionRecord {
+        SessionRecord {
+            pubkey: pubkey.to_string(),
+            session_id: self.session_id,
+            remote_ip: self.remote_ip,
+            connected_at_unix_ms: unix_ms(self.connected_at),
+            disconnected_at_unix_ms: unix_ms(SystemTime::now()),
+            disconnect_reason: reason,
+            num_packets_forwarded: self.num_packets_forwarded.load(Ordering::Relaxed),
+            num_packets_dropped: self.num_packets_dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The sessions of a validator identity, ordered from oldest to newest
+pub struct ValidatorSubscription {
+    /// Identities without a session policy only keep their newest session
+    policy: Option<SessionPolicy>,
+    sessions: Vec<SubscriptionSession>,
+}
+
+impl ValidatorSubscription {
+    /// Returns the sessions packets are forwarded to
+    pub(crate) fn active_sessions(&self) -> &[SubscriptionSession] {
+        let num_sessions = self.sessions.len();
+        match self.policy {
+            None | Some(SessionPolicy::All) => &self.sessions,
+            Some(SessionPolicy::NewestOnly) => &self.sessions[num_sessions.saturating_sub(1)..],
+            Some(SessionPolicy::PrimaryBackup) => &self.sessions[..num_sessions.min(1)],
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SessionInfo {
+    pub pubkey: Pubkey,
+    pub session_id: u64,
+    pub connected_at: SystemTime,
+    /// Whether packets are currently forwarded to this session
+    pub is_active: bool,
+    /// Whether the session is falling behind on its packet stream
+    pub is_slow: bool,
+    /// Number of messages queued for the session
+    pub queue_depth: usize,
+}
+
 #[derive(Error, Debug)]
 pub enum RelayerError {
     #[error("shutdown")]
@@ -372,19 +389,74 @@ pub enum RelayerError {
 
 pub type RelayerResult<T> = Result<T, RelayerError>;
 
-type PacketSubscriptions =
-    Arc<RwLock<HashMap<Pubkey, TokioSender<Result<SubscribePacketsResponse, Status>>>>>;
+pub(crate) type PacketSubscriptions = Arc<RwLock<HashMap<Pubkey, ValidatorSubscription>>>;
+
+/// Cumulative packet counters since startup. In shadow mode, forwarded counts what would have
+/// been sent.
+#[derive(Debug, Default)]
+pub struct RelayerCounters {
+    pub num_batches_forwarded: AtomicU64,
+    pub num_packets_forwarded: AtomicU64,
+    pub num_packets_dropped: AtomicU64,
+    pub num_packets_filtered: AtomicU64,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RelayerCountersSnapshot {
+    pub num_batches_forwarded: u64,
+    pub num_packets_forwarded: u64,
+    pub num_packets_dropped: u64,
+    pub num_packets_filtered: u64,
+}
+
+impl RelayerCounters {
+    pub fn snapshot(&self) -> RelayerCountersSnapshot {
+        RelayerCountersSnapshot {
+            num_batches_forwarded: self.num_batches_forwarded.load(Ordering::Relaxed),
+            num_packets_forwarded: self.num_packets_forwarded.load(Ordering::Relaxed),
+            num_packets_dropped: self.num_packets_dropped.load(Ordering::Relaxed),
+            num_packets_filtered: self.num_packets_filtered.load(Ordering::Relaxed),
+        }
+    }
+}
+
 pub struct RelayerHandle {
     packet_subscriptions: PacketSubscriptions,
+    session_log: SessionLog,
+    counters: Arc<RelayerCounters>,
+    shadow_mode: bool,
 }
 
 impl RelayerHandle {
-    pub fn new(packet_subscriptions: &PacketSubscriptions) -> RelayerHandle {
+    pub fn new(
+        packet_subscriptions: &PacketSubscriptions,
+        session_log: &SessionLog,
+        counters: &Arc<RelayerCounters>,
+        shadow_mode: bool,
+    ) -> RelayerHandle {
         RelayerHandle {
             packet_subscriptions: packet_subscriptions.clone(),
+            session_log: session_log.clone(),
+            counters: counters.clone(),
+            shadow_mode,
         }
     }
 
+    pub fn counters(&self) -> RelayerCountersSnapshot {
+        self.counters.snapshot()
+    }
+
+    /// In shadow mode validators only get heartbeats, packets are counted but never sent
+    pub fn is_shadow_mode(&self) -> bool {
+        self.shadow_mode
+    }
+
+    /// Returns up to limit ended sessions, newest first
+    pub fn session_history(&self, pubkey: Option<&Pubkey>, limit: usize) -> Vec<SessionRecord> {
+        self.session_log
+            .history(pubkey.map(|p| p.to_string()).as_deref(), limit)
+    }
+
     pub fn connected_validators(&self) -> Vec<Pubkey> {
         self.packet_subscriptions
             .read()
@@ -393,20 +465,51 @@ impl RelayerHandle {
             .cloned()
             .collect()
     }
+
+    pub fn sessions(&self) -> Vec<SessionInfo> {
+        let l_subscriptions = self.packet_subscriptions.read().unwrap();
+        let mut sessions = Vec::new();
+        for (pubkey, subscription) in l_subscriptions.iter() {
+            let active_sessions = subscription.active_sessions();
+            for session in &subscription.sessions {
+                sessions.push(SessionInfo {
+                    pubkey: *pubkey,
+                    session_id: session.session_id,
+                    connected_at: session.connected_at,
+                    is_active: active_sessions
+                        .iter()
+                        .any(|s| s.session_id == session.session_id),
+                    is_slow: session.consumer_state.is_slow(),
+                    queue_depth: RelayerImpl::SUBSCRIBER_QUEUE_CAPACITY - session.sender.capacity(),
+                });
+            }
+        }
+        sessions
+    }
 }
 
 pub struct RelayerImpl {
-    tpu_quic_ports: Vec<u16>,
-    tpu_fwd_quic_ports: Vec<u16>,
+    tpu_port_assigner: TpuPortAssigner,
     public_ip: IpAddr,
-    seq: AtomicU64,
+    next_session_id: AtomicU64,
+    packet_filters: HashMap<Pubkey, PacketFilter>,
 
     subscription_sender: Sender<Subscription>,
     threads: Vec<JoinHandle<()>>,
+    fan_out_stage: FanOutStage,
+    leader_fallback_stage: Option<LeaderFallbackStage>,
     health_state: Arc<RwLock<HealthState>>,
     packet_subscriptions: PacketSubscriptions,
+    session_log: SessionLog,
+    counters: Arc<RelayerCounters>,
+    shadow_mode: bool,
 }
 
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Response metadata key carrying the id of a packet subscription session
+pub const SESSION_ID_METADATA_KEY: &str = "x-session-id";
+
 impl RelayerImpl {
     pub const SUBSCRIBER_QUEUE_CAPACITY: usize = 50_000;
 
@@ -416,8 +519,7 @@ impl RelayerImpl {
         delay_packet_receiver: Receiver<RelayerPacketBatches>,
         leader_schedule_cache: LeaderScheduleUpdatingHandle,
         public_ip: IpAddr,
-        tpu_quic_ports: Vec<u16>,
-        tpu_fwd_quic_ports: Vec<u16>,
+        tpu_port_assigner: TpuPortAssigner,
         health_state: Arc<RwLock<HealthState>>,
         exit: Arc<AtomicBool>,
         ofac_addresses: HashSet<Pubkey>,
@@ -425,32 +527,83 @@ impl RelayerImpl {
         validator_packet_batch_size: usize,
         forward_all: bool,
         slot_lookahead: u64,
+        slot_lookahead_overrides: HashMap<Pubkey, u64>,
+        trace_tracer_packets: bool,
+        session_policies: HashMap<Pubkey, SessionPolicy>,
+        packet_filters: HashMap<Pubkey, PacketFilter>,
+        num_fan_out_workers: usize,
+        session_log: SessionLog,
+        slow_consumer_policy: SlowConsumerPolicy,
+        packet_quotas: PacketQuotas,
+        shadow_mode: bool,
+        leader_fallback: Option<LeaderFallbackConfig>,
+        unhealthy_disconnect_spread: Duration,
     ) -> Self {
         // receiver tracked as relayer_metrics.subscription_receiver_len
         let (subscription_sender, subscription_receiver) =
             bounded(LoadBalancer::SLOT_QUEUE_CAPACITY);
+        let (failed_session_sender, failed_session_receiver) = unbounded();
 
         let packet_subscriptions = Arc::new(RwLock::new(HashMap::default()));
+        let slot_leaders = Arc::new(RwLock::new(HashSet::new()));
+        let counters = Arc::new(RelayerCounters::default());
+
+        if shadow_mode && leader_fallback.is_some() {
+            warn!("leader fallback is disabled in shadow mode");
+        }
+        let (leader_fallback_stage, leader_fallback_sender) = leader_fallback
+            .filter(|_| !shadow_mode)
+            .map(|config| {
+                LeaderFallbackStage::new(
+                    config,
+                    public_ip,
+                    &packet_subscriptions,
+                    &slot_leaders,
+                    &exit,
+                )
+            })
+            .unzip();
+
+        let fan_out_stage = FanOutStage::new(
+            delay_packet_receiver,
+            &packet_subscriptions,
+            &slot_leaders,
+            failed_session_sender,
+            &counters,
+            ofac_addresses,
+            address_lookup_table_cache,
+            validator_packet_batch_size,
+            forward_all,
+            slow_consumer_policy,
+            packet_quotas,
+            trace_tracer_packets,
+            num_fan_out_workers,
+            shadow_mode,
+            leader_fallback_sender,
+            &exit,
+        );
 
         let thread = {
             let health_state = health_state.clone();
             let packet_subscriptions = packet_subscriptions.clone();
+            let session_log = session_log.clone();
             thread::Builder::new()
                 .name("relayer_impl-event_loop_thread".to_string())
                 .spawn(move || {
                     let res = Self::run_event_loop(
                         slot_receiver,
                         subscription_receiver,
-                        delay_packet_receiver,
+                        failed_session_receiver,
                         leader_schedule_cache,
                         slot_lookahead,
+                        &slot_lookahead_overrides,
                         health_state,
                         exit,
                         &packet_subscriptions,
-                        ofac_addresses,
-                        address_lookup_table_cache,
-                        validator_packet_batch_size,
-                        forward_all,
+                        &slot_leaders,
+                        session_policies,
+                        &session_log,
+                        unhealthy_disconnect_spread,
                     );
                     warn!("RelayerImpl thread exited with result {res:?}")
                 })
@@ -458,49 +611,60 @@ impl RelayerImpl {
         };
 
         Self {
-            tpu_quic_ports,
-            tpu_fwd_quic_ports,
+            tpu_port_assigner,
             subscription_sender,
             public_ip,
             threads: vec![thread],
+            fan_out_stage,
+            leader_fallback_stage,
             health_state,
             packet_subscriptions,
-            seq: AtomicU64::new(0),
+            next_session_id: AtomicU64::new(0),
+            packet_filters,
+            session_log,
+            counters,
+            shadow_mode,
         }
     }
 
     pub fn handle(&self) -> RelayerHandle {
-        RelayerHandle::new(&self.packet_subscriptions)
+        RelayerHandle::new(
+            &self.packet_subscriptions,
+            &self.session_log,
+            &self.counters,
+            self.shadow_mode,
+        )
     }
 
     #[allow(clippy::too_many_arguments)]
     fn run_event_loop(
         slot_receiver: Receiver<Slot>,
         subscription_receiver: Receiver<Subscription>,
-        delay_packet_receiver: Receiver<RelayerPacketBatches>,
+        failed_session_receiver: Receiver<(Pubkey, u64, DisconnectReason)>,
         leader_schedule_cache: LeaderScheduleUpdatingHandle,
         slot_lookahead: u64,
+        slot_lookahead_overrides: &HashMap<Pubkey, u64>,
         health_state: Arc<RwLock<HealthState>>,
         exit: Arc<AtomicBool>,
         packet_subscriptions: &PacketSubscriptions,
-        ofac_addresses: HashSet<Pubkey>,
-        address_lookup_table_cache: Arc<DashMap<Pubkey, AddressLookupTableAccount>>,
-        validator_packet_batch_size: usize,
-        forward_all: bool,
+        slot_leaders: &Arc<RwLock<HashSet<Pubkey>>>,
+        session_policies: HashMap<Pubkey, SessionPolicy>,
+        session_log: &SessionLog,
+        unhealthy_disconnect_spread: Duration,
     ) -> RelayerResult<()> {
         let mut highest_slot = Slot::default();
+        // set while unhealthy, sessions dropped per heartbeat so they're spread over
+        // unhealthy_disconnect_spread
+        let mut unhealthy_disconnects_per_tick: Option<usize> = None;
 
-        let heartbeat_tick = crossbeam_channel::tick(Duration::from_millis(500));
+        let heartbeat_tick = crossbeam_channel::tick(HEARTBEAT_INTERVAL);
         let metrics_tick = crossbeam_channel::tick(Duration::from_millis(1000));
 
         let mut relayer_metrics = RelayerMetrics::new(
             slot_receiver.capacity().unwrap(),
             subscription_receiver.capacity().unwrap(),
-            delay_packet_receiver.capacity().unwrap(),
         );
 
-        let mut slot_leaders = HashSet::new();
-
         while !exit.load(Ordering::Relaxed) {
             crossbeam_channel::select! {
                 recv(slot_receiver) -> maybe_slot => {
@@ -508,20 +672,19 @@ impl RelayerImpl {
 
                     Self::update_highest_slot(maybe_slot, &mut highest_slot, &mut relayer_metrics)?;
 
-                    let slots: Vec<_> = (highest_slot..highest_slot + slot_lookahead).collect();
-                    slot_leaders = leader_schedule_cache.leaders_for_slots(&slots);
+                    *slot_leaders.write().unwrap() = leader_schedule_cache.leaders_within_lookahead(highest_slot, slot_lookahead, slot_lookahead_overrides);
 
                     let _ = relayer_metrics.crossbeam_slot_receiver_processing_us.increment(start.elapsed().as_micros() as u64);
                 },
-                recv(delay_packet_receiver) -> maybe_packet_batches => {
+                recv(failed_session_receiver) -> maybe_failed_session => {
                     let start = Instant::now();
-                    let failed_forwards = Self::forward_packets(maybe_packet_batches, packet_subscriptions, &slot_leaders, &mut relayer_metrics, &ofac_addresses, &address_lookup_table_cache, validator_packet_batch_size, forward_all)?;
-                    Self::drop_connections(failed_forwards, packet_subscriptions, &mut relayer_metrics);
-                    let _ = relayer_metrics.crossbeam_delay_packet_receiver_processing_us.increment(start.elapsed().as_micros() as u64);
+                    let (pubkey, session_id, reason) = maybe_failed_session?;
+                    Self::drop_connections(vec![(pubkey, session_id)], reason, packet_subscriptions, session_log, &mut relayer_metrics);
+                    let _ = relayer_metrics.crossbeam_failed_session_receiver_processing_us.increment(start.elapsed().as_micros() as u64);
                 },
                 recv(subscription_receiver) -> maybe_subscription => {
                     let start = Instant::now();
-                    Self::handle_subscription(maybe_subscription, packet_subscriptions, &mut relayer_metrics)?;
+                    Self::handle_subscription(maybe_subscription, packet_subscriptions, &session_policies, session_log, &mut relayer_metrics)?;
                     let _ = relayer_metrics.crossbeam_subscription_receiver_processing_us.increment(start.elapsed().as_micros() as u64);
                 }
                 recv(heartbeat_tick) -> time_generated => {
@@ -530,23 +693,26 @@ impl RelayerImpl {
                         relayer_metrics.max_heartbeat_tick_latency_us = std::cmp::max(relayer_metrics.max_heartbeat_tick_latency_us, Instant::now().duration_since(time_generated).as_micros() as u64);
                     }
 
-                    // heartbeat if state is healthy, drop all connections on unhealthy
-                    let pubkeys_to_drop = match *health_state.read().unwrap() {
-                        HealthState::Healthy => {
-                            Self::handle_heartbeat(
-                                packet_subscriptions,
-                                &mut relayer_metrics,
-                            )
-                        },
-                        HealthState::Unhealthy => packet_subscriptions.read().unwrap().keys().cloned().collect(),
-                    };
-                    Self::drop_connections(pubkeys_to_drop, packet_subscriptions, &mut relayer_metrics);
+                    // streams are kept while healthy or degraded. on unhealthy, sessions are dropped a
+                    // batch at a time so validators don't all reconnect at once
+                    if *health_state.read().unwrap() == HealthState::Unhealthy {
+                        let mut sessions_to_drop = Self::all_sessions(packet_subscriptions);
+                        let disconnects_per_tick = *unhealthy_disconnects_per_tick.get_or_insert_with(|| {
+                            Self::disconnects_per_tick(sessions_to_drop.len(), unhealthy_disconnect_spread)
+                        });
+                        sessions_to_drop.truncate(disconnects_per_tick);
+                        Self::drop_connections(sessions_to_drop, DisconnectReason::Unhealthy, packet_subscriptions, session_log, &mut relayer_metrics);
+                    } else {
+                        unhealthy_disconnects_per_tick = None;
+                    }
+                    let failed_sessions = Self::handle_heartbeat(packet_subscriptions, &mut relayer_metrics);
+                    Self::drop_connections(failed_sessions, DisconnectReason::Closed, packet_subscriptions, session_log, &mut relayer_metrics);
                     let _ = relayer_metrics.crossbeam_heartbeat_tick_processing_us.increment(start.elapsed().as_micros() as u64);
                 }
                 recv(metrics_tick) -> time_generated => {
                     let start = Instant::now();
                     let l_packet_subscriptions = packet_subscriptions.read().unwrap();
-                    relayer_metrics.num_current_connections = l_packet_subscriptions.len() as u64;
+                    relayer_metrics.num_current_connections = l_packet_subscriptions.values().map(|s| s.sessions.len() as u64).sum();
                     relayer_metrics.update_packet_subscription_total_capacity(&l_packet_subscriptions);
                     drop(l_packet_subscriptions);
 
@@ -559,198 +725,176 @@ impl RelayerImpl {
                     relayer_metrics = RelayerMetrics::new(
                         slot_receiver.capacity().unwrap(),
                         subscription_receiver.capacity().unwrap(),
-                        delay_packet_receiver.capacity().unwrap(),
                     );
                 }
             }
 
-            relayer_metrics.update_max_len(
-                slot_receiver.len(),
-                subscription_receiver.len(),
-                delay_packet_receiver.len(),
-            );
+            relayer_metrics.update_max_len(slot_receiver.len(), subscription_receiver.len());
         }
         Ok(())
     }
 
+    /// Number of sessions to drop per heartbeat so num_sessions are dropped over spread
+    fn disconnects_per_tick(num_sessions: usize, spread: Duration) -> usize {
+        let num_ticks = (spread.as_millis() / HEARTBEAT_INTERVAL.as_millis()) as usize;
+        if num_ticks == 0 {
+            return usize::MAX;
+        }
+        num_sessions.div_ceil(num_ticks).max(1)
+    }
+
+    fn all_sessions(subscriptions: &PacketSubscriptions) -> Vec<(Pubkey, u64)> {
+        subscriptions
+            .read()
+            .unwrap()
+            .iter()
+            .flat_map(|(pubkey, subscription)| {
+                subscription
+                    .sessions
+                    .iter()
+                    .map(|session| (*pubkey, session.session_id))
+            })
+            .collect()
+    }
+
     fn drop_connections(
-        disconnected_pubkeys: Vec<Pubkey>,
+        disconnected_sessions: Vec<(Pubkey, u64)>,
+        reason: DisconnectReason,
         subscriptions: &PacketSubscriptions,
+        session_log: &SessionLog,
         relayer_metrics: &mut RelayerMetrics,
     ) {
-        relayer_metrics.num_removed_connections += disconnected_pubkeys.len() as u64;
-
+        let mut records = Vec::new();
         let mut l_subscriptions = subscriptions.write().unwrap();
-        for disconnected in disconnected_pubkeys {
-            if let Some(sender) = l_subscriptions.remove(&disconnected) {
-                datapoint_info!(
-                    "relayer_removed_subscription",
-                    ("pubkey", disconnected.to_string(), String)
-                );
-                drop(sender);
+        for (pubkey, session_id) in disconnected_sessions {
+            let Entry::Occupied(mut entry) = l_subscriptions.entry(pubkey) else {
+                continue;
+            };
+            let sessions = &mut entry.get_mut().sessions;
+            let Some(idx) = sessions.iter().position(|s| s.session_id == session_id) else {
+                continue;
+            };
+            // dropping the sender closes the stream
+            let session = sessions.remove(idx);
+            if sessions.is_empty() {
+                entry.remove();
             }
+            records.push(session.to_record(&pubkey, reason));
+
+            relayer_metrics.num_removed_connections += 1;
+            datapoint_info!(
+                "relayer_removed_subscription",
+                ("pubkey", pubkey.to_string(), String),
+                ("session_id", session_id, i64)
+            );
+        }
+        // fan-out workers wait on the subscriptions lock, so record after releasing it
+        drop(l_subscriptions);
+        for record in records {
+            session_log.record(record);
         }
     }
 
+    /// Heartbeats go to every session so standby sessions stay alive
     fn handle_heartbeat(
         subscriptions: &PacketSubscriptions,
         relayer_metrics: &mut RelayerMetrics,
-    ) -> Vec<Pubkey> {
-        let failed_pubkey_updates = subscriptions
-            .read()
-            .unwrap()
-            .iter()
-            .filter_map(|(pubkey, sender)| {
+    ) -> Vec<(Pubkey, u64)> {
+        let mut failed_session_updates = Vec::new();
+        for (pubkey, subscription) in subscriptions.read().unwrap().iter() {
+            for session in &subscription.sessions {
                 // try send because it's a bounded channel and we don't want to block if the channel is full
-                match sender.try_send(Ok(SubscribePacketsResponse {
+                match session.sender.try_send(Ok(SubscribePacketsResponse {
                     header: None,
                     msg: Some(subscribe_packets_response::Msg::Heartbeat(Heartbeat {
                         count: relayer_metrics.num_heartbeats,
                     })),
                 })) {
                     Ok(_) => {}
-                    Err(TrySendError::Closed(_)) => return Some(*pubkey),
+                    Err(TrySendError::Closed(_)) => {
+                        failed_session_updates.push((*pubkey, session.session_id))
+                    }
                     Err(TrySendError::Full(_)) => {
                         relayer_metrics.num_try_send_channel_full += 1;
-                        warn!("heartbeat channel is full for: {:?}", pubkey);
+                        warn!(
+                            "heartbeat channel is full for: {:?} session: {}",
+                            pubkey, session.session_id
+                        );
                     }
                 }
-                None
-            })
-            .collect();
-
-        relayer_metrics.num_heartbeats += 1;
-
-        failed_pubkey_updates
-    }
-
-    /// Returns pubkeys of subscribers that failed to send
-    fn forward_packets(
-        maybe_packet_batches: Result<RelayerPacketBatches, RecvError>,
-        subscriptions: &PacketSubscriptions,
-        slot_leaders: &HashSet<Pubkey>,
-        relayer_metrics: &mut RelayerMetrics,
-        ofac_addresses: &HashSet<Pubkey>,
-        address_lookup_table_cache: &Arc<DashMap<Pubkey, AddressLookupTableAccount>>,
-        validator_packet_batch_size: usize,
-        forward_all: bool,
-    ) -> RelayerResult<Vec<Pubkey>> {
-        let packet_batches = maybe_packet_batches?;
-
-        let _ = relayer_metrics
-            .packet_latencies_us
-            .increment(packet_batches.stamp.elapsed().as_micros() as u64);
-
-        // remove discards + check for OFAC before forwarding
-        let packets: Vec<_> = packet_batches
-            .banking_packet_batch
-            .0
-            .iter()
-            .flat_map(|batch| {
-                batch
-                    .iter()
-                    .filter(|p| !p.meta().discard())
-                    .filter_map(|packet| {
-                        if !ofac_addresses.is_empty() {
-                            let tx: VersionedTransaction = packet.deserialize_slice(..).ok()?;
-                            if !is_tx_ofac_related(&tx, ofac_addresses, address_lookup_table_cache)
-                            {
-                                Some(packet)
-                            } else {
-                                None
-                            }
-                        } else {
-                            Some(packet)
-                        }
-                    })
-                    .filter_map(packet_to_proto_packet)
-            })
-            .collect();
-
-        let mut proto_packet_batches =
-            Vec::with_capacity(packets.len() / validator_packet_batch_size);
-        for packet_chunk in packets.chunks(validator_packet_batch_size) {
-            proto_packet_batches.push(ProtoPacketBatch {
-                packets: packet_chunk.to_vec(),
-            });
+            }
         }
 
-        let l_subscriptions = subscriptions.read().unwrap();
-
-        let senders = if forward_all {
-            l_subscriptions.iter().collect::<Vec<(
-                &Pubkey,
-                &TokioSender<Result<SubscribePacketsResponse, Status>>,
-            )>>()
-        } else {
-            slot_leaders
-                .iter()
-                .filter_map(|pubkey| l_subscriptions.get(pubkey).map(|sender| (pubkey, sender)))
-                .collect()
-        };
-
-        let mut failed_forwards = Vec::new();
-        for batch in &proto_packet_batches {
-            // NOTE: this is important to avoid divide-by-0 inside the validator if packets
-            // get routed to sigverify under the assumption theres > 0 packets in the batch
-            if batch.packets.is_empty() {
-                continue;
-            }
+        relayer_metrics.num_heartbeats += 1;
 
-            for (pubkey, sender) in &senders {
-                // try send because it's a bounded channel and we don't want to block if the channel is full
-                match sender.try_send(Ok(SubscribePacketsResponse {
-                    header: Some(Header {
-                        ts: Some(Timestamp::from(SystemTime::now())),
-                    }),
-                    msg: Some(subscribe_packets_response::Msg::Batch(batch.clone())),
-                })) {
-                    Ok(_) => {
-                        relayer_metrics
-                            .increment_packets_forwarded(pubkey, batch.packets.len() as u64);
-                    }
-                    Err(TrySendError::Full(_)) => {
-                        error!("packet channel is full for pubkey: {:?}", pubkey);
-                        relayer_metrics
-                            .increment_packets_dropped(pubkey, batch.packets.len() as u64);
-                    }
-                    Err(TrySendError::Closed(_)) => {
-                        error!("channel is closed for pubkey: {:?}", pubkey);
-                        failed_forwards.push(**pubkey);
-                        break;
-                    }
-                }
-            }
-        }
-        Ok(failed_forwards)
+        failed_session_updates
     }
 
     fn handle_subscription(
         maybe_subscription: Result<Subscription, RecvError>,
         subscriptions: &PacketSubscriptions,
+        session_policies: &HashMap<Pubkey, SessionPolicy>,
+        session_log: &SessionLog,
         relayer_metrics: &mut RelayerMetrics,
     ) -> RelayerResult<()> {
         match maybe_subscription? {
-            Subscription::ValidatorPacketSubscription { pubkey, sender } => {
+            Subscription::ValidatorPacketSubscription {
+                pubkey,
+                session_id,
+                sender,
+                remote_ip,
+                filter,
+            } => {
+                let session = SubscriptionSession {
+                    session_id,
+                    sender,
+                    connected_at: SystemTime::now(),
+                    remote_ip,
+                    filter,
+                    num_packets_forwarded: AtomicU64::new(0),
+                    num_packets_dropped: AtomicU64::new(0),
+                    consumer_state: ConsumerState::default(),
+                };
+                relayer_metrics.num_added_connections += 1;
+
+                let mut replaced_records = Vec::new();
                 match subscriptions.write().unwrap().entry(pubkey) {
                     Entry::Vacant(entry) => {
-                        entry.insert(sender);
+                        entry.insert(ValidatorSubscription {
+                            policy: session_policies.get(&pubkey).cloned(),
+                            sessions: vec![session],
+                        });
 
-                        relayer_metrics.num_added_connections += 1;
                         datapoint_info!(
                             "relayer_new_subscription",
-                            ("pubkey", pubkey.to_string(), String)
+                            ("pubkey", pubkey.to_string(), String),
+                            ("session_id", session_id, i64)
                         );
                     }
                     Entry::Occupied(mut entry) => {
                         datapoint_info!(
                             "relayer_duplicate_subscription",
-                            ("pubkey", pubkey.to_string(), String)
+                            ("pubkey", pubkey.to_string(), String),
+                            ("session_id", session_id, i64)
                         );
-                        error!("already connected, dropping old connection: {pubkey:?}");
-                        entry.insert(sender);
+                        let subscription = entry.get_mut();
+                        if subscription.policy.is_some() {
+                            info!("adding session {session_id} for: {pubkey:?}");
+                        } else {
+                            error!("already connected, dropping old connection: {pubkey:?}");
+                            relayer_metrics.num_removed_connections +=
+                                subscription.sessions.len() as u64;
+                            replaced_records.extend(subscription.sessions.drain(..).map(
+                                |replaced| replaced.to_record(&pubkey, DisconnectReason::Replaced),
+                            ));
+                        }
+                        subscription.sessions.push(session);
                     }
                 }
+                // recorded after the subscriptions lock is released
+                for record in replaced_records {
+                    session_log.record(record);
+                }
             }
         }
         Ok(())
@@ -780,7 +924,10 @@ impl RelayerImpl {
         for t in self.threads {
             t.join()?;
         }
-        Ok(())
+        if let Some(leader_fallback_stage) = self.leader_fallback_stage {
+            leader_fallback_stage.join()?;
+        }
+        self.fan_out_stage.join()
     }
 }
 
@@ -789,18 +936,26 @@ impl Relayer for RelayerImpl {
     /// Validator calls this to get the public IP of the relayers TPU and TPU forward sockets.
     async fn get_tpu_configs(
         &self,
-        _: Request<GetTpuConfigsRequest>,
+        request: Request<GetTpuConfigsRequest>,
     ) -> Result<Response<GetTpuConfigsResponse>, Status> {
-        let seq = self.seq.fetch_add(1, Ordering::Acquire);
+        let pubkey: &Pubkey = request
+            .extensions()
+            .get()
+            .ok_or_else(|| Status::internal("internal error fetching public key"))?;
+
+        let ports = {
+            let l_subscriptions = self.packet_subscriptions.read().unwrap();
+            self.tpu_port_assigner
+                .assign(pubkey, l_subscriptions.keys(), Instant::now())
+        };
         return Ok(Response::new(GetTpuConfigsResponse {
             tpu: Some(Socket {
                 ip: self.public_ip.to_string(),
-                port: (self.tpu_quic_ports[seq as usize % self.tpu_quic_ports.len()] - 6) as i64,
+                port: (ports.tpu - 6) as i64,
             }),
             tpu_forward: Some(Socket {
                 ip: self.public_ip.to_string(),
-                port: (self.tpu_fwd_quic_ports[seq as usize % self.tpu_fwd_quic_ports.len()] - 6)
-                    as i64,
+                port: (ports.tpu_fwd - 6) as i64,
             }),
         }));
     }
@@ -819,13 +974,32 @@ impl Relayer for RelayerImpl {
             .get()
             .ok_or_else(|| Status::internal("internal error fetching public key"))?;
 
+        // operator configured filters apply on top of the ones requested by the validator
+        let requested_filter =
+            PacketFilter::from_metadata(request.metadata()).map_err(Status::invalid_argument)?;
+        let filter = match (self.packet_filters.get(pubkey).cloned(), requested_filter) {
+            (Some(operator_filter), Some(requested_filter)) => {
+                Some(operator_filter.merge(requested_filter))
+            }
+            (operator_filter, requested_filter) => operator_filter.or(requested_filter),
+        };
+
+        let session_id = self.next_session_id.fetch_add(1, Ordering::Relaxed);
         let (sender, receiver) = channel(RelayerImpl::SUBSCRIBER_QUEUE_CAPACITY);
         self.subscription_sender
             .send(Subscription::ValidatorPacketSubscription {
                 pubkey: *pubkey,
+                session_id,
                 sender,
+                remote_ip: request.remote_addr().map(|addr| addr.ip()),
+                filter,
             })
             .map_err(|_| Status::internal("internal error adding subscription"))?;
-        Ok(Response::new(ReceiverStream::new(receiver)))
+
+        let mut response = Response::new(ReceiverStream::new(receiver));
+        response
+            .metadata_mut()
+            .insert(SESSION_ID_METADATA_KEY, MetadataValue::from(session_id));
+        Ok(response)
     }
 }
@@ -41,11 +41,28 @@ impl LeaderScheduleUpdatingHandle {
         self.schedule.read().unwrap().get(slot).cloned()
     }
 
-    pub fn leaders_for_slots(&self, slots: &[Slot]) -> HashSet<Pubkey> {
+    /// Leaders of the slots starting at slot that fall within their lookahead. Leaders without an
+    /// override use default_lookahead.
+    pub fn leaders_within_lookahead(
+        &self,
+        slot: Slot,
+        default_lookahead: u64,
+        lookahead_overrides: &HashMap<Pubkey, u64>,
+    ) -> HashSet<Pubkey> {
+        let max_lookahead = lookahead_overrides
+            .values()
+            .copied()
+            .fold(default_lookahead, u64::max);
         let schedule = self.schedule.read().unwrap();
-        slots
-            .iter()
-            .filter_map(|s| schedule.get(s).cloned())
+        (slot..slot.saturating_add(max_lookahead))
+            .filter_map(|s| {
+                let leader = schedule.get(&s)?;
+                let lookahead = lookahead_overrides
+                    .get(leader)
+                    .copied()
+                    .unwrap_or(default_lookahead);
+                (s - slot < lookahead).then_some(*leader)
+            })
             .collect()
     }
 
@@ -145,3 +162,42 @@ impl LeaderScheduleCacheUpdater {
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{HashMap, HashSet},
+        sync::{Arc, RwLock},
+    };
+
+    use solana_sdk::pubkey::Pubkey;
+
+    use crate::schedule_cache::LeaderScheduleUpdatingHandle;
+
+    #[test]
+    fn test_leaders_within_lookahead() {
+        let early = Pubkey::new_unique();
+        let late = Pubkey::new_unique();
+        let other = Pubkey::new_unique();
+        let handle = LeaderScheduleUpdatingHandle::new(Arc::new(RwLock::new(HashMap::from([
+            (102, other),
+            (106, early),
+            (103, late),
+        ]))));
+        let overrides = HashMap::from([(early, 8), (late, 2)]);
+
+        // early wants packets 8 slots ahead, late only 2, everyone else uses the default of 4
+        assert_eq!(
+            handle.leaders_within_lookahead(100, 4, &overrides),
+            HashSet::from([early, other])
+        );
+        assert_eq!(
+            handle.leaders_within_lookahead(102, 4, &overrides),
+            HashSet::from([early, late, other])
+        );
+        assert_eq!(
+            handle.leaders_within_lookahead(103, 4, &overrides),
+            HashSet::from([early, late])
+        );
+    }
+}
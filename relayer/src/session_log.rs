@@ -0,0 +1,293 @@
+//! Audit log of ended packet subscription sessions, so operators can tell when and why a validator
+//! disconnected. The most recent sessions are kept in memory and optionally appended to a JSON
+//! lines file by a background writer, which is read back on startup. The file is rewritten down
+//! to the in-memory capacity whenever it grows to twice that, so it stays bounded.
+
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread::Builder,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crossbeam_channel::{bounded, Receiver, Sender, TrySendError};
+use log::error;
+use serde::{Deserialize, Serialize};
+
+/// Records waiting to be written to the persist file, newer ones are dropped once full
+const SESSION_LOG_WRITER_QUEUE_CAPACITY: usize = 10_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisconnectReason {
+    /// The validator closed the stream
+    Closed,
+    /// The relayer became unhealthy and dropped every session
+    Unhealthy,
+    /// A newer session for the same identity replaced this one
+    Replaced,
+    /// The session was evicted for not keeping up with its packet channel
+    ChannelFull,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionRecord {
+    pub pubkey: String,
+    pub session_id: u64,
+    pub remote_ip: Option<IpAddr>,
+    pub connected_at_unix_ms: u64,
+    pub disconnected_at_unix_ms: u64,
+    pub disconnect_reason: DisconnectReason,
+    pub num_packets_forwarded: u64,
+    pub num_packets_dropped: u64,
+}
+
+pub fn unix_ms(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+enum WriterCommand {
+    Record(SessionRecord),
+    /// Acked once everything sent before it is flushed to the file
+    Flush(Sender<()>),
+}
+
+fn push(records: &mut VecDeque<SessionRecord>, capacity: usize, record: SessionRecord) {
+    if capacity == 0 {
+        return;
+    }
+    if records.len() == capacity {
+        records.pop_front();
+    }
+    records.push_back(record);
+}
+
+#[derive(Clone)]
+pub struct SessionLog {
+    records: Arc<Mutex<VecDeque<SessionRecord>>>,
+    capacity: usize,
+    /// Hands records to the persist file writer, None when not persisting
+    writer_sender: Option<Sender<WriterCommand>>,
+}
+
+impl SessionLog {
+    /// Keeps the last capacity sessions. If persist_path is set, sessions already in the file are
+    /// loaded and new ones are appended to it.
+    pub fn new(capacity: usize, persist_path: Option<&Path>) -> io::Result<SessionLog> {
+        let mut records = VecDeque::with_capacity(capacity);
+        let writer_sender = match persist_path {
+            None => None,
+            Some(path) => {
+                if path.exists() {
+                    for line in BufReader::new(File::open(path)?).lines() {
+                        match serde_json::from_str(&line?) {
+                            Ok(record) => push(&mut records, capacity, record),
+                            Err(e) => error!("skipping invalid session log line: {e}"),
+                        }
+                    }
+                }
+                let writer = SessionLogWriter::new(path.to_path_buf(), capacity, records.clone())?;
+                let (writer_sender, writer_receiver) = bounded(SESSION_LOG_WRITER_QUEUE_CAPACITY);
+                Builder::new()
+                    .name("session_log_writer".to_string())
+                    .spawn(move || writer.run(writer_receiver))?;
+                Some(writer_sender)
+            }
+        };
+
+        Ok(SessionLog {
+            records: Arc::new(Mutex::new(records)),
+            capacity,
+            writer_sender,
+        })
+    }
+
+    /// Never blocks on disk, records are persisted by the background writer
+    pub fn record(&self, record: SessionRecord) {
+        if let Some(writer_sender) = &self.writer_sender {
+            match writer_sender.try_send(WriterCommand::Record(record.clone())) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    error!("session log writer is behind, not persisting session record")
+                }
+                Err(TrySendError::Disconnected(_)) => {
+                    error!("session log writer exited, not persisting session record")
+                }
+            }
+        }
+        push(&mut self.records.lock().unwrap(), self.capacity, record);
+    }
+
+    /// Blocks until every record so far is written to the persist file, if any
+    pub fn flush(&self) {
+        if let Some(writer_sender) = &self.writer_sender {
+            let (ack_sender, ack_receiver) = bounded(1);
+            if writer_sender.send(WriterCommand::Flush(ack_sender)).is_ok() {
+                let _ = ack_receiver.recv();
+            }
+        }
+    }
+
+    /// Returns up to limit sessions, newest first, optionally only those of one validator
+    pub fn history(&self, pubkey: Option<&str>, limit: usize) -> Vec<SessionRecord> {
+        self.records
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .filter(|record| pubkey.map_or(true, |pubkey| record.pubkey == pubkey))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Appends records to the persist file. Exits once every SessionLog clone is dropped.
+struct SessionLogWriter {
+    path: PathBuf,
+    capacity: usize,
+    /// Last capacity records written, the file is compacted down to these
+    records: VecDeque<SessionRecord>,
+    file: BufWriter<File>,
+    num_lines: usize,
+}
+
+impl SessionLogWriter {
+    /// Compacts the file down to the given records before appending to it
+    fn new(
+        path: PathBuf,
+        capacity: usize,
+        records: VecDeque<SessionRecord>,
+    ) -> io::Result<SessionLogWriter> {
+        let file = Self::compact(&path, &records)?;
+        Ok(SessionLogWriter {
+            path,
+            capacity,
+            num_lines: records.len(),
+            records,
+            file,
+        })
+    }
+
+    /// Replaces the file with one holding only the given records and opens it for appending
+    fn compact(path: &Path, records: &VecDeque<SessionRecord>) -> io::Result<BufWriter<File>> {
+        let tmp_path = path.with_extension("tmp");
+        let mut tmp_file = BufWriter::new(File::create(&tmp_path)?);
+        for record in records {
+            serde_json::to_writer(&mut tmp_file, record)?;
+            tmp_file.write_all(b"\n")?;
+        }
+        tmp_file.flush()?;
+        fs::rename(&tmp_path, path)?;
+        Ok(BufWriter::new(
+            OpenOptions::new().create(true).append(true).open(path)?,
+        ))
+    }
+
+    fn write(&mut self, record: &SessionRecord) -> io::Result<()> {
+        if self.num_lines >= self.capacity.saturating_mul(2) {
+            self.file = Self::compact(&self.path, &self.records)?;
+            self.num_lines = self.records.len();
+        }
+        serde_json::to_writer(&mut self.file, record)?;
+        self.file.write_all(b"\n")?;
+        self.num_lines += 1;
+        Ok(())
+    }
+
+    fn run(mut self, receiver: Receiver<WriterCommand>) {
+        while let Ok(command) = receiver.recv() {
+            let mut res = Ok(());
+            let mut flush_acks = Vec::new();
+            for command in std::iter::once(command).chain(receiver.try_iter()) {
+                match command {
+                    WriterCommand::Record(record) => {
+                        res = res.and_then(|_| self.write(&record));
+                        push(&mut self.records, self.capacity, record);
+                    }
+                    WriterCommand::Flush(ack_sender) => flush_acks.push(ack_sender),
+                }
+            }
+            if let Err(e) = res.and_then(|_| self.file.flush()) {
+                error!("error persisting session records: {e}");
+            }
+            for ack_sender in flush_acks {
+                let _ = ack_sender.send(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+
+    use crate::session_log::{DisconnectReason, SessionLog, SessionRecord};
+
+    fn record(pubkey: &str, session_id: u64) -> SessionRecord {
+        SessionRecord {
+            pubkey: pubkey.to_string(),
+            session_id,
+            remote_ip: Some("127.0.0.1".parse().unwrap()),
+            connected_at_unix_ms: 1,
+            disconnected_at_unix_ms: 2,
+            disconnect_reason: DisconnectReason::Closed,
+            num_packets_forwarded: 10,
+            num_packets_dropped: 0,
+        }
+    }
+
+    #[test]
+    fn test_session_log() {
+        let path = temp_dir().join(format!("session_log_{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let session_log = SessionLog::new(2, Some(&path)).unwrap();
+        session_log.record(record("a", 0));
+        session_log.record(record("b", 1));
+        session_log.record(record("a", 2));
+
+        // the oldest session fell out of the ring buffer
+        assert_eq!(
+            session_log.history(None, 10),
+            vec![record("a", 2), record("b", 1)]
+        );
+        assert_eq!(session_log.history(Some("a"), 10), vec![record("a", 2)]);
+        assert_eq!(session_log.history(None, 1), vec![record("a", 2)]);
+
+        // records are written in the background
+        let read_file = |session_log: &SessionLog| -> Vec<SessionRecord> {
+            session_log.flush();
+            std::fs::read_to_string(&path)
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        };
+        assert_eq!(
+            read_file(&session_log),
+            vec![record("a", 0), record("b", 1), record("a", 2)]
+        );
+
+        // reloads the last sessions from disk
+        let reloaded = SessionLog::new(2, Some(&path)).unwrap();
+        assert_eq!(reloaded.history(None, 10), session_log.history(None, 10));
+
+        // the file is compacted down to the capacity whenever it holds twice that
+        for session_id in 3..10 {
+            reloaded.record(record("c", session_id));
+        }
+        assert_eq!(
+            read_file(&reloaded),
+            vec![record("c", 7), record("c", 8), record("c", 9)]
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
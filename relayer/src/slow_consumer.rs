@@ -0,0 +1,134 @@
+//! Tracks subscribers that can't keep up with their packet stream. Lagging subscribers get larger
+//! batches so they have fewer messages to drain, and subscribers that stay slow can be evicted.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Copy, Debug)]
+pub struct SlowConsumerPolicy {
+    /// Fraction of the subscriber queue in use at which a subscriber is considered slow
+    pub slow_queue_threshold: f64,
+    /// Subscribers that stay slow for this long are evicted, None never evicts
+    pub eviction_after: Option<Duration>,
+    /// Upper bound on the adaptive batch size
+    pub max_batch_size: usize,
+}
+
+impl SlowConsumerPolicy {
+    /// Doubles the batch size for every quarter of the queue in use
+    pub fn batch_size(
+        &self,
+        base_batch_size: usize,
+        queue_depth: usize,
+        queue_capacity: usize,
+    ) -> usize {
+        if queue_capacity == 0 {
+            return base_batch_size;
+        }
+        let quarters_used = (queue_depth.min(queue_capacity) * 4 / queue_capacity).min(3);
+        (base_batch_size << quarters_used)
+            .clamp(base_batch_size, self.max_batch_size.max(base_batch_size))
+    }
+
+    pub fn is_slow(&self, queue_depth: usize, queue_capacity: usize) -> bool {
+        queue_depth as f64 >= queue_capacity as f64 * self.slow_queue_threshold
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConsumerTransition {
+    None,
+    BecameSlow,
+    Recovered,
+    /// The consumer stayed slow past the eviction deadline
+    Evict,
+}
+
+#[derive(Debug, Default)]
+pub struct ConsumerState {
+    slow_since: Mutex<Option<Instant>>,
+}
+
+impl ConsumerState {
+    pub fn update(
+        &self,
+        policy: &SlowConsumerPolicy,
+        is_slow: bool,
+        now: Instant,
+    ) -> ConsumerTransition {
+        let mut slow_since = self.slow_since.lock().unwrap();
+        match (*slow_since, is_slow) {
+            (None, true) => {
+                *slow_since = Some(now);
+                ConsumerTransition::BecameSlow
+            }
+            (Some(_), false) => {
+                *slow_since = None;
+                ConsumerTransition::Recovered
+            }
+            (Some(since), true)
+                if policy
+                    .eviction_after
+                    .is_some_and(|eviction_after| now.duration_since(since) >= eviction_after) =>
+            {
+                ConsumerTransition::Evict
+            }
+            _ => ConsumerTransition::None,
+        }
+    }
+
+    pub fn is_slow(&self) -> bool {
+        self.slow_since.lock().unwrap().is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::slow_consumer::{ConsumerState, ConsumerTransition, SlowConsumerPolicy};
+
+    #[test]
+    fn test_slow_consumer() {
+        let policy = SlowConsumerPolicy {
+            slow_queue_threshold: 0.5,
+            eviction_after: Some(Duration::from_secs(5)),
+            max_batch_size: 16,
+        };
+        assert_eq!(policy.batch_size(4, 0, 100), 4);
+        assert_eq!(policy.batch_size(4, 30, 100), 8);
+        assert_eq!(policy.batch_size(4, 60, 100), 16);
+        // capped at max_batch_size
+        assert_eq!(policy.batch_size(4, 100, 100), 16);
+        assert!(!policy.is_slow(49, 100));
+        assert!(policy.is_slow(50, 100));
+
+        let state = ConsumerState::default();
+        let start = Instant::now();
+        assert_eq!(
+            state.update(&policy, true, start),
+            ConsumerTransition::BecameSlow
+        );
+        assert!(state.is_slow());
+        assert_eq!(
+            state.update(&policy, true, start + Duration::from_secs(1)),
+            ConsumerTransition::None
+        );
+        assert_eq!(
+            state.update(&policy, false, start + Duration::from_secs(2)),
+            ConsumerTransition::Recovered
+        );
+        // the slow period restarts after recovering
+        state.update(&policy, true, start + Duration::from_secs(3));
+        assert_eq!(
+            state.update(&policy, true, start + Duration::from_secs(7)),
+            ConsumerTransition::None
+        );
+        assert_eq!(
+            state.update(&policy, true, start + Duration::from_secs(8)),
+            ConsumerTransition::Evict
+        );
+    }
+}
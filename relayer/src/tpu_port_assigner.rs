@@ -0,0 +1,253 @@
+//! Chooses the TPU and TPU forward ports handed out to a validator in GetTpuConfigs. A validator
+//! without an assignment gets its highest ranked port by rendezvous hashing on its identity that
+//! has room for its stake, given the stake of the connected validators already on each port, so
+//! load is spread by stake rather than by validator count. The assignment is kept until the
+//! validator has been disconnected for ASSIGNMENT_RETENTION, so its port doesn't change when it
+//! reconnects or when other validators come and go.
+
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use sha2::{Digest, Sha256};
+use solana_sdk::pubkey::Pubkey;
+use solana_streamer::streamer::StakedNodes;
+
+/// How far above an even stake split a port may go before validators spill to their next port
+const LOAD_SLACK_PERCENT: u64 = 25;
+
+/// Assignments of validators that haven't been connected for this long are forgotten
+const ASSIGNMENT_RETENTION: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TpuPorts {
+    pub tpu: u16,
+    pub tpu_fwd: u16,
+}
+
+/// Parses an admin override formatted as pubkey:tpu_port:tpu_fwd_port
+pub fn parse_tpu_port_override(arg: &str) -> Result<(Pubkey, TpuPorts), String> {
+    let mut parts = arg.split(':');
+    let (Some(pubkey), Some(tpu), Some(tpu_fwd), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(format!(
+            "invalid tpu port override {arg}, expected pubkey:tpu_port:tpu_fwd_port"
+        ));
+    };
+    let pubkey = Pubkey::from_str(pubkey).map_err(|e| format!("invalid pubkey {pubkey}: {e}"))?;
+    let tpu = tpu
+        .parse()
+        .map_err(|e| format!("invalid tpu port {tpu}: {e}"))?;
+    let tpu_fwd = tpu_fwd
+        .parse()
+        .map_err(|e| format!("invalid tpu forward port {tpu_fwd}: {e}"))?;
+    Ok((pubkey, TpuPorts { tpu, tpu_fwd }))
+}
+
+pub struct TpuPortAssigner {
+    tpu_ports: Vec<u16>,
+    tpu_fwd_ports: Vec<u16>,
+    staked_nodes: Arc<RwLock<StakedNodes>>,
+    overrides: HashMap<Pubkey, TpuPorts>,
+    /// Port index assigned to each validator and when it was last connected
+    assignments: Mutex<HashMap<Pubkey, (usize, Instant)>>,
+}
+
+impl TpuPortAssigner {
+    /// Ports are the QUIC ports the relayer listens on. Overrides must use one of them.
+    pub fn new(
+        tpu_ports: Vec<u16>,
+        tpu_fwd_ports: Vec<u16>,
+        staked_nodes: Arc<RwLock<StakedNodes>>,
+        overrides: HashMap<Pubkey, TpuPorts>,
+    ) -> Result<TpuPortAssigner, String> {
+        if tpu_ports.is_empty() || tpu_fwd_ports.is_empty() {
+            return Err("need at least one tpu and tpu forward port".to_string());
+        }
+        for (pubkey, ports) in &overrides {
+            if !tpu_ports.contains(&ports.tpu) || !tpu_fwd_ports.contains(&ports.tpu_fwd) {
+                return Err(format!(
+                    "tpu port override for {pubkey} uses ports the relayer doesn't listen on: {ports:?}"
+                ));
+            }
+        }
+        Ok(TpuPortAssigner {
+            tpu_ports,
+            tpu_fwd_ports,
+            staked_nodes,
+            overrides,
+            assignments: Mutex::default(),
+        })
+    }
+
+    /// Returns the ports for a validator given the validators currently connected. A validator
+    /// keeps the port it was assigned, new ones are placed on their highest ranked port with room
+    /// for their stake.
+    pub fn assign<'a>(
+        &self,
+        pubkey: &Pubkey,
+        connected: impl IntoIterator<Item = &'a Pubkey>,
+        now: Instant,
+    ) -> TpuPorts {
+        if let Some(ports) = self.overrides.get(pubkey) {
+            return *ports;
+        }
+
+        let connected: HashSet<&Pubkey> = connected.into_iter().collect();
+        let mut l_assignments = self.assignments.lock().unwrap();
+        for (validator, (_, last_connected)) in l_assignments.iter_mut() {
+            if connected.contains(validator) {
+                *last_connected = now;
+            }
+        }
+        l_assignments.retain(|_, (_, last_connected)| {
+            now.saturating_duration_since(*last_connected) < ASSIGNMENT_RETENTION
+        });
+        if let Some((idx, last_connected)) = l_assignments.get_mut(pubkey) {
+            *last_connected = now;
+            return self.ports(*idx);
+        }
+
+        let l_staked_nodes = self.staked_nodes.read().unwrap();
+        // unstaked validators still count, so they spread out too
+        let stake_of = |pubkey: &Pubkey| l_staked_nodes.get_node_stake(pubkey).unwrap_or(0).max(1);
+
+        let mut port_stakes = vec![0u64; self.tpu_ports.len()];
+        for validator in connected.iter().copied().filter(|v| *v != pubkey) {
+            let idx = match self.overrides.get(validator) {
+                Some(ports) => self.tpu_ports.iter().position(|p| *p == ports.tpu),
+                None => l_assignments.get(validator).map(|(idx, _)| *idx),
+            };
+            if let Some(idx) = idx {
+                port_stakes[idx] = port_stakes[idx].saturating_add(stake_of(validator));
+            }
+        }
+
+        let stake = stake_of(pubkey);
+        let total_stake = port_stakes.iter().copied().fold(stake, u64::saturating_add);
+        let port_capacity = total_stake.saturating_mul(100 + LOAD_SLACK_PERCENT)
+            / 100
+            / self.tpu_ports.len() as u64;
+
+        let mut ranked: Vec<usize> = (0..self.tpu_ports.len()).collect();
+        ranked.sort_by_key(|idx| std::cmp::Reverse(rendezvous_score(pubkey, self.tpu_ports[*idx])));
+        let idx = ranked
+            .iter()
+            .copied()
+            .find(|idx| port_stakes[*idx].saturating_add(stake) <= port_capacity.max(stake))
+            .unwrap_or(ranked[0]);
+        l_assignments.insert(*pubkey, (idx, now));
+        self.ports(idx)
+    }
+
+    fn ports(&self, idx: usize) -> TpuPorts {
+        TpuPorts {
+            tpu: self.tpu_ports[idx],
+            tpu_fwd: self.tpu_fwd_ports[idx % self.tpu_fwd_ports.len()],
+        }
+    }
+}
+
+/// Stable across restarts so a validator ranks ports the same way every time
+fn rendezvous_score(pubkey: &Pubkey, port: u16) -> u64 {
+    let hash = Sha256::new()
+        .chain_update(pubkey.as_ref())
+        .chain_update(port.to_le_bytes())
+        .finalize();
+    u64::from_le_bytes(hash[..8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, RwLock},
+        time::{Duration, Instant},
+    };
+
+    use solana_sdk::pubkey::Pubkey;
+    use solana_streamer::streamer::StakedNodes;
+
+    use crate::tpu_port_assigner::{
+        parse_tpu_port_override, TpuPortAssigner, TpuPorts, ASSIGNMENT_RETENTION,
+    };
+
+    #[test]
+    fn test_tpu_port_assigner() {
+        let whale = Pubkey::new_unique();
+        let pinned = Pubkey::new_unique();
+        let others: Vec<Pubkey> = (0..20).map(|_| Pubkey::new_unique()).collect();
+
+        let stakes: HashMap<Pubkey, u64> = others
+            .iter()
+            .map(|p| (*p, 10))
+            .chain([(whale, 1_000)])
+            .collect();
+        let staked_nodes = Arc::new(RwLock::new(StakedNodes::new(
+            Arc::new(stakes),
+            HashMap::new(),
+        )));
+
+        let (_, pinned_ports) = parse_tpu_port_override(&format!("{pinned}:11229:11240")).unwrap();
+        assert!(parse_tpu_port_override("not-a-pubkey:1:2").is_err());
+        assert!(TpuPortAssigner::new(
+            vec![11228],
+            vec![11239],
+            staked_nodes.clone(),
+            HashMap::from([(pinned, pinned_ports)]),
+        )
+        .is_err());
+
+        let assigner = TpuPortAssigner::new(
+            vec![11228, 11229],
+            vec![11239, 11240],
+            staked_nodes,
+            HashMap::from([(pinned, pinned_ports)]),
+        )
+        .unwrap();
+        let now = Instant::now();
+        assert_eq!(
+            assigner.assign(&pinned, &[], now),
+            TpuPorts {
+                tpu: 11229,
+                tpu_fwd: 11240
+            }
+        );
+
+        let mut connected = vec![pinned];
+        let whale_ports = assigner.assign(&whale, &connected, now);
+        connected.push(whale);
+        // the whale fills its port, so everyone else is pushed to the other one
+        let mut other_ports = Vec::new();
+        for other in &others {
+            let ports = assigner.assign(other, &connected, now);
+            assert_ne!(ports, whale_ports);
+            other_ports.push(ports);
+            connected.push(*other);
+        }
+
+        // validators keep their port when they reconnect and when others connect or disconnect
+        let later = now + Duration::from_secs(60);
+        let newcomer = Pubkey::new_unique();
+        assigner.assign(&newcomer, &connected, later);
+        connected.push(newcomer);
+        connected.retain(|p| *p != whale);
+        for (other, ports) in others.iter().zip(&other_ports) {
+            assert_eq!(assigner.assign(other, &connected, later), *ports);
+        }
+        assert_eq!(assigner.assign(&whale, &connected, later), whale_ports);
+
+        // assignments of validators that stay disconnected for the retention period are forgotten
+        let much_later = later + ASSIGNMENT_RETENTION;
+        connected.retain(|p| *p != whale);
+        assert_eq!(
+            assigner.assign(&others[0], &connected, much_later),
+            other_ports[0]
+        );
+        assert!(!assigner.assignments.lock().unwrap().contains_key(&whale));
+    }
+}
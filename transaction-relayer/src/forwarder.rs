@@ -1,84 +1,207 @@
 use std::{
     collections::VecDeque,
+    mem::size_of,
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicU32, Ordering},
         Arc,
     },
     thread::{Builder, JoinHandle},
     time::{Duration, Instant, SystemTime},
 };
 
+use clap::ValueEnum;
 use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
 use jito_block_engine::block_engine::BlockEnginePackets;
-use jito_relayer::relayer::RelayerPacketBatches;
+use jito_core::receive_stamp_stage::QuicReceiveTimes;
+use jito_relayer::{
+    blockhash_cache::{BlockhashCacheHandle, BlockhashStatus},
+    relayer::{PacketBatchStamps, RelayerPacketBatches, TracerPacket},
+};
+use log::error;
 use solana_core::banking_trace::BankingPacketBatch;
-use solana_metrics::datapoint_info;
+use solana_metrics::{datapoint_error, datapoint_info};
+use solana_perf::packet::Packet;
+use solana_sdk::transaction::VersionedTransaction;
 use tokio::sync::mpsc::error::TrySendError;
 
 pub const BLOCK_ENGINE_FORWARDER_QUEUE_CAPACITY: usize = 5_000;
 
+/// Determines what the forwarder does when the relayer event loop can't keep up with ingestion.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OverloadPolicy {
+    /// Block on the relayer event loop and stop ingesting while the delay buffer is full.
+    /// Note: this also stalls the block engine path.
+    Block,
+    /// Evict the oldest buffered packets to make room for new ones.
+    DropOldest,
+    /// Reject incoming packets while the delay buffer is full.
+    DropNewest,
+}
+
+struct BufferedPacketBatches {
+    packet_batches: RelayerPacketBatches,
+    num_packets: u64,
+    num_bytes: usize,
+}
+
+/// Returns the number of packets and the approximate memory footprint of a batch
+fn packet_batch_size(banking_packet_batch: &BankingPacketBatch) -> (u64, usize) {
+    let num_packets = banking_packet_batch
+        .0
+        .iter()
+        .map(|b| b.len() as u64)
+        .sum::<u64>();
+    (num_packets, num_packets as usize * size_of::<Packet>())
+}
+
+/// Marks packets whose recent_blockhash has expired as discarded so they aren't forwarded.
+/// Durable nonce transactions are left alone since their recent_blockhash is the nonce value.
+fn discard_expired_blockhash_packets(
+    banking_packet_batch: &mut BankingPacketBatch,
+    blockhash_cache: &BlockhashCacheHandle,
+    forwarder_metrics: &mut ForwarderMetrics,
+) {
+    let mut expired = Vec::new();
+    for (batch_idx, batch) in banking_packet_batch.0.iter().enumerate() {
+        for (packet_idx, packet) in batch.iter().enumerate() {
+            if packet.meta().discard() {
+                continue;
+            }
+            let Ok(tx) = packet.deserialize_slice::<VersionedTransaction, _>(..) else {
+                continue;
+            };
+            if tx.uses_durable_nonce() {
+                forwarder_metrics.num_durable_nonce_packets += 1;
+                continue;
+            }
+            match blockhash_cache.status(tx.message.recent_blockhash()) {
+                BlockhashStatus::Valid => {}
+                BlockhashStatus::Expired => expired.push((batch_idx, packet_idx)),
+                BlockhashStatus::Unknown => forwarder_metrics.num_unknown_blockhash_packets += 1,
+            }
+        }
+    }
+
+    if expired.is_empty() {
+        return;
+    }
+    forwarder_metrics.num_expired_blockhash_packets_dropped += expired.len() as u64;
+
+    // only copies the batch if something else still holds a reference to it
+    let (batches, _) = Arc::make_mut(banking_packet_batch);
+    for (batch_idx, packet_idx) in expired {
+        batches[batch_idx][packet_idx].meta_mut().set_discard(true);
+    }
+}
+
 /// Forwards packets to the Block Engine handler thread.
-/// Delays transactions for packet_delay_ms before forwarding them to the validator.
+/// Delays transactions for effective_packet_delay_ms before forwarding them to the validator.
+/// The delay is re-read on every iteration so it can be adjusted while running.
+/// Each thread buffers at most buffer_max_bytes of delayed packets, after which the
+/// overload_policy kicks in.
+/// If a blockhash_cache is provided, transactions with expired blockhashes are dropped.
+/// If the block engine handler goes away, is_block_engine_failed is set and the forwarder keeps
+/// serving validators.
+/// Batches are stamped with their QUIC receive time from quic_receive_times when it's recorded and
+/// known.
+/// If a packet_mirror_sender is provided, a copy of each batch released to the relayer is sent to
+/// it. Batches are dropped when the mirror is backed up so it never slows down forwarding.
+#[allow(clippy::too_many_arguments)]
 pub fn start_forward_and_delay_thread(
     verified_receiver: Receiver<BankingPacketBatch>,
     delay_packet_sender: Sender<RelayerPacketBatches>,
-    packet_delay_ms: u32,
+    effective_packet_delay_ms: &Arc<AtomicU32>,
     block_engine_sender: tokio::sync::mpsc::Sender<BlockEnginePackets>,
     num_threads: u64,
     disable_mempool: bool,
+    overload_policy: OverloadPolicy,
+    buffer_max_bytes: usize,
+    blockhash_cache: Option<BlockhashCacheHandle>,
+    packet_mirror_sender: Option<Sender<BankingPacketBatch>>,
+    quic_receive_times: Option<QuicReceiveTimes>,
+    is_block_engine_failed: &Arc<AtomicBool>,
     exit: &Arc<AtomicBool>,
 ) -> Vec<JoinHandle<()>> {
     const SLEEP_DURATION: Duration = Duration::from_millis(5);
-    let packet_delay = Duration::from_millis(packet_delay_ms as u64);
 
     (0..num_threads)
         .map(|thread_id| {
             let verified_receiver = verified_receiver.clone();
             let delay_packet_sender = delay_packet_sender.clone();
             let block_engine_sender = block_engine_sender.clone();
+            let blockhash_cache = blockhash_cache.clone();
+            let packet_mirror_sender = packet_mirror_sender.clone();
+            let effective_packet_delay_ms = effective_packet_delay_ms.clone();
+            let quic_receive_times = quic_receive_times.clone();
+            let is_block_engine_failed = is_block_engine_failed.clone();
 
             let exit = exit.clone();
             Builder::new()
                 .name(format!("forwarder_thread_{thread_id}"))
                 .spawn(move || {
-                    let mut buffered_packet_batches: VecDeque<RelayerPacketBatches> =
-                        VecDeque::with_capacity(100_000);
+                    let mut buffered_packet_batches: VecDeque<BufferedPacketBatches> =
+                        VecDeque::new();
+                    let mut buffered_bytes = 0usize;
+                    let mut is_block_engine_sender_closed = false;
 
                     let metrics_interval = Duration::from_secs(1);
                     let mut forwarder_metrics = ForwarderMetrics::new(
-                        buffered_packet_batches.capacity(),
+                        buffer_max_bytes,
                         verified_receiver.capacity().unwrap_or_default(), // TODO (LB): unbounded channel now, remove metric
                         block_engine_sender.capacity(),
                     );
                     let mut last_metrics_upload = Instant::now();
 
                     while !exit.load(Ordering::Relaxed) {
+                        let packet_delay_ms = effective_packet_delay_ms.load(Ordering::Relaxed);
+                        let packet_delay = Duration::from_millis(packet_delay_ms as u64);
+
                         if last_metrics_upload.elapsed() >= metrics_interval {
                             forwarder_metrics.report(thread_id, packet_delay_ms);
 
                             forwarder_metrics = ForwarderMetrics::new(
-                                buffered_packet_batches.capacity(),
+                                buffer_max_bytes,
                                 verified_receiver.capacity().unwrap_or_default(), // TODO (LB): unbounded channel now, remove metric
                                 block_engine_sender.capacity(),
                             );
                             last_metrics_upload = Instant::now();
                         }
 
-                        match verified_receiver.recv_timeout(SLEEP_DURATION) {
-                            Ok(banking_packet_batch) => {
+                        // when blocking, leave packets in verified_receiver until the delay buffer drains
+                        let maybe_packet_batch = if overload_policy == OverloadPolicy::Block
+                            && buffered_bytes >= buffer_max_bytes
+                        {
+                            forwarder_metrics.num_ingest_blocked += 1;
+                            std::thread::sleep(SLEEP_DURATION);
+                            Err(RecvTimeoutError::Timeout)
+                        } else {
+                            verified_receiver.recv_timeout(SLEEP_DURATION)
+                        };
+
+                        match maybe_packet_batch {
+                            Ok(mut banking_packet_batch) => {
                                 let instant = Instant::now();
                                 let system_time = SystemTime::now();
-                                let num_packets = banking_packet_batch
-                                    .0
-                                    .iter()
-                                    .map(|b| b.len() as u64)
-                                    .sum::<u64>();
+                                let (quic_received, tracer_packets) = quic_receive_times
+                                    .as_ref()
+                                    .map(|q| q.take(&banking_packet_batch.0))
+                                    .unwrap_or_default();
+                                let (num_packets, num_bytes) =
+                                    packet_batch_size(&banking_packet_batch);
                                 forwarder_metrics.num_batches_received += 1;
                                 forwarder_metrics.num_packets_received += num_packets;
 
+                                if let Some(blockhash_cache) = &blockhash_cache {
+                                    discard_expired_blockhash_packets(
+                                        &mut banking_packet_batch,
+                                        blockhash_cache,
+                                        &mut forwarder_metrics,
+                                    );
+                                }
+
                                 // try_send because the block engine receiver only drains when it's connected
                                 // and we don't want to OOM on packet_receiver
-                                if !disable_mempool {
+                                if !disable_mempool && !is_block_engine_sender_closed {
                                     match block_engine_sender.try_send(BlockEnginePackets {
                                         banking_packet_batch: banking_packet_batch.clone(),
                                         stamp: system_time,
@@ -89,9 +212,15 @@ pub fn start_forward_and_delay_thread(
                                                 num_packets;
                                         }
                                         Err(TrySendError::Closed(_)) => {
-                                            panic!(
-                                                "error sending packet batch to block engine handler"
+                                            // keep forwarding to validators without the block engine
+                                            error!("block engine handler is gone, no longer forwarding packets to it");
+                                            datapoint_error!(
+                                                "forwarder-block_engine_sender_closed",
+                                                ("thread_id", thread_id, i64)
                                             );
+                                            is_block_engine_sender_closed = true;
+                                            is_block_engine_failed.store(true, Ordering::Relaxed);
+                                            forwarder_metrics.num_be_packets_dropped += num_packets;
                                         }
                                         Err(TrySendError::Full(_)) => {
                                             // block engine most likely not connected
@@ -100,39 +229,117 @@ pub fn start_forward_and_delay_thread(
                                         }
                                     }
                                 }
-                                buffered_packet_batches.push_back(RelayerPacketBatches {
-                                    stamp: instant,
-                                    banking_packet_batch,
-                                });
+
+                                if overload_policy == OverloadPolicy::DropNewest
+                                    && buffered_bytes + num_bytes > buffer_max_bytes
+                                {
+                                    forwarder_metrics.num_relayer_packets_dropped_newest +=
+                                        num_packets;
+                                } else {
+                                    buffered_bytes += num_bytes;
+                                    buffered_packet_batches.push_back(BufferedPacketBatches {
+                                        packet_batches: RelayerPacketBatches {
+                                            stamps: PacketBatchStamps {
+                                                quic_received,
+                                                sigverified: instant,
+                                                released: None,
+                                            },
+                                            banking_packet_batch,
+                                            tracer_packets: tracer_packets
+                                                .into_iter()
+                                                .map(|(signature, quic_received)| TracerPacket {
+                                                    signature,
+                                                    quic_received,
+                                                })
+                                                .collect(),
+                                        },
+                                        num_packets,
+                                        num_bytes,
+                                    });
+                                }
+
+                                if overload_policy == OverloadPolicy::DropOldest {
+                                    while buffered_bytes > buffer_max_bytes {
+                                        if let Some(oldest) = buffered_packet_batches.pop_front() {
+                                            buffered_bytes -= oldest.num_bytes;
+                                            forwarder_metrics.num_relayer_packets_dropped_oldest +=
+                                                oldest.num_packets;
+                                        } else {
+                                            break;
+                                        }
+                                    }
+                                }
                             }
                             Err(RecvTimeoutError::Timeout) => {}
                             Err(RecvTimeoutError::Disconnected) => {
-                                panic!("packet receiver disconnected");
+                                // nothing left to forward once the TPU is gone, shut down gracefully
+                                error!("packet receiver disconnected, exiting");
+                                datapoint_error!(
+                                    "forwarder-verified_receiver_disconnected",
+                                    ("thread_id", thread_id, i64)
+                                );
+                                exit.store(true, Ordering::Relaxed);
+                                break;
                             }
                         }
 
-                        while let Some(packet_batches) = buffered_packet_batches.front() {
-                            if packet_batches.stamp.elapsed() < packet_delay {
+                        while let Some(buffered) = buffered_packet_batches.front() {
+                            if buffered.packet_batches.stamps.sigverified.elapsed() < packet_delay
+                            {
                                 break;
                             }
-                            let batch = buffered_packet_batches.pop_front().unwrap();
-
-                            let num_packets = batch
-                                .banking_packet_batch
-                                .0
-                                .iter()
-                                .map(|b| b.len() as u64)
-                                .sum::<u64>();
-
-                            forwarder_metrics.num_relayer_packets_forwarded += num_packets;
-                            delay_packet_sender
-                                .send(batch)
-                                .expect("exiting forwarding delayed packets");
+                            let mut buffered = buffered_packet_batches.pop_front().unwrap();
+                            buffered.packet_batches.stamps.released = Some(Instant::now());
+                            let mirror_batch = packet_mirror_sender.as_ref().map(|_| {
+                                buffered.packet_batches.banking_packet_batch.clone()
+                            });
+
+                            if overload_policy == OverloadPolicy::Block {
+                                if delay_packet_sender.send(buffered.packet_batches).is_err() {
+                                    delay_sender_disconnected(thread_id, &exit);
+                                    break;
+                                }
+                            } else {
+                                // keep the batch buffered if the relayer is backed up, it's subject
+                                // to the overload policy once the buffer fills up
+                                match delay_packet_sender.try_send(buffered.packet_batches) {
+                                    Ok(_) => {}
+                                    Err(crossbeam_channel::TrySendError::Full(packet_batches)) => {
+                                        forwarder_metrics.num_relayer_sender_full += 1;
+                                        buffered_packet_batches.push_front(BufferedPacketBatches {
+                                            packet_batches,
+                                            ..buffered
+                                        });
+                                        break;
+                                    }
+                                    Err(crossbeam_channel::TrySendError::Disconnected(_)) => {
+                                        delay_sender_disconnected(thread_id, &exit);
+                                        break;
+                                    }
+                                }
+                            }
+                            buffered_bytes -= buffered.num_bytes;
+                            forwarder_metrics.num_relayer_packets_forwarded += buffered.num_packets;
+
+                            if let (Some(sender), Some(batch)) =
+                                (&packet_mirror_sender, mirror_batch)
+                            {
+                                match sender.try_send(batch) {
+                                    Ok(_) => {
+                                        forwarder_metrics.num_mirror_packets_forwarded +=
+                                            buffered.num_packets;
+                                    }
+                                    Err(_) => {
+                                        forwarder_metrics.num_mirror_packets_dropped +=
+                                            buffered.num_packets;
+                                    }
+                                }
+                            }
                         }
 
                         forwarder_metrics.update_queue_lengths(
                             buffered_packet_batches.len(),
-                            buffered_packet_batches.capacity(),
+                            buffered_bytes,
                             verified_receiver.len(),
                             BLOCK_ENGINE_FORWARDER_QUEUE_CAPACITY - block_engine_sender.capacity(),
                         );
@@ -143,6 +350,16 @@ pub fn start_forward_and_delay_thread(
         .collect()
 }
 
+/// The relayer stopped taking delayed packets, so the forwarder shuts down gracefully
+fn delay_sender_disconnected(thread_id: u64, exit: &AtomicBool) {
+    error!("delay packet sender disconnected, exiting");
+    datapoint_error!(
+        "forwarder-delay_sender_disconnected",
+        ("thread_id", thread_id, i64)
+    );
+    exit.store(true, Ordering::Relaxed);
+}
+
 struct ForwarderMetrics {
     pub num_batches_received: u64,
     pub num_packets_received: u64,
@@ -153,9 +370,25 @@ struct ForwarderMetrics {
 
     pub num_relayer_packets_forwarded: u64,
 
+    // packet mirror
+    pub num_mirror_packets_forwarded: u64,
+    pub num_mirror_packets_dropped: u64,
+
+    // blockhash filtering
+    pub num_expired_blockhash_packets_dropped: u64,
+    pub num_unknown_blockhash_packets: u64,
+    pub num_durable_nonce_packets: u64,
+
+    // overload handling
+    pub num_ingest_blocked: u64,
+    pub num_relayer_sender_full: u64,
+    pub num_relayer_packets_dropped_oldest: u64,
+    pub num_relayer_packets_dropped_newest: u64,
+
     // high water mark on queue lengths
     pub buffered_packet_batches_max_len: usize,
-    pub buffered_packet_batches_capacity: usize,
+    pub buffered_packet_batches_max_bytes: usize,
+    pub buffered_packet_batches_bytes_budget: usize,
     pub verified_receiver_max_len: usize,
     pub verified_receiver_capacity: usize,
     pub block_engine_sender_max_len: usize,
@@ -164,7 +397,7 @@ struct ForwarderMetrics {
 
 impl ForwarderMetrics {
     pub fn new(
-        buffered_packet_batches_capacity: usize,
+        buffered_packet_batches_bytes_budget: usize,
         verified_receiver_capacity: usize,
         block_engine_sender_capacity: usize,
     ) -> Self {
@@ -175,8 +408,18 @@ impl ForwarderMetrics {
             num_be_packets_dropped: 0,
             num_be_sender_full: 0,
             num_relayer_packets_forwarded: 0,
+            num_mirror_packets_forwarded: 0,
+            num_mirror_packets_dropped: 0,
+            num_expired_blockhash_packets_dropped: 0,
+            num_unknown_blockhash_packets: 0,
+            num_durable_nonce_packets: 0,
+            num_ingest_blocked: 0,
+            num_relayer_sender_full: 0,
+            num_relayer_packets_dropped_oldest: 0,
+            num_relayer_packets_dropped_newest: 0,
             buffered_packet_batches_max_len: 0,
-            buffered_packet_batches_capacity,
+            buffered_packet_batches_max_bytes: 0,
+            buffered_packet_batches_bytes_budget,
             verified_receiver_max_len: 0,
             verified_receiver_capacity,
             block_engine_sender_max_len: 0,
@@ -187,7 +430,7 @@ impl ForwarderMetrics {
     pub fn update_queue_lengths(
         &mut self,
         buffered_packet_batches_len: usize,
-        buffered_packet_batches_capacity: usize,
+        buffered_packet_batches_bytes: usize,
         verified_receiver_len: usize,
         block_engine_sender_len: usize,
     ) {
@@ -195,9 +438,9 @@ impl ForwarderMetrics {
             self.buffered_packet_batches_max_len,
             buffered_packet_batches_len,
         );
-        self.buffered_packet_batches_capacity = std::cmp::max(
-            self.buffered_packet_batches_capacity,
-            buffered_packet_batches_capacity,
+        self.buffered_packet_batches_max_bytes = std::cmp::max(
+            self.buffered_packet_batches_max_bytes,
+            buffered_packet_batches_bytes,
         );
         self.verified_receiver_max_len =
             std::cmp::max(self.verified_receiver_max_len, verified_receiver_len);
@@ -227,6 +470,46 @@ impl ForwarderMetrics {
                 self.num_relayer_packets_forwarded,
                 i64
             ),
+            // Packet mirror metrics
+            (
+                "num_mirror_packets_forwarded",
+                self.num_mirror_packets_forwarded,
+                i64
+            ),
+            (
+                "num_mirror_packets_dropped",
+                self.num_mirror_packets_dropped,
+                i64
+            ),
+            // Blockhash filter metrics
+            (
+                "num_expired_blockhash_packets_dropped",
+                self.num_expired_blockhash_packets_dropped,
+                i64
+            ),
+            (
+                "num_unknown_blockhash_packets",
+                self.num_unknown_blockhash_packets,
+                i64
+            ),
+            (
+                "num_durable_nonce_packets",
+                self.num_durable_nonce_packets,
+                i64
+            ),
+            // Overload metrics
+            ("num_ingest_blocked", self.num_ingest_blocked, i64),
+            ("num_relayer_sender_full", self.num_relayer_sender_full, i64),
+            (
+                "num_relayer_packets_dropped_oldest",
+                self.num_relayer_packets_dropped_oldest,
+                i64
+            ),
+            (
+                "num_relayer_packets_dropped_newest",
+                self.num_relayer_packets_dropped_newest,
+                i64
+            ),
             // Channel stats
             (
                 "buffered_packet_batches_len",
@@ -234,8 +517,13 @@ impl ForwarderMetrics {
                 i64
             ),
             (
-                "buffered_packet_batches_capacity",
-                self.buffered_packet_batches_capacity,
+                "buffered_packet_batches_bytes",
+                self.buffered_packet_batches_max_bytes,
+                i64
+            ),
+            (
+                "buffered_packet_batches_bytes_budget",
+                self.buffered_packet_batches_bytes_budget,
                 i64
             ),
             ("verified_receiver_len", self.verified_receiver_max_len, i64),
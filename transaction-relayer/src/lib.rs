@@ -1 +1,3 @@
 pub mod forwarder;
+pub mod packet_delay;
+pub mod packet_mirror;
@@ -1,12 +1,12 @@
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     fs,
     net::{IpAddr, Ipv4Addr, SocketAddr},
     ops::Range,
     path::PathBuf,
     str::FromStr,
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicU32, Ordering},
         Arc,
     },
     thread,
@@ -19,7 +19,15 @@ use clap::Parser;
 use crossbeam_channel::tick;
 use dashmap::DashMap;
 use env_logger::Env;
-use jito_block_engine::block_engine::{BlockEngineConfig, BlockEngineRelayerHandler};
+use jito_block_engine::{
+    block_engine::{
+        BlockEngineConfig, BlockEnginePolicy, BlockEngineRelayerHandler,
+        BLOCK_ENGINE_RUNTIME_THREAD_NAME,
+    },
+    block_engine_selector::BlockEngineSelectorConfig,
+    packet_coalescer::PacketCoalescerConfig,
+    static_interest::{StaticInterestHandle, StaticInterestUpdater},
+};
 use jito_core::{
     graceful_panic,
     tpu::{Tpu, TpuSockets},
@@ -30,13 +38,24 @@ use jito_protos::{
 use jito_relayer::{
     auth_interceptor::AuthInterceptor,
     auth_service::{AuthServiceImpl, ValidatorAuther},
-    health_manager::HealthManager,
-    relayer::RelayerImpl,
+    blockhash_cache::BlockhashCacheUpdater,
+    health_manager::{HealthManager, HealthPolicy},
+    leader_fallback::LeaderFallbackConfig,
+    packet_filter::{PacketFilter, PacketFilterConfig},
+    packet_quota::PacketQuotas,
+    relayer::{RelayerImpl, SessionPolicy},
     schedule_cache::{LeaderScheduleCacheUpdater, LeaderScheduleUpdatingHandle},
+    session_log::SessionLog,
+    slow_consumer::SlowConsumerPolicy,
+    tpu_port_assigner::{parse_tpu_port_override, TpuPortAssigner, TpuPorts},
 };
 use jito_relayer_web::{start_relayer_web_server, RelayerState};
 use jito_rpc::load_balancer::LoadBalancer;
-use jito_transaction_relayer::forwarder::start_forward_and_delay_thread;
+use jito_transaction_relayer::{
+    forwarder::{start_forward_and_delay_thread, OverloadPolicy},
+    packet_delay::AdaptivePacketDelay,
+    packet_mirror::PacketMirror,
+};
 use jwt::{AlgorithmType, PKeyWithDigest};
 use log::{debug, error, info, warn};
 use openssl::{hash::MessageDigest, pkey::PKey};
@@ -94,6 +113,12 @@ struct Args {
     #[arg(long, env, default_value_t = 1)]
     num_tpu_fwd_quic_servers: u16,
 
+    /// Space-separated validators pinned to specific ports in get_tpu_configs, formatted as
+    /// pubkey:tpu_quic_port:tpu_quic_fwd_port. Other validators get ports by consistent hashing
+    /// on their identity, balanced by stake.
+    #[arg(long, env, value_delimiter = ' ', value_parser = parse_tpu_port_override)]
+    tpu_port_overrides: Option<Vec<(Pubkey, TpuPorts)>>,
+
     /// Bind IP address for GRPC server
     #[arg(long, env, default_value_t = IpAddr::V4(std::net::Ipv4Addr::new(0, 0, 0, 0)))]
     grpc_bind_ip: IpAddr,
@@ -132,15 +157,120 @@ struct Args {
     #[arg(long, env, default_value_t = 200)]
     packet_delay_ms: u32,
 
-    /// Address for Jito Block Engine.
-    /// See https://jito-labs.gitbook.io/mev/searcher-resources/block-engine#connection-details
+    /// Shrink the packet delay to degraded_packet_delay_ms while the block engine is disconnected,
+    /// failed or disabled, since the delay only adds latency without it
+    #[arg(long, env, default_value_t = false)]
+    adaptive_packet_delay: bool,
+
+    /// Packet delay in milliseconds used by adaptive_packet_delay while the block engine is down
+    #[arg(long, env, default_value_t = 0)]
+    degraded_packet_delay_ms: u32,
+
+    /// How long the block engine needs to stay healthy before adaptive_packet_delay restores
+    /// packet_delay_ms, in milliseconds
+    #[arg(long, env, default_value_t = 10_000)]
+    packet_delay_recovery_ms: u64,
+
+    /// What the forwarder does when the relayer can't keep up with incoming packets.
+    /// block: stop ingesting packets until the delay buffer drains (stalls the block engine path too).
+    /// drop-oldest: evict the oldest delayed packets to make room for new ones.
+    /// drop-newest: reject new packets while the delay buffer is full.
+    #[arg(long, env, value_enum, default_value_t = OverloadPolicy::Block)]
+    forwarder_overload_policy: OverloadPolicy,
+
+    /// Memory budget in bytes for packets held in the forwarder's delay buffer
+    #[arg(long, env, default_value_t = 1_073_741_824)]
+    forwarder_buffer_max_bytes: usize,
+
+    /// Drop transactions whose recent blockhash has expired instead of forwarding them to
+    /// validators and the block engine. Durable nonce transactions are always forwarded.
+    #[arg(long, env, default_value_t = false)]
+    drop_expired_blockhash_packets: bool,
+
+    /// How frequently to poll RPC for the latest blockhash, in milliseconds
+    #[arg(long, env, default_value_t = 400)]
+    blockhash_refresh_ms: u64,
+
+    /// Report a datapoint with per-stage latencies for every tracer packet forwarded to validators.
+    /// Also records the QUIC receive time of packets on their way to sigverify, so latencies
+    /// include QUIC receipt and sigverify
+    #[arg(long, env, default_value_t = false)]
+    trace_tracer_packets: bool,
+
+    /// Path of a unix domain socket to mirror the verified, post-delay packet stream to.
+    /// Consumers receive length-delimited protobuf PacketBatch messages.
     #[arg(long, env)]
-    block_engine_url: Option<String>,
+    packet_mirror_socket_path: Option<PathBuf>,
+
+    /// Number of packet batches buffered for the packet mirror before dropping
+    #[arg(long, env, default_value_t = 10_000)]
+    packet_mirror_queue_capacity: usize,
+
+    /// Addresses for Jito Block Engines, space separated.
+    /// See https://jito-labs.gitbook.io/mev/searcher-resources/block-engine#connection-details
+    #[arg(long, env, value_delimiter = ' ')]
+    block_engine_url: Option<Vec<String>>,
+
+    /// Manual override for authentication service addresses of the block engines, space
+    /// separated and paired with `--block-engine-url` by position.
+    /// Defaults to the matching `--block-engine-url`
+    #[arg(long, env, value_delimiter = ' ')]
+    block_engine_auth_service_url: Option<Vec<String>>,
+
+    /// Block engines, from `--block-engine-url`, sent every verified transaction instead of only
+    /// the ones touching their accounts and programs of interest, space separated. OFAC filtering
+    /// and the static interest deny list still apply
+    #[arg(long, env, value_delimiter = ' ')]
+    block_engine_forward_all: Option<Vec<String>>,
+
+    /// How packets are spread across block engines.
+    /// fan-out: send every packet to every connected block engine.
+    /// failover: send packets only to the first connected block engine, in the order given.
+    /// fastest: connect only to the block engine with the lowest connect and auth round-trip time.
+    #[arg(long, env, default_value = "fan-out", value_parser = BlockEnginePolicy::from_str)]
+    block_engine_policy: BlockEnginePolicy,
+
+    /// Bytes of the newest packets buffered while a block engine is disconnected that are forwarded
+    /// to it after reconnecting, if they haven't expired. Packets expire once the packet delay they
+    /// were forwarded with has passed, degraded_packet_delay_ms under adaptive_packet_delay, so
+    /// only disconnects shorter than that are covered. 0 drops everything sent while disconnected
+    #[arg(long, env, default_value_t = 0)]
+    block_engine_replay_buffer_bytes: usize,
 
-    /// Manual override for authentication service address of the block-engine.
-    /// Defaults to `--block-engine-url`
+    /// Filtered packets are merged into messages of up to this many packets before being sent to
+    /// a block engine. 0 or 1 sends every batch from the forwarder as its own message
+    #[arg(long, env, default_value_t = 0)]
+    block_engine_coalesce_max_packets: usize,
+
+    /// How long filtered packets can wait to be merged with later ones before being sent to a
+    /// block engine
+    #[arg(long, env, default_value_t = 1_000)]
+    block_engine_coalesce_max_delay_us: u64,
+
+    /// Path to a yaml file of accounts and programs always or never forwarded to block engines,
+    /// e.g. { allow_accounts: [...], allow_programs: [...], deny_accounts: [...], deny_programs: [...] }.
+    /// Allowed pubkeys are treated like accounts and programs of interest sent by the block engine,
+    /// transactions referencing a denied pubkey are never forwarded. Denied accounts loaded through
+    /// a lookup table missing from the relayer's cache can't be seen, so those transactions are
+    /// still forwarded. Reloaded when the file changes.
     #[arg(long, env)]
-    block_engine_auth_service_url: Option<String>,
+    block_engine_static_interest_path: Option<PathBuf>,
+
+    /// How often block engines are probed under the fastest block engine policy
+    #[arg(long, env, default_value_t = 60)]
+    block_engine_probe_interval_secs: u64,
+
+    /// Block engine probes taking longer than this count as failed
+    #[arg(long, env, default_value_t = 5_000)]
+    block_engine_probe_timeout_ms: u64,
+
+    /// How much faster another block engine must probe than the connected one to switch to it
+    #[arg(long, env, default_value_t = 10)]
+    block_engine_switch_margin_ms: u64,
+
+    /// How long another block engine must stay faster by the margin before switching to it
+    #[arg(long, env, default_value_t = 300)]
+    block_engine_switch_hold_secs: u64,
 
     /// Path to keypair file used to authenticate with the backend.
     #[arg(long, env)]
@@ -151,6 +281,47 @@ struct Args {
     #[arg(long, env, value_delimiter = ',')]
     allowed_validators: Option<Vec<Pubkey>>,
 
+    /// Space-separated validator identities allowed to hold several packet subscriptions at once,
+    /// formatted as pubkey:policy. Policy is one of all, newest-only or primary-backup.
+    /// Identities not listed here only keep their newest subscription.
+    #[arg(long, env, value_delimiter = ' ', value_parser = parse_session_policy)]
+    multi_session_validators: Option<Vec<(Pubkey, SessionPolicy)>>,
+
+    /// Packets per second a validator may be sent, across all its sessions. Packets over the
+    /// quota are dropped. If unset, validators are only limited by their packet channel.
+    #[arg(long, env, value_parser = clap::value_parser!(u64).range(1..))]
+    default_validator_packet_quota: Option<u64>,
+
+    /// Space-separated per-validator packet quotas, formatted as pubkey:packets_per_second.
+    /// Overrides --default-validator-packet-quota.
+    #[arg(long, env, value_delimiter = ' ', value_parser = parse_packet_quota)]
+    validator_packet_quotas: Option<Vec<(Pubkey, u64)>>,
+
+    /// Send packets directly to the TPU QUIC port of upcoming leaders that don't have a packet
+    /// subscription to this relayer, signed with the relayer's keypair
+    #[arg(long, env, default_value_t = false)]
+    enable_leader_fallback: bool,
+
+    /// Space-separated leader identities that never get packets through the leader fallback
+    #[arg(long, env, value_delimiter = ' ', value_parser = Pubkey::from_str)]
+    leader_fallback_opt_out: Option<Vec<Pubkey>>,
+
+    /// Path to a yaml file of per-validator packet filters, keyed on validator identity, e.g.
+    /// <pubkey>: { exclude_programs: [...], exclude_accounts: [...], non_forwarded_only: true }.
+    /// Applied on top of any filters the validator requests when subscribing.
+    #[arg(long, env)]
+    packet_filters_path: Option<PathBuf>,
+
+    /// Number of ended validator sessions kept in memory for the /sessions endpoint
+    #[arg(long, env, default_value_t = 10_000)]
+    session_log_capacity: usize,
+
+    /// If set, ended validator sessions are appended to this JSON lines file and reloaded on
+    /// startup. The file is compacted to the last `--session-log-capacity` sessions whenever it
+    /// grows to twice that
+    #[arg(long, env)]
+    session_log_path: Option<PathBuf>,
+
     /// The private key used to sign tokens by this server.
     #[arg(long, env)]
     signing_key_pem_path: PathBuf,
@@ -175,10 +346,24 @@ struct Args {
     #[arg(long, env, default_value_t = 180)]
     challenge_expiration_sleep_interval_secs: u64,
 
-    /// How long it takes to miss a slot for the system to be considered unhealthy
+    /// How long it takes to miss a slot for the system to be considered degraded. Degraded
+    /// relayers keep existing validator streams but refuse new ones.
     #[arg(long, env, default_value_t = 10)]
     missing_slot_unhealthy_secs: u64,
 
+    /// How long the relayer may stay degraded before it's unhealthy and drops validators
+    #[arg(long, env, default_value_t = 0)]
+    degraded_grace_secs: u64,
+
+    /// How long slots must arrive on time before a degraded or unhealthy relayer is healthy again
+    #[arg(long, env, default_value_t = 0)]
+    health_recovery_secs: u64,
+
+    /// When unhealthy, validators are disconnected gradually over this many milliseconds instead
+    /// of all at once
+    #[arg(long, env, default_value_t = 0)]
+    unhealthy_disconnect_spread_ms: u64,
+
     /// DEPRECATED. Solana cluster name (mainnet-beta, testnet, devnet, ...)
     #[arg(long, env)]
     cluster: Option<String>,
@@ -217,6 +402,20 @@ struct Args {
     #[arg(long, env, default_value_t = 4)]
     validator_packet_batch_size: usize,
 
+    /// Largest packet batch sent to a validator that's falling behind. Batches grow from
+    /// validator_packet_batch_size as a validator's queue fills up.
+    #[arg(long, env, default_value_t = 32)]
+    max_validator_packet_batch_size: usize,
+
+    /// Fraction of a validator's packet queue in use at which it's considered slow
+    #[arg(long, env, default_value_t = 0.5)]
+    slow_consumer_queue_threshold: f64,
+
+    /// Evict validator sessions that stay slow for this many milliseconds. If unset, slow
+    /// sessions are never evicted.
+    #[arg(long, env)]
+    slow_consumer_eviction_ms: Option<u64>,
+
     /// Disable Mempool forwarding
     #[arg(long, env, default_value_t = false)]
     disable_mempool: bool,
@@ -227,6 +426,15 @@ struct Args {
     #[arg(long, env, default_value_t = false)]
     forward_all: bool,
 
+    /// Accept validator subscriptions and run filtering and fan-out as usual, but only send
+    /// heartbeats. Packets that would have been sent are counted, for canarying a new relayer.
+    #[arg(long, env, default_value_t = false)]
+    shadow_mode: bool,
+
+    /// Status URL base of the relayer to compare against in shadow mode, ie http://relayer:11227
+    #[arg(long, env)]
+    reference_relayer_url: Option<String>,
+
     /// Staked Nodes Overrides Path
     /// Provide path to a yaml file with custom overrides for stakes of specific
     ///  identities. Overriding the amount of stake this validator considers as valid
@@ -239,6 +447,17 @@ struct Args {
     /// The slot lookahead to use when forwarding transactions
     #[arg(long, env, default_value_t = 5)]
     slot_lookahead: u64,
+
+    /// Space-separated per-validator slot lookaheads, formatted as pubkey:slots. A listed
+    /// validator starts getting packets this many slots before its leader slot instead of
+    /// --slot-lookahead.
+    #[arg(long, env, value_delimiter = ' ', value_parser = parse_slot_lookahead_override)]
+    slot_lookahead_overrides: Option<Vec<(Pubkey, u64)>>,
+
+    /// Number of threads fanning packets out to subscribed validators. Validators are sharded
+    /// across the threads by identity.
+    #[arg(long, env, default_value_t = 4)]
+    num_fan_out_workers: usize,
 }
 
 #[derive(Debug)]
@@ -396,12 +615,16 @@ fn main() {
         keypair.pubkey()
     ));
     info!("Relayer started with pubkey: {}", keypair.pubkey());
+    if args.shadow_mode {
+        warn!("shadow mode enabled, no packets will be forwarded to validators");
+    }
     datapoint_info!(
         "relayer-mempool-enabled",
         ("mempool_enabled", !args.disable_mempool, bool)
     );
 
-    let exit = graceful_panic(None);
+    // the block engine handler restarts its connection task if it panics
+    let exit = graceful_panic(None, &[BLOCK_ENGINE_RUNTIME_THREAD_NAME]);
 
     assert_eq!(
         args.rpc_servers.len(),
@@ -437,14 +660,10 @@ fn main() {
     let staked_nodes_overrides = match args.staked_nodes_overrides {
         None => StakedNodesOverrides::default(),
         Some(p) => {
-            let file = fs::File::open(&p).expect(&format!(
-                "Failed to open staked nodes overrides file: {:?}",
-                &p
-            ));
-            serde_yaml::from_reader(file).expect(&format!(
-                "Failed to read staked nodes overrides file: {:?}",
-                &p,
-            ))
+            let file = fs::File::open(&p)
+                .unwrap_or_else(|_| panic!("Failed to open staked nodes overrides file: {:?}", &p));
+            serde_yaml::from_reader(file)
+                .unwrap_or_else(|_| panic!("Failed to read staked nodes overrides file: {:?}", &p))
         }
     };
     let (tpu, verified_receiver) = Tpu::new(
@@ -455,52 +674,124 @@ fn main() {
         args.max_unstaked_quic_connections,
         args.max_staked_quic_connections,
         staked_nodes_overrides.staked_map_id,
+        args.trace_tracer_packets,
     );
 
     let leader_cache = LeaderScheduleCacheUpdater::new(&rpc_load_balancer, &exit);
 
-    // receiver tracked as relayer_metrics.delay_packet_receiver_len
+    let blockhash_cache = args.drop_expired_blockhash_packets.then(|| {
+        BlockhashCacheUpdater::new(
+            &rpc_load_balancer,
+            Duration::from_millis(args.blockhash_refresh_ms),
+            &exit,
+        )
+    });
+
+    let packet_mirror = args.packet_mirror_socket_path.clone().map(|socket_path| {
+        PacketMirror::new(socket_path, args.packet_mirror_queue_capacity, &exit)
+            .expect("failed to start packet mirror")
+    });
+
+    // receiver tracked as relayer_fan_out_dispatch_metrics.delay_packet_receiver_len
     let (delay_packet_sender, delay_packet_receiver) =
         crossbeam_channel::bounded(Tpu::TPU_QUEUE_CAPACITY);
 
-    // NOTE: make sure the channel here isn't too big because it will get backed up
-    // with packets when the block engine isn't connected
+    // drained by the block engine dispatcher, which drops packets for block engines that can't keep up
     // tracked as forwarder_metrics.block_engine_sender_len
     let (block_engine_sender, block_engine_receiver) =
         channel(jito_transaction_relayer::forwarder::BLOCK_ENGINE_FORWARDER_QUEUE_CAPACITY);
 
+    let is_connected_to_block_engine = Arc::new(AtomicBool::new(false));
+    let is_block_engine_failed = Arc::new(AtomicBool::new(false));
+
+    let effective_packet_delay_ms = Arc::new(AtomicU32::new(args.packet_delay_ms));
+    let adaptive_packet_delay = args.adaptive_packet_delay.then(|| {
+        AdaptivePacketDelay::new(
+            &effective_packet_delay_ms,
+            args.packet_delay_ms,
+            args.degraded_packet_delay_ms,
+            Duration::from_millis(args.packet_delay_recovery_ms),
+            args.disable_mempool,
+            &is_connected_to_block_engine,
+            &is_block_engine_failed,
+            &exit,
+        )
+    });
+
     let forward_and_delay_threads = start_forward_and_delay_thread(
         verified_receiver,
         delay_packet_sender,
-        args.packet_delay_ms,
+        &effective_packet_delay_ms,
         block_engine_sender,
         1,
         args.disable_mempool,
+        args.forwarder_overload_policy,
+        args.forwarder_buffer_max_bytes,
+        blockhash_cache.as_ref().map(|c| c.handle()),
+        packet_mirror.as_ref().map(|m| m.sender()),
+        tpu.quic_receive_times(),
+        &is_block_engine_failed,
         &exit,
     );
 
-    let is_connected_to_block_engine = Arc::new(AtomicBool::new(false));
-    let block_engine_config = if !args.disable_mempool && args.block_engine_url.is_some() {
-        let block_engine_url = args.block_engine_url.unwrap();
-        let auth_service_url = args
-            .block_engine_auth_service_url
-            .unwrap_or(block_engine_url.clone());
-        Some(BlockEngineConfig {
-            block_engine_url,
-            auth_service_url,
-        })
+    let block_engine_configs: Vec<BlockEngineConfig> = if args.disable_mempool {
+        Vec::new()
     } else {
-        None
+        let block_engine_urls = args.block_engine_url.unwrap_or_default();
+        let auth_service_urls = args.block_engine_auth_service_url.unwrap_or_default();
+        let forward_all_urls: HashSet<String> = args
+            .block_engine_forward_all
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        if let Some(url) = forward_all_urls
+            .iter()
+            .find(|url| !block_engine_urls.contains(url))
+        {
+            panic!("Forward-all block engine {url} isn't in --block-engine-url");
+        }
+        block_engine_urls
+            .into_iter()
+            .enumerate()
+            .map(|(i, block_engine_url)| BlockEngineConfig {
+                auth_service_url: auth_service_urls
+                    .get(i)
+                    .cloned()
+                    .unwrap_or(block_engine_url.clone()),
+                forward_all: forward_all_urls.contains(&block_engine_url),
+                block_engine_url,
+            })
+            .collect()
     };
+    let static_interest_updater = args.block_engine_static_interest_path.map(|p| {
+        StaticInterestUpdater::new(p, &exit)
+            .unwrap_or_else(|e| panic!("Invalid block engine static interest file: {e}"))
+    });
     let block_engine_forwarder = BlockEngineRelayerHandler::new(
-        block_engine_config,
+        block_engine_configs,
+        args.block_engine_policy,
+        BlockEngineSelectorConfig {
+            probe_interval: Duration::from_secs(args.block_engine_probe_interval_secs),
+            probe_timeout: Duration::from_millis(args.block_engine_probe_timeout_ms),
+            switch_margin: Duration::from_millis(args.block_engine_switch_margin_ms),
+            switch_hold: Duration::from_secs(args.block_engine_switch_hold_secs),
+        },
         block_engine_receiver,
-        keypair,
+        keypair.clone(),
         exit.clone(),
         args.aoi_cache_ttl_secs,
+        args.block_engine_replay_buffer_bytes,
+        PacketCoalescerConfig {
+            max_packets: args.block_engine_coalesce_max_packets,
+            max_delay: Duration::from_micros(args.block_engine_coalesce_max_delay_us),
+        },
         address_lookup_table_cache.clone(),
         &is_connected_to_block_engine,
+        &is_block_engine_failed,
         ofac_addresses.clone(),
+        static_interest_updater
+            .as_ref()
+            .map_or_else(StaticInterestHandle::default, |u| u.handle()),
     );
 
     // receiver tracked as relayer_metrics.slot_receiver_len
@@ -510,18 +801,47 @@ fn main() {
     let health_manager = HealthManager::new(
         slot_receiver,
         downstream_slot_sender,
-        Duration::from_secs(args.missing_slot_unhealthy_secs),
+        HealthPolicy {
+            missing_slot_threshold: Duration::from_secs(args.missing_slot_unhealthy_secs),
+            degraded_grace_period: Duration::from_secs(args.degraded_grace_secs),
+            recovery_period: Duration::from_secs(args.health_recovery_secs),
+        },
         exit.clone(),
     );
 
     let server_addr = SocketAddr::new(args.grpc_bind_ip, args.grpc_bind_port);
+    let packet_filters = match &args.packet_filters_path {
+        None => HashMap::new(),
+        Some(p) => {
+            let file = fs::File::open(p)
+                .unwrap_or_else(|_| panic!("Failed to open packet filters file: {p:?}"));
+            let configs: HashMap<String, PacketFilterConfig> = serde_yaml::from_reader(file)
+                .unwrap_or_else(|_| panic!("Failed to read packet filters file: {p:?}"));
+            PacketFilter::from_configs(&configs)
+                .unwrap_or_else(|e| panic!("Invalid packet filters file {p:?}: {e}"))
+        }
+    };
+
+    let session_log = SessionLog::new(args.session_log_capacity, args.session_log_path.as_deref())
+        .unwrap_or_else(|e| panic!("Failed to open session log: {e}"));
+
+    let tpu_port_assigner = TpuPortAssigner::new(
+        tpu_quic_ports,
+        tpu_quic_fwd_ports,
+        tpu.staked_nodes(),
+        args.tpu_port_overrides
+            .unwrap_or_default()
+            .into_iter()
+            .collect(),
+    )
+    .unwrap_or_else(|e| panic!("Invalid tpu port overrides: {e}"));
+
     let relayer_svc = RelayerImpl::new(
         downstream_slot_receiver,
         delay_packet_receiver,
         leader_cache.handle(),
         public_ip,
-        tpu_quic_ports,
-        tpu_quic_fwd_ports,
+        tpu_port_assigner,
         health_manager.handle(),
         exit.clone(),
         ofac_addresses,
@@ -529,6 +849,42 @@ fn main() {
         args.validator_packet_batch_size,
         args.forward_all,
         args.slot_lookahead,
+        args.slot_lookahead_overrides
+            .unwrap_or_default()
+            .into_iter()
+            .collect(),
+        args.trace_tracer_packets,
+        args.multi_session_validators
+            .unwrap_or_default()
+            .into_iter()
+            .collect(),
+        packet_filters,
+        args.num_fan_out_workers,
+        session_log,
+        SlowConsumerPolicy {
+            slow_queue_threshold: args.slow_consumer_queue_threshold,
+            eviction_after: args.slow_consumer_eviction_ms.map(Duration::from_millis),
+            max_batch_size: args.max_validator_packet_batch_size,
+        },
+        PacketQuotas {
+            default_pps: args.default_validator_packet_quota,
+            overrides: args
+                .validator_packet_quotas
+                .unwrap_or_default()
+                .into_iter()
+                .collect(),
+        },
+        args.shadow_mode,
+        args.enable_leader_fallback.then(|| LeaderFallbackConfig {
+            keypair,
+            rpc_load_balancer: rpc_load_balancer.clone(),
+            opt_out: args
+                .leader_fallback_opt_out
+                .unwrap_or_default()
+                .into_iter()
+                .collect(),
+        }),
+        Duration::from_millis(args.unhealthy_disconnect_spread_ms),
     );
 
     let priv_key = fs::read(&args.signing_key_pem_path).unwrap_or_else(|_| {
@@ -561,7 +917,11 @@ fn main() {
     let relayer_state = Arc::new(RelayerState::new(
         health_manager.handle(),
         &is_connected_to_block_engine,
+        &is_block_engine_failed,
+        &effective_packet_delay_ms,
         relayer_svc.handle(),
+        args.reference_relayer_url,
+        block_engine_forwarder.handle(),
     ));
 
     let rt = Builder::new_multi_thread().enable_all().build().unwrap();
@@ -607,10 +967,22 @@ fn main() {
     tpu.join().unwrap();
     health_manager.join().unwrap();
     leader_cache.join().unwrap();
+    if let Some(blockhash_cache) = blockhash_cache {
+        blockhash_cache.join().unwrap();
+    }
+    if let Some(adaptive_packet_delay) = adaptive_packet_delay {
+        adaptive_packet_delay.join().unwrap();
+    }
+    if let Some(packet_mirror) = packet_mirror {
+        packet_mirror.join().unwrap();
+    }
     for t in forward_and_delay_threads {
         t.join().unwrap();
     }
     lookup_table_refresher.join().unwrap();
+    if let Some(static_interest_updater) = static_interest_updater {
+        static_interest_updater.join().unwrap();
+    }
     block_engine_forwarder.join();
 }
 
@@ -640,6 +1012,39 @@ pub async fn shutdown_signal(exit: Arc<AtomicBool>) {
     warn!("signal received, starting graceful shutdown");
 }
 
+fn parse_session_policy(arg: &str) -> Result<(Pubkey, SessionPolicy), String> {
+    let (pubkey, policy) = arg
+        .split_once(':')
+        .ok_or_else(|| format!("expected pubkey:policy, got {arg}"))?;
+    let pubkey = Pubkey::from_str(pubkey).map_err(|e| format!("invalid pubkey {pubkey}: {e}"))?;
+    Ok((pubkey, SessionPolicy::from_str(policy)?))
+}
+
+fn parse_slot_lookahead_override(arg: &str) -> Result<(Pubkey, u64), String> {
+    let (pubkey, slots) = arg
+        .split_once(':')
+        .ok_or_else(|| format!("expected pubkey:slots, got {arg}"))?;
+    let pubkey = Pubkey::from_str(pubkey).map_err(|e| format!("invalid pubkey {pubkey}: {e}"))?;
+    let slots = slots
+        .parse()
+        .map_err(|e| format!("invalid slot lookahead {slots}: {e}"))?;
+    Ok((pubkey, slots))
+}
+
+fn parse_packet_quota(arg: &str) -> Result<(Pubkey, u64), String> {
+    let (pubkey, pps) = arg
+        .split_once(':')
+        .ok_or_else(|| format!("expected pubkey:packets_per_second, got {arg}"))?;
+    let pubkey = Pubkey::from_str(pubkey).map_err(|e| format!("invalid pubkey {pubkey}: {e}"))?;
+    let pps = pps
+        .parse()
+        .map_err(|e| format!("invalid packet quota {pps}: {e}"))?;
+    if pps == 0 {
+        return Err(format!("packet quota of {pubkey} must be at least 1"));
+    }
+    Ok((pubkey, pps))
+}
+
 enum ValidatorStore {
     LeaderSchedule(LeaderScheduleUpdatingHandle),
     UserDefined(HashSet<Pubkey>),
@@ -0,0 +1,90 @@
+//! Controls the packet delay applied before forwarding packets to validators. The delay gives the
+//! block engine a head start on packets, so it only adds latency while the mempool path is down.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc,
+    },
+    thread,
+    thread::{sleep, Builder, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use log::info;
+use solana_metrics::datapoint_info;
+
+const UPDATE_INTERVAL: Duration = Duration::from_millis(100);
+
+pub struct AdaptivePacketDelay {
+    refresh_thread: JoinHandle<()>,
+}
+
+impl AdaptivePacketDelay {
+    /// Drops effective_packet_delay_ms to degraded_packet_delay_ms as soon as the block engine path
+    /// is down and restores packet_delay_ms once it has been healthy for recovery_hold.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        effective_packet_delay_ms: &Arc<AtomicU32>,
+        packet_delay_ms: u32,
+        degraded_packet_delay_ms: u32,
+        recovery_hold: Duration,
+        disable_mempool: bool,
+        is_connected_to_block_engine: &Arc<AtomicBool>,
+        is_block_engine_failed: &Arc<AtomicBool>,
+        exit: &Arc<AtomicBool>,
+    ) -> AdaptivePacketDelay {
+        let effective_packet_delay_ms = effective_packet_delay_ms.clone();
+        let is_connected_to_block_engine = is_connected_to_block_engine.clone();
+        let is_block_engine_failed = is_block_engine_failed.clone();
+        let exit = exit.clone();
+
+        let refresh_thread = Builder::new()
+            .name("adaptive_packet_delay".to_string())
+            .spawn(move || {
+                // the block engine starts out disconnected, so start out degraded
+                let mut healthy_since: Option<Instant> = None;
+                let mut is_degraded = true;
+                effective_packet_delay_ms.store(degraded_packet_delay_ms, Ordering::Relaxed);
+
+                while !exit.load(Ordering::Relaxed) {
+                    let is_mempool_healthy = !disable_mempool
+                        && is_connected_to_block_engine.load(Ordering::Relaxed)
+                        && !is_block_engine_failed.load(Ordering::Relaxed);
+
+                    let should_degrade = if is_mempool_healthy {
+                        let healthy_since = *healthy_since.get_or_insert_with(Instant::now);
+                        is_degraded && healthy_since.elapsed() < recovery_hold
+                    } else {
+                        healthy_since = None;
+                        true
+                    };
+
+                    if should_degrade != is_degraded {
+                        is_degraded = should_degrade;
+                        let delay_ms = if is_degraded {
+                            degraded_packet_delay_ms
+                        } else {
+                            packet_delay_ms
+                        };
+                        effective_packet_delay_ms.store(delay_ms, Ordering::Relaxed);
+                        info!("block engine path degraded: {is_degraded}, packet delay now {delay_ms}ms");
+                        datapoint_info!(
+                            "adaptive_packet_delay-update",
+                            ("is_degraded", is_degraded, bool),
+                            ("effective_packet_delay_ms", delay_ms, i64),
+                        );
+                    }
+
+                    sleep(UPDATE_INTERVAL);
+                }
+            })
+            .unwrap();
+
+        AdaptivePacketDelay { refresh_thread }
+    }
+
+    pub fn join(self) -> thread::Result<()> {
+        self.refresh_thread.join()
+    }
+}
@@ -0,0 +1,169 @@
+//! Mirrors the verified, post-delay packet stream to local consumers (e.g. analytics) over a
+//! unix domain socket. Each message is a length-delimited `jito_protos::packet::PacketBatch`.
+//! The mirror has its own bounded queue and drops packets instead of slowing down forwarding.
+
+use std::{
+    fs,
+    io::{self, ErrorKind, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    thread::{Builder, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crossbeam_channel::{bounded, Receiver, RecvTimeoutError, Sender};
+use jito_protos::{convert::packet_to_proto_packet, packet::PacketBatch as ProtoPacketBatch};
+use log::{error, info, warn};
+use prost::Message;
+use solana_core::banking_trace::BankingPacketBatch;
+use solana_metrics::datapoint_info;
+
+/// A consumer that can't take a message within this time is disconnected
+const CLIENT_WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+
+pub struct PacketMirror {
+    sender: Sender<BankingPacketBatch>,
+    thread: JoinHandle<()>,
+}
+
+impl PacketMirror {
+    pub fn new(
+        socket_path: PathBuf,
+        queue_capacity: usize,
+        exit: &Arc<AtomicBool>,
+    ) -> io::Result<PacketMirror> {
+        // clean up the socket left behind by a previous run
+        if socket_path.exists() {
+            fs::remove_file(&socket_path)?;
+        }
+        let listener = UnixListener::bind(&socket_path)?;
+        listener.set_nonblocking(true)?;
+        info!("packet mirror listening at: {socket_path:?}");
+
+        let (sender, receiver) = bounded(queue_capacity);
+        let exit = exit.clone();
+        let thread = Builder::new()
+            .name("packet_mirror".to_string())
+            .spawn(move || Self::run(listener, receiver, exit))
+            .unwrap();
+
+        Ok(PacketMirror { sender, thread })
+    }
+
+    /// Returns the sender side of the mirror queue, use try_send so a slow consumer can't block
+    pub fn sender(&self) -> Sender<BankingPacketBatch> {
+        self.sender.clone()
+    }
+
+    pub fn join(self) -> thread::Result<()> {
+        self.thread.join()
+    }
+
+    fn run(listener: UnixListener, receiver: Receiver<BankingPacketBatch>, exit: Arc<AtomicBool>) {
+        let mut clients: Vec<UnixStream> = Vec::new();
+        let mut metrics = PacketMirrorMetrics::default();
+        let mut last_metrics_upload = Instant::now();
+
+        while !exit.load(Ordering::Relaxed) {
+            Self::accept_clients(&listener, &mut clients, &mut metrics);
+
+            match receiver.recv_timeout(Duration::from_millis(100)) {
+                Ok(banking_packet_batch) => {
+                    let packets: Vec<_> = banking_packet_batch
+                        .0
+                        .iter()
+                        .flat_map(|batch| {
+                            batch
+                                .iter()
+                                .filter(|p| !p.meta().discard())
+                                .filter_map(packet_to_proto_packet)
+                        })
+                        .collect();
+                    if !packets.is_empty() && !clients.is_empty() {
+                        let num_packets = packets.len() as u64;
+                        let message = ProtoPacketBatch { packets }.encode_length_delimited_to_vec();
+
+                        let num_clients = clients.len();
+                        clients.retain_mut(|client| match client.write_all(&message) {
+                            Ok(_) => true,
+                            Err(e) => {
+                                // a partial write breaks framing, so the consumer has to reconnect
+                                warn!("dropping packet mirror client: {e}");
+                                false
+                            }
+                        });
+                        metrics.num_clients_dropped += (num_clients - clients.len()) as u64;
+                        metrics.num_batches_sent += 1;
+                        metrics.num_packets_sent += num_packets;
+                        metrics.num_bytes_sent += (message.len() * clients.len()) as u64;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+
+            if last_metrics_upload.elapsed() >= Duration::from_secs(1) {
+                metrics.report(clients.len(), receiver.len());
+                metrics = PacketMirrorMetrics::default();
+                last_metrics_upload = Instant::now();
+            }
+        }
+    }
+
+    fn accept_clients(
+        listener: &UnixListener,
+        clients: &mut Vec<UnixStream>,
+        metrics: &mut PacketMirrorMetrics,
+    ) {
+        loop {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    if let Err(e) = stream
+                        .set_nonblocking(false)
+                        .and_then(|_| stream.set_write_timeout(Some(CLIENT_WRITE_TIMEOUT)))
+                    {
+                        error!("error configuring packet mirror client: {e}");
+                        continue;
+                    }
+                    info!("packet mirror client connected");
+                    metrics.num_clients_connected += 1;
+                    clients.push(stream);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    error!("error accepting packet mirror client: {e}");
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct PacketMirrorMetrics {
+    num_batches_sent: u64,
+    num_packets_sent: u64,
+    num_bytes_sent: u64,
+    num_clients_connected: u64,
+    num_clients_dropped: u64,
+}
+
+impl PacketMirrorMetrics {
+    fn report(&self, num_clients: usize, queue_len: usize) {
+        datapoint_info!(
+            "packet_mirror-stats",
+            ("num_batches_sent", self.num_batches_sent, i64),
+            ("num_packets_sent", self.num_packets_sent, i64),
+            ("num_bytes_sent", self.num_bytes_sent, i64),
+            ("num_clients_connected", self.num_clients_connected, i64),
+            ("num_clients_dropped", self.num_clients_dropped, i64),
+            ("num_clients", num_clients, i64),
+            ("queue_len", queue_len, i64),
+        );
+    }
+}
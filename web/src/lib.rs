@@ -1,38 +1,68 @@
 use std::{
+    collections::HashSet,
     net::SocketAddr,
+    str::FromStr,
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicU32, Ordering},
         Arc, RwLock,
     },
-    time::Duration,
+    time::{Duration, Instant, UNIX_EPOCH},
 };
 
 use axum::{
-    error_handling::HandleErrorLayer, http::StatusCode, routing::get, BoxError, Extension, Json,
-    Router,
+    error_handling::HandleErrorLayer,
+    extract::Query,
+    http::StatusCode,
+    routing::{get, post},
+    BoxError, Extension, Json, Router,
+};
+use base64::{prelude::BASE64_STANDARD, Engine};
+use jito_block_engine::{
+    block_engine::{BlockEngineHandle, BlockEngineState, TransactionExplanation},
+    interest_cache::InterestKind,
+};
+use jito_relayer::{
+    health_manager::HealthState,
+    relayer::{RelayerCountersSnapshot, RelayerHandle},
+    session_log::SessionRecord,
 };
-use jito_relayer::{health_manager::HealthState, relayer::RelayerHandle};
 use log::debug;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use solana_sdk::{pubkey::Pubkey, transaction::VersionedTransaction};
 use tower::{buffer::BufferLayer, limit::RateLimitLayer, ServiceBuilder};
 
 /// State object that exposes info inside relayer
 pub struct RelayerState {
     slot_health: Arc<RwLock<HealthState>>,
     is_connected_to_block_engine: Arc<AtomicBool>,
+    is_block_engine_failed: Arc<AtomicBool>,
+    effective_packet_delay_ms: Arc<AtomicU32>,
     relayer_handle: RelayerHandle,
+    /// Relayer whose /status counters are compared against ours on /shadow
+    reference_relayer_url: Option<String>,
+    started_at: Instant,
+    block_engine_handle: BlockEngineHandle,
 }
 
 impl RelayerState {
     pub fn new(
         slot_health: Arc<RwLock<HealthState>>,
         is_connected_to_block_engine: &Arc<AtomicBool>,
+        is_block_engine_failed: &Arc<AtomicBool>,
+        effective_packet_delay_ms: &Arc<AtomicU32>,
         relayer_handle: RelayerHandle,
+        reference_relayer_url: Option<String>,
+        block_engine_handle: BlockEngineHandle,
     ) -> RelayerState {
         RelayerState {
             slot_health,
             is_connected_to_block_engine: is_connected_to_block_engine.clone(),
+            is_block_engine_failed: is_block_engine_failed.clone(),
+            effective_packet_delay_ms: effective_packet_delay_ms.clone(),
             relayer_handle,
+            reference_relayer_url,
+            started_at: Instant::now(),
+            block_engine_handle,
         }
     }
 }
@@ -40,8 +70,240 @@ impl RelayerState {
 #[derive(Serialize, Debug)]
 pub struct RelayerStatus {
     slots_healthy: bool,
+    /// healthy, degraded or unhealthy. Degraded relayers keep existing streams but refuse new
+    /// validators.
+    health_state: &'static str,
     is_connected_to_block_engine: bool,
+    /// The block engine (mempool) path failed and packets are only forwarded to validators
+    is_block_engine_failed: bool,
+    /// Delay currently applied before forwarding packets to validators
+    effective_packet_delay_ms: u32,
+    block_engines: Vec<BlockEngineStatus>,
     validators_connected: Vec<String>,
+    sessions: Vec<SessionStatus>,
+    /// Packets are counted but not sent to validators
+    shadow_mode: bool,
+    uptime_secs: u64,
+    counters: CountersStatus,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BlockEngineStatus {
+    block_engine_url: String,
+    /// Sent all transactions instead of filtering on accounts and programs of interest
+    forward_all: bool,
+    is_connected: bool,
+    /// The connection task panicked and is being restarted
+    is_failed: bool,
+    /// Whether packets are sent to this block engine, per the block engine policy
+    is_active: bool,
+    /// Whether the fastest policy picked this block engine, always true for other policies
+    is_selected: bool,
+    /// Connect and auth round-trip time of the latest successful probe
+    probe_rtt_us: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+pub struct CountersStatus {
+    num_batches_forwarded: u64,
+    num_packets_forwarded: u64,
+    num_packets_dropped: u64,
+    num_packets_filtered: u64,
+}
+
+impl From<RelayerCountersSnapshot> for CountersStatus {
+    fn from(counters: RelayerCountersSnapshot) -> Self {
+        CountersStatus {
+            num_batches_forwarded: counters.num_batches_forwarded,
+            num_packets_forwarded: counters.num_packets_forwarded,
+            num_packets_dropped: counters.num_packets_dropped,
+            num_packets_filtered: counters.num_packets_filtered,
+        }
+    }
+}
+
+/// The subset of a reference relayer's /status compared on /shadow. Relayers that predate the
+/// counters only report connected validators.
+#[derive(Deserialize, Debug)]
+struct ReferenceStatus {
+    validators_connected: Vec<String>,
+    #[serde(default)]
+    uptime_secs: u64,
+    counters: Option<CountersStatus>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CountersComparison {
+    uptime_secs: u64,
+    counters: Option<CountersStatus>,
+    packets_forwarded_per_sec: Option<f64>,
+    packets_filtered_per_sec: Option<f64>,
+}
+
+impl CountersComparison {
+    fn new(uptime_secs: u64, counters: Option<CountersStatus>) -> Self {
+        let per_sec = |n: u64| n as f64 / uptime_secs.max(1) as f64;
+        CountersComparison {
+            uptime_secs,
+            counters,
+            packets_forwarded_per_sec: counters.map(|c| per_sec(c.num_packets_forwarded)),
+            packets_filtered_per_sec: counters.map(|c| per_sec(c.num_packets_filtered)),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct ShadowComparison {
+    reference_relayer_url: String,
+    shadow_mode: bool,
+    local: CountersComparison,
+    reference: CountersComparison,
+    /// Validators connected here but not to the reference relayer
+    validators_only_local: Vec<String>,
+    /// Validators connected to the reference relayer but not here
+    validators_only_reference: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SessionStatus {
+    pubkey: String,
+    session_id: u64,
+    connected_at_unix_ms: u64,
+    /// Whether packets are forwarded to this session, per the identity's session policy
+    is_active: bool,
+    /// Whether the session is falling behind on its packet stream
+    is_slow: bool,
+    queue_depth: usize,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum InterestKindQuery {
+    #[default]
+    Accounts,
+    Programs,
+}
+
+impl From<InterestKindQuery> for InterestKind {
+    fn from(kind: InterestKindQuery) -> Self {
+        match kind {
+            InterestKindQuery::Accounts => InterestKind::Account,
+            InterestKindQuery::Programs => InterestKind::Program,
+        }
+    }
+}
+
+fn interest_kind_str(kind: InterestKind) -> &'static str {
+    match kind {
+        InterestKind::Account => "account",
+        InterestKind::Program => "program",
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct InterestQuery {
+    /// Block engine whose cache is listed, defaults to the first one configured
+    block_engine_url: Option<String>,
+    #[serde(default)]
+    kind: InterestKindQuery,
+    /// Only return pubkeys after this one, for paging
+    after: Option<String>,
+    /// Max number of pubkeys to return, defaults to 100
+    limit: Option<usize>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct InterestEntry {
+    pubkey: String,
+    ttl_remaining_ms: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct InterestPage {
+    block_engine_url: String,
+    entries: Vec<InterestEntry>,
+    /// Pass as `after` to get the next page, None on the last page
+    next_after: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct InterestLookupQuery {
+    pubkey: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct InterestLookup {
+    block_engine_url: String,
+    /// Time until the pubkey expires from the accounts of interest, None if it isn't one
+    account_ttl_remaining_ms: Option<u64>,
+    /// Time until the pubkey expires from the programs of interest, None if it isn't one
+    program_ttl_remaining_ms: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ExplainRequest {
+    /// Base64 encoded, bincode serialized VersionedTransaction
+    transaction: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct InterestMatchStatus {
+    pubkey: String,
+    kind: &'static str,
+    /// Lookup table the pubkey was loaded from, None for static account keys
+    lookup_table: Option<String>,
+    /// Only in the operator's static interest, not sent by the block engine
+    is_static: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ExplainStatus {
+    block_engine_url: String,
+    would_forward: bool,
+    reason: &'static str,
+    is_ofac_related: bool,
+    is_forward_all: bool,
+    /// Account keys on the operator's deny list
+    denied: Vec<String>,
+    matches: Vec<InterestMatchStatus>,
+    /// Lookup tables missing from the relayer's cache, their accounts can't be matched
+    missing_lookup_tables: Vec<String>,
+}
+
+impl From<TransactionExplanation> for ExplainStatus {
+    fn from(explanation: TransactionExplanation) -> Self {
+        ExplainStatus {
+            reason: explanation.reason(),
+            block_engine_url: explanation.block_engine_url,
+            would_forward: explanation.would_forward,
+            is_ofac_related: explanation.is_ofac_related,
+            is_forward_all: explanation.is_forward_all,
+            denied: explanation.denied.iter().map(|p| p.to_string()).collect(),
+            matches: explanation
+                .matches
+                .into_iter()
+                .map(|m| InterestMatchStatus {
+                    pubkey: m.pubkey.to_string(),
+                    kind: interest_kind_str(m.kind),
+                    lookup_table: m.lookup_table.map(|t| t.to_string()),
+                    is_static: m.is_static,
+                })
+                .collect(),
+            missing_lookup_tables: explanation
+                .missing_lookup_tables
+                .iter()
+                .map(|t| t.to_string())
+                .collect(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SessionsQuery {
+    /// Only return sessions of this validator identity
+    pubkey: Option<String>,
+    /// Max number of sessions to return, defaults to 100
+    limit: Option<usize>,
 }
 
 /// Returns an axum router with endpoints to get status of relayer
@@ -59,9 +321,17 @@ pub fn build_relayer_router(
         let slots_healthy = *state.slot_health.read().unwrap() == HealthState::Healthy;
         let is_connected_to_block_engine =
             state.is_connected_to_block_engine.load(Ordering::Relaxed);
+        let is_block_engine_failed = state.is_block_engine_failed.load(Ordering::Relaxed);
 
-        let health = if slots_healthy && is_connected_to_block_engine {
+        let is_degraded = *state.slot_health.read().unwrap() == HealthState::Degraded;
+
+        // degraded only softens slot health, losing the block engine is always unhealthy
+        let health = if !is_connected_to_block_engine || is_block_engine_failed {
+            "unhealthy".to_string()
+        } else if slots_healthy {
             "ok".to_string()
+        } else if is_degraded {
+            "degraded".to_string()
         } else {
             "unhealthy".to_string()
         };
@@ -74,25 +344,240 @@ pub fn build_relayer_router(
     async fn get_status(Extension(state): Extension<Arc<RelayerState>>) -> Json<RelayerStatus> {
         let status = RelayerStatus {
             slots_healthy: *state.slot_health.read().unwrap() == HealthState::Healthy,
+            health_state: state.slot_health.read().unwrap().as_str(),
             is_connected_to_block_engine: state
                 .is_connected_to_block_engine
                 .load(Ordering::Relaxed),
+            is_block_engine_failed: state.is_block_engine_failed.load(Ordering::Relaxed),
+            effective_packet_delay_ms: state.effective_packet_delay_ms.load(Ordering::Relaxed),
+            block_engines: state
+                .block_engine_handle
+                .block_engine_states()
+                .iter()
+                .map(|block_engine| BlockEngineStatus {
+                    block_engine_url: block_engine.block_engine_url.clone(),
+                    forward_all: block_engine.forward_all,
+                    is_connected: block_engine.is_connected(),
+                    is_failed: block_engine.is_failed(),
+                    is_active: block_engine.is_active(),
+                    is_selected: block_engine.is_selected(),
+                    probe_rtt_us: block_engine.probe_rtt().map(|rtt| rtt.as_micros() as u64),
+                })
+                .collect(),
             validators_connected: state
                 .relayer_handle
                 .connected_validators()
                 .iter()
                 .map(|p| p.to_string())
                 .collect(),
+            sessions: state
+                .relayer_handle
+                .sessions()
+                .into_iter()
+                .map(|session| SessionStatus {
+                    pubkey: session.pubkey.to_string(),
+                    session_id: session.session_id,
+                    connected_at_unix_ms: session
+                        .connected_at
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_millis() as u64,
+                    is_active: session.is_active,
+                    is_slow: session.is_slow,
+                    queue_depth: session.queue_depth,
+                })
+                .collect(),
+            shadow_mode: state.relayer_handle.is_shadow_mode(),
+            uptime_secs: state.started_at.elapsed().as_secs(),
+            counters: state.relayer_handle.counters().into(),
         };
         debug!("get_status: {:?}", status);
 
         Json(status)
     }
 
+    /// Returns ended validator sessions, newest first, with when and why they disconnected
+    async fn get_sessions(
+        Extension(state): Extension<Arc<RelayerState>>,
+        Query(query): Query<SessionsQuery>,
+    ) -> Result<Json<Vec<SessionRecord>>, (StatusCode, String)> {
+        let pubkey = query
+            .pubkey
+            .map(|p| Pubkey::from_str(&p))
+            .transpose()
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid pubkey: {e}")))?;
+        Ok(Json(state.relayer_handle.session_history(
+            pubkey.as_ref(),
+            query.limit.unwrap_or(100),
+        )))
+    }
+
+    /// Lists a page of a block engine's accounts or programs of interest, ordered by pubkey
+    async fn get_interest(
+        Extension(state): Extension<Arc<RelayerState>>,
+        Query(query): Query<InterestQuery>,
+    ) -> Result<Json<InterestPage>, (StatusCode, String)> {
+        let block_engines = state.block_engine_handle.block_engine_states();
+        let block_engine: &Arc<BlockEngineState> = match &query.block_engine_url {
+            Some(url) => block_engines.iter().find(|b| &b.block_engine_url == url),
+            None => block_engines.first(),
+        }
+        .ok_or((StatusCode::NOT_FOUND, "block engine not found".to_string()))?;
+        let after = query
+            .after
+            .map(|p| Pubkey::from_str(&p))
+            .transpose()
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid pubkey: {e}")))?;
+        let limit = query.limit.unwrap_or(100).min(1_000);
+
+        let entries = block_engine.interest_cache().read().unwrap().list(
+            query.kind.into(),
+            after.as_ref(),
+            limit,
+            Instant::now(),
+        );
+        let next_after = (entries.len() == limit)
+            .then(|| entries.last().map(|(pubkey, _)| pubkey.to_string()))
+            .flatten();
+
+        Ok(Json(InterestPage {
+            block_engine_url: block_engine.block_engine_url.clone(),
+            entries: entries
+                .into_iter()
+                .map(|(pubkey, ttl_remaining)| InterestEntry {
+                    pubkey: pubkey.to_string(),
+                    ttl_remaining_ms: ttl_remaining.as_millis() as u64,
+                })
+                .collect(),
+            next_after,
+        }))
+    }
+
+    /// Looks up whether a pubkey is of interest to each block engine and when it expires
+    async fn get_interest_lookup(
+        Extension(state): Extension<Arc<RelayerState>>,
+        Query(query): Query<InterestLookupQuery>,
+    ) -> Result<Json<Vec<InterestLookup>>, (StatusCode, String)> {
+        let pubkey = Pubkey::from_str(&query.pubkey)
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid pubkey: {e}")))?;
+        let now = Instant::now();
+
+        Ok(Json(
+            state
+                .block_engine_handle
+                .block_engine_states()
+                .iter()
+                .map(|block_engine| {
+                    let interest_cache = block_engine.interest_cache().read().unwrap();
+                    let ttl_remaining_ms = |kind| {
+                        interest_cache
+                            .ttl_remaining(kind, &pubkey, now)
+                            .map(|ttl| ttl.as_millis() as u64)
+                    };
+                    InterestLookup {
+                        block_engine_url: block_engine.block_engine_url.clone(),
+                        account_ttl_remaining_ms: ttl_remaining_ms(InterestKind::Account),
+                        program_ttl_remaining_ms: ttl_remaining_ms(InterestKind::Program),
+                    }
+                })
+                .collect(),
+        ))
+    }
+
+    /// Explains whether a transaction would be forwarded to each block engine and why
+    async fn post_interest_explain(
+        Extension(state): Extension<Arc<RelayerState>>,
+        Json(request): Json<ExplainRequest>,
+    ) -> Result<Json<Vec<ExplainStatus>>, (StatusCode, String)> {
+        let tx: VersionedTransaction = BASE64_STANDARD
+            .decode(&request.transaction)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| bincode::deserialize(&bytes).map_err(|e| e.to_string()))
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid transaction: {e}")))?;
+
+        Ok(Json(
+            state
+                .block_engine_handle
+                .explain_transaction(&tx)
+                .into_iter()
+                .map(ExplainStatus::from)
+                .collect(),
+        ))
+    }
+
+    /// Compares what this relayer forwarded, or would have in shadow mode, against the reference
+    /// relayer's status
+    async fn get_shadow(
+        Extension(state): Extension<Arc<RelayerState>>,
+    ) -> Result<Json<ShadowComparison>, (StatusCode, String)> {
+        let Some(reference_relayer_url) = &state.reference_relayer_url else {
+            return Err((
+                StatusCode::NOT_FOUND,
+                "no reference relayer configured".to_string(),
+            ));
+        };
+
+        let reference: ReferenceStatus = async {
+            reqwest::Client::new()
+                .get(format!(
+                    "{}/status",
+                    reference_relayer_url.trim_end_matches('/')
+                ))
+                .timeout(Duration::from_secs(5))
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await
+        }
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::BAD_GATEWAY,
+                format!("error fetching reference relayer status: {e}"),
+            )
+        })?;
+
+        let local_validators: HashSet<String> = state
+            .relayer_handle
+            .connected_validators()
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        let reference_validators: HashSet<String> =
+            reference.validators_connected.into_iter().collect();
+
+        let comparison = ShadowComparison {
+            reference_relayer_url: reference_relayer_url.clone(),
+            shadow_mode: state.relayer_handle.is_shadow_mode(),
+            local: CountersComparison::new(
+                state.started_at.elapsed().as_secs(),
+                Some(state.relayer_handle.counters().into()),
+            ),
+            reference: CountersComparison::new(reference.uptime_secs, reference.counters),
+            validators_only_local: local_validators
+                .difference(&reference_validators)
+                .cloned()
+                .collect(),
+            validators_only_reference: reference_validators
+                .difference(&local_validators)
+                .cloned()
+                .collect(),
+        };
+        debug!("get_shadow: {:?}", comparison);
+
+        Ok(Json(comparison))
+    }
+
     Router::new()
         .route("/", get(homepage))
         .route("/health", get(get_health))
         .route("/status", get(get_status))
+        .route("/sessions", get(get_sessions))
+        .route("/shadow", get(get_shadow))
+        .route("/interest", get(get_interest))
+        .route("/interest/lookup", get(get_interest_lookup))
+        .route("/interest/explain", post(post_interest_explain))
         .layer(
             ServiceBuilder::new()
                 .layer(HandleErrorLayer::new(|err: BoxError| async move {